@@ -0,0 +1,381 @@
+//! Just enough ACPI to find the tables, parsing them is up to the kernel.
+use core::{ptr, slice};
+
+use crate::EFISystemTable;
+use crate::guid::known;
+
+const RSDP_SIGNATURE: &[u8; 8] = b"RSD PTR ";
+// The ACPI 1.0 part of the RSDP that the first checksum covers.
+const RSDP_V1_LEN: usize = 20;
+// Revision 2 appends the length, XSDT address and extended checksum.
+const RSDP_V2_LEN: usize = 36;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AcpiError {
+    /// Neither ACPI configuration table is published.
+    NoRsdp,
+    InvalidSignature,
+    InvalidChecksum,
+    InvalidLength,
+}
+
+/// Root System Description Pointer, copied out of firmware memory by [`Rsdp::from_ptr`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Rsdp {
+    /// Where the firmware published it, the kernel is handed this rather than a copy.
+    pub address: u64,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+    /// Revision 2 and later only.
+    pub xsdt_address: Option<u64>,
+}
+
+/// Header shared by every System Description Table.
+#[derive(Clone, Copy, Debug)]
+#[repr(C, packed)]
+pub struct SdtHeader {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub oem_table_id: [u8; 8],
+    pub oem_revision: u32,
+    pub creator_id: u32,
+    pub creator_revision: u32,
+}
+
+/// Every ACPI checksum is "all bytes add up to zero".
+pub fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+impl Rsdp {
+    /// Validates the signature and the checksums that apply to the revision. Nothing past the
+    /// ACPI 1.0 part is read unless the revision says it is there.
+    ///
+    /// # Safety
+    /// `ptr` must point to readable memory holding at least an ACPI 1.0 RSDP, and the full
+    /// `length` bytes for revision 2 and later.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Rsdp, AcpiError> {
+        let v1 = unsafe { slice::from_raw_parts(ptr, RSDP_V1_LEN) };
+        if &v1[..8] != RSDP_SIGNATURE {
+            return Err(AcpiError::InvalidSignature);
+        }
+        if !checksum_ok(v1) {
+            return Err(AcpiError::InvalidChecksum);
+        }
+        let mut rsdp = Rsdp {
+            address: ptr as u64,
+            oem_id: v1[9..15].try_into().unwrap(),
+            revision: v1[15],
+            rsdt_address: u32::from_le_bytes(v1[16..20].try_into().unwrap()),
+            xsdt_address: None,
+        };
+        if rsdp.revision >= 2 {
+            let len = unsafe { ptr.add(RSDP_V1_LEN).cast::<u32>().read_unaligned() } as usize;
+            if len < RSDP_V2_LEN {
+                return Err(AcpiError::InvalidLength);
+            }
+            let bytes = unsafe { slice::from_raw_parts(ptr, len) };
+            if !checksum_ok(bytes) {
+                return Err(AcpiError::InvalidChecksum);
+            }
+            rsdp.xsdt_address = Some(u64::from_le_bytes(bytes[24..32].try_into().unwrap()));
+        }
+        Ok(rsdp)
+    }
+
+    /// The XSDT when there is a valid one, the RSDT otherwise.
+    ///
+    /// # Safety
+    /// The addresses in the RSDP must be identity mapped, which holds until the kernel sets up
+    /// its own page tables.
+    pub unsafe fn root(&self) -> Result<RootTable, AcpiError> {
+        if let Some(xsdt) = self.xsdt_address.filter(|&addr| addr != 0) {
+            let root = unsafe { SdtHeader::from_addr(xsdt) }
+                .and_then(|header| RootTable::new(header, 8, b"XSDT"));
+            // Some firmware ships a broken XSDT next to a good RSDT, the RSDT is still worth a try.
+            if root.is_ok() {
+                return root;
+            }
+        }
+        let header = unsafe { SdtHeader::from_addr(self.rsdt_address as u64)? };
+        RootTable::new(header, 4, b"RSDT")
+    }
+}
+
+impl SdtHeader {
+    /// Validates the length and checksum of the table at `addr`.
+    ///
+    /// # Safety
+    /// `addr` must be a readable, identity mapped ACPI table.
+    pub unsafe fn from_addr(addr: u64) -> Result<&'static SdtHeader, AcpiError> {
+        if addr == 0 {
+            return Err(AcpiError::InvalidLength);
+        }
+        let header = unsafe { &*(addr as *const SdtHeader) };
+        let len = header.length as usize;
+        if len < size_of::<SdtHeader>() {
+            return Err(AcpiError::InvalidLength);
+        }
+        if !checksum_ok(unsafe { header.bytes() }) {
+            return Err(AcpiError::InvalidChecksum);
+        }
+        Ok(header)
+    }
+
+    /// The whole table, header included.
+    ///
+    /// # Safety
+    /// `length` must describe readable memory, which [`SdtHeader::from_addr`] assumes as well.
+    pub unsafe fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(ptr::from_ref(self).cast(), self.length as usize) }
+    }
+
+    pub fn signature_str(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+}
+
+/// The XSDT or RSDT, a header followed by 8 or 4 byte table addresses.
+#[derive(Clone, Copy)]
+pub struct RootTable {
+    header: &'static SdtHeader,
+    entry_size: usize,
+}
+
+impl RootTable {
+    fn new(
+        header: &'static SdtHeader,
+        entry_size: usize,
+        signature: &[u8; 4],
+    ) -> Result<Self, AcpiError> {
+        if &header.signature != signature {
+            return Err(AcpiError::InvalidSignature);
+        }
+        Ok(Self { header, entry_size })
+    }
+
+    pub fn header(&self) -> &'static SdtHeader {
+        self.header
+    }
+
+    pub fn is_xsdt(&self) -> bool {
+        self.entry_size == 8
+    }
+
+    pub fn len(&self) -> usize {
+        (self.header.length as usize - size_of::<SdtHeader>()) / self.entry_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Physical address of every table, entries are not necessarily aligned.
+    pub fn addresses(&self) -> impl Iterator<Item = u64> + '_ {
+        let entries = &unsafe { self.header.bytes() }[size_of::<SdtHeader>()..];
+        entries
+            .chunks_exact(self.entry_size)
+            .map(|entry| match *entry {
+                [a, b, c, d] => u32::from_le_bytes([a, b, c, d]) as u64,
+                _ => u64::from_le_bytes(entry.try_into().unwrap()),
+            })
+    }
+
+    /// Every table the root points at, validated.
+    pub fn tables(&self) -> impl Iterator<Item = Result<&'static SdtHeader, AcpiError>> + '_ {
+        self.addresses()
+            .map(|addr| unsafe { SdtHeader::from_addr(addr) })
+    }
+
+    /// First valid table with `signature`, e.g. `b"APIC"` for the MADT.
+    pub fn find(&self, signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+        self.tables()
+            .filter_map(Result::ok)
+            .find(|table| &table.signature == signature)
+    }
+}
+
+/// RSDP from the configuration tables, the ACPI 2.0 entry is preferred over 1.0.
+pub fn find_rsdp(st: &EFISystemTable) -> Result<Rsdp, AcpiError> {
+    let mut last_err = AcpiError::NoRsdp;
+    for guid in [known::ACPI_20_TABLE, known::ACPI_TABLE] {
+        let Some(table) = st.find_config_table(&guid) else {
+            continue;
+        };
+        match unsafe { Rsdp::from_ptr(table as *const u8) } {
+            Ok(rsdp) => return Ok(rsdp),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// Root table of the system's ACPI tables, see [`RootTable::tables`].
+pub fn root_table() -> Result<RootTable, AcpiError> {
+    let st = EFISystemTable::fetch_global().ok_or(AcpiError::NoRsdp)?;
+    unsafe { find_rsdp(st)?.root() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EFIConfigurationTable;
+    use crate::mock::MockFirmware;
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn fix_checksum(bytes: &mut [u8], at: usize) {
+        bytes[at] = 0;
+        let sum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        bytes[at] = sum.wrapping_neg();
+    }
+
+    // Leaks a table with the given body so its address stays valid for the test.
+    fn table(signature: &[u8; 4], body: &[u8]) -> u64 {
+        let len = size_of::<SdtHeader>() + body.len();
+        let mut bytes = vec![0u8; len];
+        bytes[..4].copy_from_slice(signature);
+        bytes[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+        bytes[8] = 1;
+        bytes[10..16].copy_from_slice(b"FIOS  ");
+        bytes[size_of::<SdtHeader>()..].copy_from_slice(body);
+        fix_checksum(&mut bytes, 9);
+        Box::leak(bytes.into_boxed_slice()).as_ptr() as u64
+    }
+
+    fn root(signature: &[u8; 4], entries: &[u64], entry_size: usize) -> u64 {
+        let body: Vec<u8> = entries
+            .iter()
+            .flat_map(|addr| addr.to_le_bytes()[..entry_size].to_vec())
+            .collect();
+        table(signature, &body)
+    }
+
+    // ACPI 1.0 ones are only 20 bytes long, so reading any further is out of bounds.
+    fn rsdp(revision: u8, rsdt: u64, xsdt: u64) -> *mut u8 {
+        let len = if revision >= 2 {
+            RSDP_V2_LEN
+        } else {
+            RSDP_V1_LEN
+        };
+        let mut bytes = vec![0u8; len];
+        bytes[..8].copy_from_slice(RSDP_SIGNATURE);
+        bytes[15] = revision;
+        bytes[16..20].copy_from_slice(&(rsdt as u32).to_le_bytes());
+        if revision >= 2 {
+            bytes[20..24].copy_from_slice(&(RSDP_V2_LEN as u32).to_le_bytes());
+            bytes[24..32].copy_from_slice(&xsdt.to_le_bytes());
+        }
+        fix_checksum(&mut bytes[..RSDP_V1_LEN], 8);
+        if revision >= 2 {
+            fix_checksum(&mut bytes, 32);
+        }
+        Box::leak(bytes.into_boxed_slice()).as_mut_ptr()
+    }
+
+    fn config(guid: crate::GUID, table: *mut u8) -> EFIConfigurationTable {
+        EFIConfigurationTable {
+            vendor_guid: guid,
+            vendor_table: table.cast(),
+        }
+    }
+
+    #[test]
+    fn test_prefers_acpi_20_and_walks_xsdt() {
+        // The RSDT lives in the low 4 GiB on real hardware, here it just has to not be used.
+        let facp = table(b"FACP", &[0; 8]);
+        let apic = table(b"APIC", &[1, 2, 3]);
+        let xsdt = root(b"XSDT", &[facp, apic], 8);
+        let v1 = rsdp(0, 0, 0);
+        let v2 = rsdp(2, 0, xsdt);
+        let _fw = MockFirmware::install_with(|s| {
+            s.config_tables = vec![
+                config(known::ACPI_TABLE, v1),
+                config(known::ACPI_20_TABLE, v2),
+            ]
+        });
+
+        let root = root_table().unwrap();
+        assert!(root.is_xsdt());
+        assert_eq!(root.len(), 2);
+        let signatures: Vec<_> = root
+            .tables()
+            .map(|table| table.unwrap().signature_str())
+            .collect();
+        assert_eq!(signatures, ["FACP", "APIC"]);
+        assert_eq!({ root.find(b"APIC").unwrap().length }, 39);
+        assert!(root.find(b"HPET").is_none());
+    }
+
+    #[test]
+    fn test_bad_checksums() {
+        let ssdt = table(b"SSDT", &[]);
+        let bad = table(b"BGRT", &[7]);
+        unsafe { *(bad as *mut u8).add(size_of::<SdtHeader>()) = 8 };
+        let xsdt = root(b"XSDT", &[ssdt, bad], 8);
+        let rsdp_ptr = rsdp(2, 0, xsdt);
+
+        let root = unsafe { Rsdp::from_ptr(rsdp_ptr).unwrap().root().unwrap() };
+        let tables: Vec<_> = root.tables().collect();
+        assert_eq!(tables[0].unwrap().signature_str(), "SSDT");
+        assert_eq!(tables[1].err(), Some(AcpiError::InvalidChecksum));
+
+        // Only the extended checksum covers the XSDT address.
+        unsafe { *rsdp_ptr.add(24) ^= 1 };
+        assert_eq!(
+            unsafe { Rsdp::from_ptr(rsdp_ptr) }.err(),
+            Some(AcpiError::InvalidChecksum)
+        );
+        unsafe { *rsdp_ptr ^= 1 };
+        assert_eq!(
+            unsafe { Rsdp::from_ptr(rsdp_ptr) }.err(),
+            Some(AcpiError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn test_v1_rsdp_and_broken_xsdt() {
+        let v1 = unsafe { Rsdp::from_ptr(rsdp(0, 0x7fe1_4000, 0)).unwrap() };
+        assert_eq!(v1.revision, 0);
+        assert_eq!(v1.rsdt_address, 0x7fe1_4000);
+        assert_eq!(v1.xsdt_address, None);
+
+        // A broken XSDT sends root() on to the RSDT. Host pointers don't fit in the 32-bit RSDT
+        // address, the missing RSDT's error shows it was tried.
+        let xsdt = root(b"XSDT", &[], 8);
+        unsafe { *(xsdt as *mut u8).add(9) ^= 1 };
+        let v2 = unsafe { Rsdp::from_ptr(rsdp(2, 0, xsdt)).unwrap() };
+        assert_eq!(v2.xsdt_address, Some(xsdt));
+        assert_eq!(
+            unsafe { SdtHeader::from_addr(xsdt) }.err(),
+            Some(AcpiError::InvalidChecksum)
+        );
+        assert_eq!(unsafe { v2.root() }.err(), Some(AcpiError::InvalidLength));
+    }
+
+    #[test]
+    fn test_rsdt_entries_are_32_bit() {
+        // Host pointers don't fit in 32 bits, only look at the addresses.
+        let rsdt = root(b"RSDT", &[0x7fe1_4000, 0xdead_beef], 4);
+        let root =
+            RootTable::new(unsafe { SdtHeader::from_addr(rsdt).unwrap() }, 4, b"RSDT").unwrap();
+        assert!(!root.is_xsdt());
+        let addresses: Vec<_> = root.addresses().collect();
+        assert_eq!(addresses, [0x7fe1_4000, 0xdead_beef]);
+        assert!(RootTable::new(root.header(), 8, b"XSDT").is_err());
+    }
+
+    #[test]
+    fn test_missing_rsdp() {
+        let _fw = MockFirmware::install();
+        assert_eq!(root_table().err(), Some(AcpiError::NoRsdp));
+        let st = EFISystemTable::fetch_global().unwrap();
+        assert!(st.config_tables().is_empty());
+        assert!(st.find_config_table(&known::ACPI_TABLE).is_none());
+    }
+}
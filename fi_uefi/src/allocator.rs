@@ -0,0 +1,141 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+
+use crate::{EFIAllocateType, EFIBootServices, EFILoadedImageProtocol, EFIMemoryType};
+
+pub const PAGE_SIZE: usize = 4096;
+
+// Pool allocations are always 8 byte aligned.
+const POOL_ALIGN: usize = 8;
+// Anything this big or bigger goes straight to the page allocator.
+const PAGE_THRESHOLD: usize = 16 * PAGE_SIZE;
+
+/// `GlobalAlloc` on top of the boot services pool and page allocators.
+///
+/// Every allocation fails (returns null) once boot services are gone, freeing after that point
+/// is a no-op since the memory is handed over with the final memory map.
+///
+/// ```ignore
+/// #[global_allocator]
+/// static ALLOCATOR: UefiAllocator = UefiAllocator::new();
+/// ```
+pub struct UefiAllocator;
+
+impl UefiAllocator {
+    pub const fn new() -> Self {
+        Self
+    }
+
+    fn uses_pages(layout: &Layout) -> bool {
+        layout.align() >= PAGE_SIZE || layout.size() >= PAGE_THRESHOLD
+    }
+
+    fn page_count(layout: &Layout) -> usize {
+        layout.size().div_ceil(PAGE_SIZE)
+    }
+}
+
+impl Default for UefiAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl GlobalAlloc for UefiAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(bs) = EFIBootServices::fetch_global() else {
+            return ptr::null_mut();
+        };
+        let mem_ty = EFILoadedImageProtocol::global_image_data_type();
+
+        if layout.align() > PAGE_SIZE {
+            return ptr::null_mut();
+        }
+
+        if Self::uses_pages(&layout) {
+            let Ok(mem_ty) = EFIMemoryType::try_from(mem_ty) else {
+                return ptr::null_mut();
+            };
+            return bs
+                .allocate_pages(
+                    EFIAllocateType::AllocateAnyPages,
+                    mem_ty,
+                    Self::page_count(&layout),
+                    0,
+                )
+                .map_or(ptr::null_mut(), |addr| addr as *mut u8);
+        }
+
+        if layout.align() <= POOL_ALIGN {
+            return bs
+                .allocate_pool(mem_ty, layout.size())
+                .unwrap_or(ptr::null_mut());
+        }
+
+        // Over allocate by `align` and stash the pool pointer right in front of the aligned
+        // block. The pool pointer is 8 byte aligned, so there is always room for it.
+        let Ok(base) = bs.allocate_pool(mem_ty, layout.size() + layout.align()) else {
+            return ptr::null_mut();
+        };
+        let offset = layout.align() - (base as usize % layout.align());
+        unsafe {
+            let aligned = base.add(offset);
+            (aligned as *mut *mut u8).sub(1).write(base);
+            aligned
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let Some(bs) = EFIBootServices::fetch_global() else {
+            return;
+        };
+
+        let _ = if Self::uses_pages(&layout) {
+            bs.free_pages(ptr as u64, Self::page_count(&layout))
+        } else if layout.align() <= POOL_ALIGN {
+            bs.free_pool(ptr)
+        } else {
+            bs.free_pool(unsafe { (ptr as *mut *mut u8).sub(1).read() })
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockFirmware, with_state};
+
+    fn round_trip(size: usize, align: usize) {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let alloc = UefiAllocator::new();
+        let ptr = unsafe { alloc.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert!((ptr as usize).is_multiple_of(align));
+        unsafe {
+            ptr.write_bytes(0xa5, size);
+            alloc.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn test_pool_and_page_allocations_are_freed() {
+        let _fw = MockFirmware::install();
+        round_trip(24, 8);
+        round_trip(100, 64);
+        round_trip(PAGE_THRESHOLD, 8);
+        round_trip(10, PAGE_SIZE);
+        with_state(|s| {
+            assert!(s.pool.is_empty());
+            assert!(s.pages.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_fails_without_boot_services() {
+        let alloc = UefiAllocator::new();
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let _fw = MockFirmware::install();
+        crate::BS.store(ptr::null_mut(), core::sync::atomic::Ordering::Release);
+        assert!(unsafe { alloc.alloc(layout) }.is_null());
+    }
+}
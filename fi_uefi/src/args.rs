@@ -0,0 +1,438 @@
+//! Our own load options as a command line: decoding, shell style splitting and flags.
+//!
+//! ```ignore
+//! let mut kernel = String::from("\\fi\\kernel.elf");
+//! let mut loglevel = String::from("info");
+//! let mut verbose = false;
+//! Flags::new()
+//!     .value("kernel", "kernel image on the boot volume", &mut kernel)
+//!     .value("loglevel", "error, warn, info, debug or trace", &mut loglevel)
+//!     .switch("verbose", "print every step", &mut verbose)
+//!     .parse(&args()?)?;
+//! ```
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+use crate::EFILoadedImageProtocol;
+
+/// How the load options decoded. The shell and most boot managers pass a null terminated
+/// UTF-16 string, but `Boot####` entries can carry any optional data.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum LoadOptions<'a> {
+    /// Well formed UTF-16.
+    Utf16(String),
+    /// UCS-2 with unpaired surrogates, which were replaced with U+FFFD.
+    Ucs2(String),
+    /// Odd sized or full of control characters, not a command line.
+    Binary(&'a [u8]),
+}
+
+impl<'a> LoadOptions<'a> {
+    /// Decodes up to the first null, anything after it is ignored.
+    pub fn from_bytes(bytes: &'a [u8]) -> Self {
+        if !bytes.len().is_multiple_of(2) {
+            return LoadOptions::Binary(bytes);
+        }
+        let units: Vec<u16> = bytes
+            .as_chunks::<2>()
+            .0
+            .iter()
+            .map(|pair| u16::from_le_bytes(*pair))
+            .take_while(|c| *c != 0)
+            .collect();
+        let mut lossy = false;
+        let mut text = String::with_capacity(units.len());
+        for c in char::decode_utf16(units) {
+            let c = c.unwrap_or_else(|_| {
+                lossy = true;
+                char::REPLACEMENT_CHARACTER
+            });
+            if c.is_control() && !matches!(c, '\t' | '\r' | '\n') {
+                return LoadOptions::Binary(bytes);
+            }
+            text.push(c);
+        }
+        if lossy {
+            LoadOptions::Ucs2(text)
+        } else {
+            LoadOptions::Utf16(text)
+        }
+    }
+
+    /// The load options of the running image, `None` if there are none.
+    pub fn from_image() -> Option<LoadOptions<'static>> {
+        let image = EFILoadedImageProtocol::fetch_global()?;
+        if image.load_options.is_null() || image.load_options_size == 0 {
+            return None;
+        }
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                image.load_options as *const u8,
+                image.load_options_size as usize,
+            )
+        };
+        Some(LoadOptions::from_bytes(bytes))
+    }
+
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            LoadOptions::Utf16(text) | LoadOptions::Ucs2(text) => Some(text),
+            LoadOptions::Binary(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SplitError {
+    /// A quote of this kind was opened but never closed.
+    UnterminatedQuote(char),
+    /// The line ends with the `^` escape character.
+    TrailingEscape,
+}
+
+/// Splits a command line into words the way the UEFI shell does: whitespace separates,
+/// double and single quotes group, and `^` escapes the next character. Backslashes are path
+/// separators and stay as they are.
+pub fn split_args(line: &str) -> Result<Vec<String>, SplitError> {
+    let mut args = Vec::new();
+    let mut word = String::new();
+    // Tells `""` apart from no word at all.
+    let mut in_word = false;
+    let mut quote = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (_, '^') => {
+                word.push(chars.next().ok_or(SplitError::TrailingEscape)?);
+                in_word = true;
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    args.push(core::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if let Some(q) = quote {
+        return Err(SplitError::UnterminatedQuote(q));
+    }
+    if in_word {
+        args.push(word);
+    }
+    Ok(args)
+}
+
+/// The words of our own load options. The shell puts the image name in front, boot entries
+/// usually leave it out, so a leading word naming an `.efi` file is dropped. Binary options
+/// give no words.
+pub fn args() -> Result<Vec<String>, SplitError> {
+    let Some(options) = LoadOptions::from_image() else {
+        return Ok(Vec::new());
+    };
+    let Some(text) = options.as_text() else {
+        return Ok(Vec::new());
+    };
+    let mut args = split_args(text)?;
+    if args
+        .first()
+        .is_some_and(|first| first.to_ascii_lowercase().ends_with(".efi"))
+    {
+        args.remove(0);
+    }
+    Ok(args)
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum FlagError {
+    Unknown(String),
+    MissingValue(&'static str),
+    InvalidValue { flag: &'static str, value: String },
+}
+
+impl fmt::Display for FlagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlagError::Unknown(flag) => write!(f, "unknown option '{flag}'"),
+            FlagError::MissingValue(flag) => write!(f, "option '{flag}' needs a value"),
+            FlagError::InvalidValue { flag, value } => {
+                write!(f, "invalid value '{value}' for option '{flag}'")
+            }
+        }
+    }
+}
+
+enum Target<'a> {
+    Switch(&'a mut bool),
+    Value(Box<dyn FnMut(&str) -> bool + 'a>),
+}
+
+struct Flag<'a> {
+    name: &'static str,
+    help: &'static str,
+    target: Target<'a>,
+}
+
+/// Declares the options and where their values go, then parses the words in one go.
+///
+/// Values are given as `name=value`, `--name=value` or `--name value`. Switches are set by
+/// `name` or `--name`, cleared by `--no-name`, and also take `name=on`/`off`, `true`/`false`,
+/// `yes`/`no` or `1`/`0`. Words that are not options, and everything after `--`, are handed
+/// back in order.
+#[derive(Default)]
+pub struct Flags<'a> {
+    flags: Vec<Flag<'a>>,
+    ignore_unknown: bool,
+}
+
+impl<'a> Flags<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn switch(mut self, name: &'static str, help: &'static str, target: &'a mut bool) -> Self {
+        self.flags.push(Flag {
+            name,
+            help,
+            target: Target::Switch(target),
+        });
+        self
+    }
+
+    /// An option parsed with `FromStr`, `target` keeps its value if the option is not given.
+    pub fn value<T: FromStr + 'a>(
+        mut self,
+        name: &'static str,
+        help: &'static str,
+        target: &'a mut T,
+    ) -> Self {
+        let set = move |value: &str| value.parse().map(|value| *target = value).is_ok();
+        self.flags.push(Flag {
+            name,
+            help,
+            target: Target::Value(Box::new(set)),
+        });
+        self
+    }
+
+    /// Hands unknown options back with the other words instead of failing, for picking a few
+    /// options out of a command line that is parsed in full elsewhere.
+    pub fn ignore_unknown(mut self) -> Self {
+        self.ignore_unknown = true;
+        self
+    }
+
+    /// Stores the values of the options in `args` and returns the remaining words.
+    pub fn parse<S: AsRef<str>>(mut self, args: &[S]) -> Result<Vec<String>, FlagError> {
+        let mut rest = Vec::new();
+        let mut words = args.iter().map(AsRef::as_ref);
+        while let Some(word) = words.next() {
+            if word == "--" {
+                rest.extend(words.map(String::from));
+                break;
+            }
+            let dashed = word.starts_with('-');
+            let bare = word.trim_start_matches('-');
+            let (name, value) = match bare.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (bare, None),
+            };
+            let negated = name
+                .strip_prefix("no-")
+                .filter(|_| dashed && value.is_none());
+            let flag = self
+                .flags
+                .iter_mut()
+                .find(|flag| flag.name == name || Some(flag.name) == negated);
+            let Some(flag) = flag else {
+                if (dashed || value.is_some()) && !self.ignore_unknown {
+                    return Err(FlagError::Unknown(String::from(word)));
+                }
+                rest.push(String::from(word));
+                continue;
+            };
+            match &mut flag.target {
+                Target::Switch(target) => {
+                    **target = match value {
+                        None => flag.name == name,
+                        Some("on" | "true" | "yes" | "1") => true,
+                        Some("off" | "false" | "no" | "0") => false,
+                        Some(value) => {
+                            return Err(FlagError::InvalidValue {
+                                flag: flag.name,
+                                value: String::from(value),
+                            });
+                        }
+                    };
+                }
+                Target::Value(set) => {
+                    let value = match value {
+                        Some(value) => value,
+                        None if dashed => words.next().ok_or(FlagError::MissingValue(flag.name))?,
+                        None => return Err(FlagError::MissingValue(flag.name)),
+                    };
+                    if !set(value) {
+                        return Err(FlagError::InvalidValue {
+                            flag: flag.name,
+                            value: String::from(value),
+                        });
+                    }
+                }
+            }
+        }
+        Ok(rest)
+    }
+
+    /// One line per option with its help text.
+    pub fn usage(&self) -> String {
+        let width = self.flags.iter().map(|f| f.name.len()).max().unwrap_or(0);
+        let mut usage = String::new();
+        for flag in &self.flags {
+            let name = match flag.target {
+                Target::Switch(_) => String::from(flag.name),
+                Target::Value(_) => format!("{}=", flag.name),
+            };
+            usage += &format!("  {name:width$}  {}\n", flag.help, width = width + 1);
+        }
+        usage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockFirmware;
+    use alloc::vec;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    #[test]
+    fn test_decode_fallbacks() {
+        let bytes = utf16("kernel=\\fi\\kernel.elf");
+        assert_eq!(
+            LoadOptions::from_bytes(&bytes),
+            LoadOptions::Utf16(String::from("kernel=\\fi\\kernel.elf"))
+        );
+        // A lone high surrogate.
+        let bytes = [0x61, 0, 0x00, 0xd8, 0x62, 0];
+        assert_eq!(
+            LoadOptions::from_bytes(&bytes),
+            LoadOptions::Ucs2(String::from("a\u{fffd}b"))
+        );
+        let bytes = [1, 0, 2, 0, 3];
+        assert_eq!(LoadOptions::from_bytes(&bytes), LoadOptions::Binary(&bytes));
+        let bytes = [1, 0, 2, 0];
+        assert_eq!(LoadOptions::from_bytes(&bytes).as_text(), None);
+    }
+
+    #[test]
+    fn test_split_quoting() {
+        assert_eq!(
+            split_args(r#"  fi.efi "a b"  'c "d"' e^"f ^^ \fi\x.elf "" "#).unwrap(),
+            vec!["fi.efi", "a b", "c \"d\"", "e\"f", "^", "\\fi\\x.elf", ""]
+        );
+        assert_eq!(split_args("a \"b"), Err(SplitError::UnterminatedQuote('"')));
+        assert_eq!(split_args("a^"), Err(SplitError::TrailingEscape));
+    }
+
+    #[test]
+    fn test_args_from_load_options() {
+        let _fw = MockFirmware::install_with(|state| {
+            state.load_options = utf16("FI.EFI kernel=\\fi\\kernel.elf loglevel=debug");
+        });
+        assert_eq!(
+            args().unwrap(),
+            vec!["kernel=\\fi\\kernel.elf", "loglevel=debug"]
+        );
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut kernel = String::from("\\fi\\kernel.elf");
+        let mut timeout = 5u32;
+        let mut verbose = false;
+        let mut quiet = true;
+        let rest = Flags::new()
+            .value("kernel", "kernel image", &mut kernel)
+            .value("timeout", "seconds", &mut timeout)
+            .switch("verbose", "print more", &mut verbose)
+            .switch("quiet", "print less", &mut quiet)
+            .parse(&[
+                "kernel=\\k.elf",
+                "--timeout",
+                "10",
+                "verbose",
+                "--no-quiet",
+                "extra",
+                "--",
+                "--kernel",
+            ])
+            .unwrap();
+        assert_eq!(rest, vec!["extra", "--kernel"]);
+        assert_eq!(
+            (kernel.as_str(), timeout, verbose, quiet),
+            ("\\k.elf", 10, true, false)
+        );
+
+        let mut timeout = 5u32;
+        let mut parse = |args: &[&str]| {
+            let mut verbose = false;
+            Flags::new()
+                .value("timeout", "seconds", &mut timeout)
+                .switch("verbose", "print more", &mut verbose)
+                .parse(args)
+        };
+        assert_eq!(
+            parse(&["timeout=soon"]),
+            Err(FlagError::InvalidValue {
+                flag: "timeout",
+                value: String::from("soon")
+            })
+        );
+        assert_eq!(
+            parse(&["--timeout"]),
+            Err(FlagError::MissingValue("timeout"))
+        );
+        assert!(parse(&["verbose=maybe"]).is_err());
+        assert_eq!(
+            parse(&["color=red"]),
+            Err(FlagError::Unknown(String::from("color=red")))
+        );
+
+        let mut timeout = 5u32;
+        let rest = Flags::new()
+            .value("timeout", "seconds", &mut timeout)
+            .ignore_unknown()
+            .parse(&["--kernel", "\\k.elf", "color=red", "timeout=7"])
+            .unwrap();
+        assert_eq!(rest, vec!["--kernel", "\\k.elf", "color=red"]);
+        assert_eq!(timeout, 7);
+
+        let mut verbose = false;
+        let mut timeout = 0u32;
+        let usage = Flags::new()
+            .switch("verbose", "print more", &mut verbose)
+            .value("timeout", "seconds", &mut timeout)
+            .usage();
+        assert_eq!(usage, "  verbose   print more\n  timeout=  seconds\n");
+    }
+}
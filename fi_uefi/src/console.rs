@@ -0,0 +1,491 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{EFIBootServices, EFIStatus, EFISystemTable, SimpleTextOutputInterface};
+
+// UTF-16 units per output_string call, the last slot is kept for the null terminator.
+const CHUNK_LEN: usize = 128;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConsoleTarget {
+    Stdout,
+    Stderr,
+}
+
+/// `fmt::Write` over the system table's text output.
+///
+/// Writes fail with `fmt::Error` once boot services have exited, the console protocols are gone
+/// by then.
+pub struct Console {
+    target: ConsoleTarget,
+}
+
+impl Console {
+    pub const fn new(target: ConsoleTarget) -> Self {
+        Self { target }
+    }
+
+    pub const fn stdout() -> Self {
+        Self::new(ConsoleTarget::Stdout)
+    }
+
+    pub const fn stderr() -> Self {
+        Self::new(ConsoleTarget::Stderr)
+    }
+
+    fn output(&self) -> Option<&'static mut SimpleTextOutputInterface> {
+        EFIBootServices::fetch_global()?;
+        let st = EFISystemTable::fetch_global()?;
+        let ptr = match self.target {
+            ConsoleTarget::Stdout => st.stdout,
+            ConsoleTarget::Stderr => st.stderr,
+        };
+        unsafe { ptr.as_mut() }
+    }
+}
+
+/// Converts `s` to null terminated UTF-16 in `buf` sized chunks, turning `\n` into `\r\n`, and
+/// hands each chunk to `out`.
+pub fn encode_chunks<E>(
+    s: &str,
+    buf: &mut [u16],
+    mut out: impl FnMut(&[u16]) -> Result<(), E>,
+) -> Result<(), E> {
+    // Room for a surrogate pair or "\r\n" plus the terminator.
+    assert!(buf.len() >= 3);
+    let mut len = 0;
+    for ch in s.chars() {
+        if len + 3 > buf.len() {
+            buf[len] = 0;
+            out(&buf[..=len])?;
+            len = 0;
+        }
+        if ch == '\n' {
+            buf[len] = '\r' as u16;
+            len += 1;
+        }
+        len += ch.encode_utf16(&mut buf[len..]).len();
+    }
+    if len > 0 {
+        buf[len] = 0;
+        out(&buf[..=len])?;
+    }
+    Ok(())
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let out = self.output().ok_or(fmt::Error)?;
+        let mut buf = [0u16; CHUNK_LEN];
+        encode_chunks(s, &mut buf, |chunk| -> Result<(), EFIStatus> {
+            out.output_string(chunk)
+        })
+        .map_err(|_| fmt::Error)
+    }
+}
+
+/// Text colors. Only the first eight can be used as a background.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0x0,
+    Blue = 0x1,
+    Green = 0x2,
+    Cyan = 0x3,
+    Red = 0x4,
+    Magenta = 0x5,
+    Brown = 0x6,
+    LightGray = 0x7,
+    DarkGray = 0x8,
+    LightBlue = 0x9,
+    LightGreen = 0xa,
+    LightCyan = 0xb,
+    LightRed = 0xc,
+    LightMagenta = 0xd,
+    Yellow = 0xe,
+    White = 0xf,
+}
+
+impl Color {
+    const ALL: [Color; 16] = [
+        Color::Black,
+        Color::Blue,
+        Color::Green,
+        Color::Cyan,
+        Color::Red,
+        Color::Magenta,
+        Color::Brown,
+        Color::LightGray,
+        Color::DarkGray,
+        Color::LightBlue,
+        Color::LightGreen,
+        Color::LightCyan,
+        Color::LightRed,
+        Color::LightMagenta,
+        Color::Yellow,
+        Color::White,
+    ];
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self::ALL[(bits & 0xf) as usize]
+    }
+}
+
+/// Foreground in the low nibble, background in the next three bits, as `set_attribute` takes
+/// it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Attribute(pub u8);
+
+impl Attribute {
+    /// Light gray on black, what consoles start out with.
+    pub const DEFAULT: Self = Self::new(Color::LightGray, Color::Black);
+
+    /// Bright backgrounds fall back to their dark variant.
+    pub const fn new(foreground: Color, background: Color) -> Self {
+        Self(foreground as u8 | (background as u8 & 0x7) << 4)
+    }
+
+    pub const fn foreground(&self) -> Color {
+        Color::from_bits(self.0)
+    }
+
+    pub const fn background(&self) -> Color {
+        Color::from_bits(self.0 >> 4 & 0x7)
+    }
+
+    /// Foreground and background swapped, e.g. for the selected line of a menu.
+    pub const fn inverted(&self) -> Self {
+        Self::new(self.background(), self.foreground())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TextMode {
+    pub number: u64,
+    pub columns: usize,
+    pub rows: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Cursor {
+    pub column: usize,
+    pub row: usize,
+    pub visible: bool,
+}
+
+impl SimpleTextOutputInterface {
+    /// Every mode the current display can show, mode 0 is always 80x25.
+    pub fn modes(&mut self) -> Vec<TextMode> {
+        let max_mode = unsafe { (*self.mode).max_mode.max(0) } as u64;
+        (0..max_mode)
+            .filter_map(|number| {
+                let (columns, rows) = self.query_mode(number).ok()?;
+                Some(TextMode {
+                    number,
+                    columns,
+                    rows,
+                })
+            })
+            .collect()
+    }
+
+    pub fn current_mode(&mut self) -> Result<TextMode, EFIStatus> {
+        let number = unsafe { (*self.mode).mode };
+        let number = u64::try_from(number).map_err(|_| EFIStatus::DEVICE_ERROR)?;
+        let (columns, rows) = self.query_mode(number)?;
+        Ok(TextMode {
+            number,
+            columns,
+            rows,
+        })
+    }
+
+    /// Switches to the mode with the most rows of at least `columns` width.
+    pub fn set_largest_mode(&mut self, columns: usize) -> Result<TextMode, EFIStatus> {
+        let mode = self
+            .modes()
+            .into_iter()
+            .filter(|mode| mode.columns >= columns)
+            .max_by_key(|mode| mode.rows)
+            .ok_or(EFIStatus::UNSUPPORTED)?;
+        self.set_mode(mode.number)?;
+        Ok(mode)
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        let mode = unsafe { &*self.mode };
+        Cursor {
+            column: mode.cursor_column.max(0) as usize,
+            row: mode.cursor_row.max(0) as usize,
+            visible: mode.cursor_visible != 0,
+        }
+    }
+
+    pub fn attribute(&self) -> Attribute {
+        Attribute(unsafe { (*self.mode).attribute } as u8)
+    }
+
+    pub fn set_colors(&mut self, foreground: Color, background: Color) -> Result<(), EFIStatus> {
+        self.set_attribute(Attribute::new(foreground, background).0 as u64)
+    }
+
+    /// Writes `s` at the cursor, `\n` moves to the start of the next line.
+    pub fn write_str(&mut self, s: &str) -> Result<(), EFIStatus> {
+        let mut buf = [0u16; CHUNK_LEN];
+        encode_chunks(s, &mut buf, |chunk| self.output_string(chunk))
+    }
+}
+
+/// A rectangle of the screen that is redrawn line by line. Every line is padded to the full
+/// width, so updates overwrite the old text in place instead of clearing the screen first,
+/// which is what makes menus and status bars flicker.
+///
+/// ```ignore
+/// let mut status = Window::new(out, 0, rows - 1, columns, 1);
+/// status.set_attribute(Attribute::new(Color::Black, Color::LightGray));
+/// status.write_line(0, "Booting in 5s")?;
+/// ```
+pub struct Window<'a> {
+    out: &'a mut SimpleTextOutputInterface,
+    left: usize,
+    top: usize,
+    width: usize,
+    height: usize,
+    attribute: Attribute,
+}
+
+impl<'a> Window<'a> {
+    pub fn new(
+        out: &'a mut SimpleTextOutputInterface,
+        left: usize,
+        top: usize,
+        width: usize,
+        height: usize,
+    ) -> Self {
+        Self {
+            out,
+            left,
+            top,
+            width,
+            height,
+            attribute: Attribute::DEFAULT,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// For everything written from here on.
+    pub fn set_attribute(&mut self, attribute: Attribute) {
+        self.attribute = attribute;
+    }
+
+    /// Blanks every line in the window's background.
+    pub fn clear(&mut self) -> Result<(), EFIStatus> {
+        for row in 0..self.height {
+            self.write_line(row, "")?;
+        }
+        Ok(())
+    }
+
+    /// Replaces line `row` with `text`, cut or padded to the window width. Rows outside the
+    /// window are ignored.
+    pub fn write_line(&mut self, row: usize, text: &str) -> Result<(), EFIStatus> {
+        self.write_line_with(row, text, self.attribute)
+    }
+
+    pub fn write_line_with(
+        &mut self,
+        row: usize,
+        text: &str,
+        attribute: Attribute,
+    ) -> Result<(), EFIStatus> {
+        self.write_at_with(0, row, &format!("{text:0$.0$}", self.width), attribute)
+    }
+
+    /// Writes `text` at `column` of line `row`, cut at the right edge. Control characters are
+    /// shown as spaces so they can't move the cursor out of the window.
+    pub fn write_at(&mut self, column: usize, row: usize, text: &str) -> Result<(), EFIStatus> {
+        self.write_at_with(column, row, text, self.attribute)
+    }
+
+    fn write_at_with(
+        &mut self,
+        column: usize,
+        row: usize,
+        text: &str,
+        attribute: Attribute,
+    ) -> Result<(), EFIStatus> {
+        if row >= self.height || column >= self.width {
+            return Ok(());
+        }
+        let text: String = text
+            .chars()
+            .take(self.width - column)
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect();
+        self.out
+            .set_cursor_position((self.left + column) as u64, (self.top + row) as u64)?;
+        self.out.set_attribute(attribute.0 as u64)?;
+        self.out.write_str(&text)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(target: ConsoleTarget, args: fmt::Arguments) {
+    use fmt::Write;
+    let _ = Console::new(target).write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::console::_print($crate::console::ConsoleTarget::Stdout, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::console::_print(
+            $crate::console::ConsoleTarget::Stdout,
+            format_args!("{}\n", format_args!($($arg)*)),
+        )
+    };
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => {
+        $crate::console::_print($crate::console::ConsoleTarget::Stderr, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprint!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::console::_print(
+            $crate::console::ConsoleTarget::Stderr,
+            format_args!("{}\n", format_args!($($arg)*)),
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn collect(s: &str, chunk_len: usize) -> Vec<Vec<u16>> {
+        let mut buf = vec![0u16; chunk_len];
+        let mut chunks = Vec::new();
+        encode_chunks(s, &mut buf, |chunk| -> Result<(), ()> {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        chunks
+    }
+
+    #[test]
+    fn test_newline_translation() {
+        let chunks = collect("a\nb", 16);
+        let expected: Vec<u16> = "a\r\nb\0".encode_utf16().collect();
+        assert_eq!(chunks, [expected]);
+    }
+
+    #[test]
+    fn test_splits_into_terminated_chunks() {
+        let chunks = collect("abcdefgh", 4);
+        assert!(chunks.iter().all(|c| c.len() <= 4 && c.last() == Some(&0)));
+        let joined: Vec<u16> = chunks
+            .iter()
+            .flat_map(|c| c[..c.len() - 1].iter().copied())
+            .collect();
+        assert_eq!(joined, "abcdefgh".encode_utf16().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_surrogate_pair_not_split() {
+        let chunks = collect("aa𐍈", 4);
+        let expected: Vec<u16> = "𐍈\0".encode_utf16().collect();
+        assert_eq!(chunks.last().unwrap(), &expected);
+    }
+
+    #[test]
+    fn test_empty_string_writes_nothing() {
+        assert!(collect("", 8).is_empty());
+    }
+
+    #[test]
+    fn test_attributes_and_modes() {
+        let attribute = Attribute::new(Color::Yellow, Color::LightBlue);
+        assert_eq!(attribute.0, 0x1e);
+        assert_eq!(attribute.foreground(), Color::Yellow);
+        assert_eq!(attribute.background(), Color::Blue);
+        assert_eq!(
+            attribute.inverted(),
+            Attribute::new(Color::Blue, Color::Yellow)
+        );
+
+        let _fw = crate::mock::MockFirmware::install();
+        let out = unsafe { &mut *EFISystemTable::fetch_global().unwrap().stdout };
+        let modes = out.modes();
+        assert_eq!(modes.len(), 2);
+        assert_eq!((modes[1].columns, modes[1].rows), (80, 50));
+        assert_eq!(out.query_mode(2), Err(EFIStatus::UNSUPPORTED));
+        assert_eq!(out.set_largest_mode(80).unwrap().number, 1);
+        assert_eq!(out.current_mode().unwrap().rows, 50);
+
+        out.set_colors(Color::White, Color::Red).unwrap();
+        assert_eq!(out.attribute(), Attribute(0x4f));
+        out.set_cursor_position(3, 4).unwrap();
+        out.enable_cursor(false).unwrap();
+        let cursor = out.cursor();
+        assert_eq!((cursor.column, cursor.row, cursor.visible), (3, 4, false));
+    }
+
+    #[test]
+    fn test_window_redraws_in_place() {
+        let fw = crate::mock::MockFirmware::install();
+        let out = unsafe { &mut *EFISystemTable::fetch_global().unwrap().stdout };
+        let mut window = Window::new(out, 2, 1, 10, 2);
+        window.write_line(0, "a long line that is cut").unwrap();
+        window.write_line(1, "two").unwrap();
+        // Out of the window.
+        window.write_line(2, "three").unwrap();
+        window.write_at(8, 1, "xyz\n").unwrap();
+        assert_eq!(fw.screen_row(1), "  a long lin");
+        assert_eq!(fw.screen_row(2), "  two     xy");
+        assert_eq!(fw.screen_row(3), "");
+
+        window.write_line(0, "short").unwrap();
+        assert_eq!(fw.screen_row(1), "  short");
+        window.clear().unwrap();
+        assert_eq!(fw.screen_row(2), "");
+        crate::mock::with_state(|s| assert_eq!(s.clear_screens, 0));
+    }
+
+    #[test]
+    fn test_macros_reach_firmware() {
+        let fw = crate::mock::MockFirmware::install();
+        crate::println!("hello {}", 42);
+        crate::eprint!("oops");
+        assert_eq!(fw.stdout(), "hello 42\r\n");
+        assert_eq!(fw.stderr(), "oops");
+    }
+}
@@ -0,0 +1,964 @@
+//! Device path nodes, an owned builder, and conversion to and from the UEFI text form, e.g.
+//! `PciRoot(0x0)/Pci(0x1,0x1)/Sata(0x0,0xffff,0x0)/HD(1,GPT,<guid>,0x800,0x100000)/\EFI\BOOT\BOOTX64.EFI`.
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::slice;
+use core::str::FromStr;
+
+use crate::guid::known;
+use crate::protocol::Protocol;
+use crate::{EFIDevicePath, EFILoadedImageProtocol, GUID};
+
+const HEADER_LEN: usize = 4;
+
+const TYPE_HARDWARE: u8 = 0x01;
+const TYPE_ACPI: u8 = 0x02;
+const TYPE_MESSAGING: u8 = 0x03;
+const TYPE_MEDIA: u8 = 0x04;
+const TYPE_END: u8 = 0x7f;
+
+const HW_PCI: u8 = 0x01;
+const ACPI_DP: u8 = 0x01;
+const MSG_USB: u8 = 0x05;
+const MSG_MAC_ADDR: u8 = 0x0b;
+const MSG_IPV4: u8 = 0x0c;
+const MSG_SATA: u8 = 0x12;
+const MSG_NVME: u8 = 0x17;
+const MEDIA_HARD_DRIVE: u8 = 0x01;
+const MEDIA_FILE_PATH: u8 = 0x04;
+const END_INSTANCE: u8 = 0x01;
+const END_ENTIRE: u8 = 0xff;
+
+// EISA ids of the PCI and PCIe root bridges, shown as PciRoot/PcieRoot.
+const PNP_0A03: u32 = eisa_pnp_id(0x0a03);
+const PNP_0A08: u32 = eisa_pnp_id(0x0a08);
+
+const fn eisa_pnp_id(product: u16) -> u32 {
+    ((product as u32) << 16) | 0x41d0
+}
+
+const HD_SIGNATURE_MBR: u8 = 0x01;
+const HD_SIGNATURE_GUID: u8 = 0x02;
+const IP_PROTOCOL_TCP: u16 = 6;
+const IP_PROTOCOL_UDP: u16 = 17;
+
+unsafe impl Protocol for EFIDevicePath {
+    const GUID: GUID = known::DEVICE_PATH_PROTOCOL;
+}
+
+impl EFIDevicePath {
+    pub fn ty(&self) -> u8 {
+        self.ty
+    }
+
+    pub fn sub_type(&self) -> u8 {
+        self.sub_type
+    }
+
+    /// Node length, header included.
+    pub fn len(&self) -> u16 {
+        u16::from_le_bytes(self.len)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() as usize <= HEADER_LEN
+    }
+}
+
+impl EFILoadedImageProtocol {
+    /// Path of the image relative to `device_handle`, usually a single file path node.
+    pub fn file_path(&self) -> Option<DevicePath<'_>> {
+        if self.file_path.is_null() {
+            return None;
+        }
+        unsafe { DevicePath::from_ptr(self.file_path) }
+    }
+}
+
+fn le16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn le32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn le64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+fn array<const N: usize>(data: &[u8], offset: usize) -> [u8; N] {
+    data[offset..offset + N].try_into().unwrap()
+}
+
+/// A complete device path, borrowed. Always ends with an end-of-path node.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DevicePath<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> DevicePath<'a> {
+    /// Checks every node length and cuts `bytes` after the first end-of-path node. `None` if the
+    /// nodes run past the end without one.
+    pub fn from_bytes(bytes: &'a [u8]) -> Option<Self> {
+        let mut offset = 0;
+        loop {
+            let header = bytes.get(offset..offset + HEADER_LEN)?;
+            let len = le16(header, 2) as usize;
+            if len < HEADER_LEN || offset + len > bytes.len() {
+                return None;
+            }
+            offset += len;
+            if header[0] == TYPE_END && header[1] == END_ENTIRE {
+                return Some(Self {
+                    bytes: &bytes[..offset],
+                });
+            }
+        }
+    }
+
+    /// # Safety
+    /// `ptr` must point to a device path that ends with an end-of-path node and stays valid for
+    /// `'a`.
+    pub unsafe fn from_ptr(ptr: *const EFIDevicePath) -> Option<Self> {
+        let mut len = 0;
+        loop {
+            let node = unsafe { &*ptr.byte_add(len) };
+            if (node.len() as usize) < HEADER_LEN {
+                return None;
+            }
+            len += node.len() as usize;
+            if node.ty == TYPE_END && node.sub_type == END_ENTIRE {
+                break;
+            }
+        }
+        Self::from_bytes(unsafe { slice::from_raw_parts(ptr.cast(), len) })
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn as_ptr(&self) -> *const EFIDevicePath {
+        self.bytes.as_ptr().cast()
+    }
+
+    /// Every node, the final end-of-path node included.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes { bytes: self.bytes }
+    }
+
+    pub fn to_path_buf(&self) -> DevicePathBuf {
+        DevicePathBuf {
+            bytes: self.bytes.to_vec(),
+        }
+    }
+}
+
+impl fmt::Display for DevicePath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = None;
+        for node in self.nodes() {
+            match node.decode() {
+                DeviceNode::End { entire: true } => break,
+                DeviceNode::End { entire: false } => separator = Some(','),
+                decoded => {
+                    if let Some(c) = separator {
+                        f.write_char(c)?;
+                    }
+                    write!(f, "{decoded}")?;
+                    separator = Some('/');
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DevicePath<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DevicePath({self})")
+    }
+}
+
+/// Iterator over the nodes of a [`DevicePath`].
+pub struct Nodes<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.len() < HEADER_LEN {
+            return None;
+        }
+        // Lengths were checked when the path was built.
+        let len = le16(self.bytes, 2) as usize;
+        let (node, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(Node {
+            ty: node[0],
+            sub_type: node[1],
+            data: &node[HEADER_LEN..],
+        })
+    }
+}
+
+/// One raw node, see [`Node::decode`] for the typed form.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Node<'a> {
+    pub ty: u8,
+    pub sub_type: u8,
+    /// Node contents after the header.
+    pub data: &'a [u8],
+}
+
+impl<'a> Node<'a> {
+    /// Nodes that are not known, or too short for their type, come back as `Unknown`.
+    pub fn decode(&self) -> DeviceNode<'a> {
+        let d = self.data;
+        let unknown = DeviceNode::Unknown {
+            ty: self.ty,
+            sub_type: self.sub_type,
+            data: d,
+        };
+        match (self.ty, self.sub_type, d.len()) {
+            (TYPE_HARDWARE, HW_PCI, 2..) => DeviceNode::Pci {
+                function: d[0],
+                device: d[1],
+            },
+            (TYPE_ACPI, ACPI_DP, 8..) => DeviceNode::Acpi {
+                hid: le32(d, 0),
+                uid: le32(d, 4),
+            },
+            (TYPE_MESSAGING, MSG_USB, 2..) => DeviceNode::Usb {
+                parent_port: d[0],
+                interface: d[1],
+            },
+            (TYPE_MESSAGING, MSG_MAC_ADDR, 33..) => DeviceNode::MacAddr {
+                address: array(d, 0),
+                if_type: d[32],
+            },
+            (TYPE_MESSAGING, MSG_IPV4, 23..) => DeviceNode::Ipv4(Ipv4Node {
+                local: array(d, 0),
+                remote: array(d, 4),
+                local_port: le16(d, 8),
+                remote_port: le16(d, 10),
+                protocol: le16(d, 12),
+                static_address: d[14] != 0,
+                gateway: array(d, 15),
+                subnet_mask: array(d, 19),
+            }),
+            (TYPE_MESSAGING, MSG_SATA, 6..) => DeviceNode::Sata {
+                hba_port: le16(d, 0),
+                port_multiplier_port: le16(d, 2),
+                lun: le16(d, 4),
+            },
+            (TYPE_MESSAGING, MSG_NVME, 12..) => DeviceNode::Nvme {
+                namespace_id: le32(d, 0),
+                eui64: array(d, 4),
+            },
+            (TYPE_MEDIA, MEDIA_HARD_DRIVE, 38..) => DeviceNode::HardDrive(HardDriveNode {
+                partition_number: le32(d, 0),
+                partition_start: le64(d, 4),
+                partition_size: le64(d, 12),
+                signature: array(d, 20),
+                partition_format: d[36],
+                signature_type: d[37],
+            }),
+            (TYPE_MEDIA, MEDIA_FILE_PATH, _) => DeviceNode::FilePath(d),
+            (TYPE_END, END_INSTANCE, _) => DeviceNode::End { entire: false },
+            (TYPE_END, END_ENTIRE, _) => DeviceNode::End { entire: true },
+            _ => unknown,
+        }
+    }
+}
+
+impl fmt::Display for Node<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.decode().fmt(f)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Ipv4Node {
+    pub local: [u8; 4],
+    pub remote: [u8; 4],
+    pub local_port: u16,
+    pub remote_port: u16,
+    /// IANA protocol number, 6 for TCP and 17 for UDP.
+    pub protocol: u16,
+    pub static_address: bool,
+    pub gateway: [u8; 4],
+    pub subnet_mask: [u8; 4],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct HardDriveNode {
+    /// Starts at 1, 0 means the whole disk.
+    pub partition_number: u32,
+    /// In logical blocks.
+    pub partition_start: u64,
+    pub partition_size: u64,
+    /// MBR disk signature in the first 4 bytes, or the GPT partition GUID.
+    pub signature: [u8; 16],
+    /// 1 for MBR, 2 for GPT.
+    pub partition_format: u8,
+    /// 0 for none, 1 for an MBR signature, 2 for a GUID.
+    pub signature_type: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DeviceNode<'a> {
+    Pci {
+        function: u8,
+        device: u8,
+    },
+    /// `hid` is an EISA id, e.g. PNP0A03 for a PCI root bridge.
+    Acpi {
+        hid: u32,
+        uid: u32,
+    },
+    Sata {
+        hba_port: u16,
+        port_multiplier_port: u16,
+        lun: u16,
+    },
+    Nvme {
+        namespace_id: u32,
+        eui64: [u8; 8],
+    },
+    Usb {
+        parent_port: u8,
+        interface: u8,
+    },
+    /// Padded to 32 bytes, Ethernet (`if_type` 0 or 1) uses the first 6.
+    MacAddr {
+        address: [u8; 32],
+        if_type: u8,
+    },
+    Ipv4(Ipv4Node),
+    HardDrive(HardDriveNode),
+    /// Null terminated UTF-16LE, see [`DeviceNode::file_path_chars`].
+    FilePath(&'a [u8]),
+    /// `entire: false` separates instances of a multi-instance path.
+    End {
+        entire: bool,
+    },
+    Unknown {
+        ty: u8,
+        sub_type: u8,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> DeviceNode<'a> {
+    /// Characters of a `FilePath` node, empty for every other node.
+    pub fn file_path_chars(&self) -> impl Iterator<Item = char> + 'a {
+        let data: &'a [u8] = match self {
+            DeviceNode::FilePath(data) => data,
+            _ => &[],
+        };
+        let units = data
+            .as_chunks::<2>()
+            .0
+            .iter()
+            .map(|c| u16::from_le_bytes(*c))
+            .take_while(|c| *c != 0);
+        char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+    }
+
+    fn header(&self) -> (u8, u8) {
+        match self {
+            DeviceNode::Pci { .. } => (TYPE_HARDWARE, HW_PCI),
+            DeviceNode::Acpi { .. } => (TYPE_ACPI, ACPI_DP),
+            DeviceNode::Sata { .. } => (TYPE_MESSAGING, MSG_SATA),
+            DeviceNode::Nvme { .. } => (TYPE_MESSAGING, MSG_NVME),
+            DeviceNode::Usb { .. } => (TYPE_MESSAGING, MSG_USB),
+            DeviceNode::MacAddr { .. } => (TYPE_MESSAGING, MSG_MAC_ADDR),
+            DeviceNode::Ipv4(_) => (TYPE_MESSAGING, MSG_IPV4),
+            DeviceNode::HardDrive(_) => (TYPE_MEDIA, MEDIA_HARD_DRIVE),
+            DeviceNode::FilePath(_) => (TYPE_MEDIA, MEDIA_FILE_PATH),
+            DeviceNode::End { entire: false } => (TYPE_END, END_INSTANCE),
+            DeviceNode::End { entire: true } => (TYPE_END, END_ENTIRE),
+            DeviceNode::Unknown { ty, sub_type, .. } => (*ty, *sub_type),
+        }
+    }
+
+    /// Appends the node, header included, to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        let (ty, sub_type) = self.header();
+        out.extend([ty, sub_type, 0, 0]);
+        match self {
+            DeviceNode::Pci { function, device } => out.extend([*function, *device]),
+            DeviceNode::Acpi { hid, uid } => {
+                out.extend(hid.to_le_bytes());
+                out.extend(uid.to_le_bytes());
+            }
+            DeviceNode::Sata {
+                hba_port,
+                port_multiplier_port,
+                lun,
+            } => {
+                out.extend(hba_port.to_le_bytes());
+                out.extend(port_multiplier_port.to_le_bytes());
+                out.extend(lun.to_le_bytes());
+            }
+            DeviceNode::Nvme {
+                namespace_id,
+                eui64,
+            } => {
+                out.extend(namespace_id.to_le_bytes());
+                out.extend(eui64);
+            }
+            DeviceNode::Usb {
+                parent_port,
+                interface,
+            } => out.extend([*parent_port, *interface]),
+            DeviceNode::MacAddr { address, if_type } => {
+                out.extend(address);
+                out.push(*if_type);
+            }
+            DeviceNode::Ipv4(ip) => {
+                out.extend(ip.local);
+                out.extend(ip.remote);
+                out.extend(ip.local_port.to_le_bytes());
+                out.extend(ip.remote_port.to_le_bytes());
+                out.extend(ip.protocol.to_le_bytes());
+                out.push(ip.static_address as u8);
+                out.extend(ip.gateway);
+                out.extend(ip.subnet_mask);
+            }
+            DeviceNode::HardDrive(hd) => {
+                out.extend(hd.partition_number.to_le_bytes());
+                out.extend(hd.partition_start.to_le_bytes());
+                out.extend(hd.partition_size.to_le_bytes());
+                out.extend(hd.signature);
+                out.extend([hd.partition_format, hd.signature_type]);
+            }
+            DeviceNode::FilePath(data) | DeviceNode::Unknown { data, .. } => out.extend(*data),
+            DeviceNode::End { .. } => {}
+        }
+        let len = (out.len() - start) as u16;
+        out[start + 2..start + 4].copy_from_slice(&len.to_le_bytes());
+    }
+}
+
+fn write_eisa_id(f: &mut fmt::Formatter<'_>, id: u32) -> fmt::Result {
+    let vendor = id as u16;
+    for shift in [10, 5, 0] {
+        f.write_char((b'@' + ((vendor >> shift) & 0x1f) as u8) as char)?;
+    }
+    write!(f, "{:04X}", id >> 16)
+}
+
+fn write_ip(f: &mut fmt::Formatter<'_>, ip: &[u8; 4]) -> fmt::Result {
+    write!(f, "{}.{}.{}.{}", ip[0], ip[1], ip[2], ip[3])
+}
+
+impl fmt::Display for DeviceNode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceNode::Pci { function, device } => write!(f, "Pci({device:#x},{function:#x})"),
+            DeviceNode::Acpi { hid, uid } => match *hid {
+                PNP_0A03 => write!(f, "PciRoot({uid:#x})"),
+                PNP_0A08 => write!(f, "PcieRoot({uid:#x})"),
+                _ => {
+                    f.write_str("Acpi(")?;
+                    write_eisa_id(f, *hid)?;
+                    write!(f, ",{uid:#x})")
+                }
+            },
+            DeviceNode::Sata {
+                hba_port,
+                port_multiplier_port,
+                lun,
+            } => write!(f, "Sata({hba_port:#x},{port_multiplier_port:#x},{lun:#x})"),
+            DeviceNode::Nvme {
+                namespace_id,
+                eui64,
+            } => {
+                write!(f, "NVMe({namespace_id:#x},")?;
+                // Printed most significant byte first, like EDK2.
+                for (i, b) in eui64.iter().rev().enumerate() {
+                    let sep = if i == 0 { "" } else { "-" };
+                    write!(f, "{sep}{b:02X}")?;
+                }
+                f.write_char(')')
+            }
+            DeviceNode::Usb {
+                parent_port,
+                interface,
+            } => write!(f, "USB({parent_port:#x},{interface:#x})"),
+            DeviceNode::MacAddr { address, if_type } => {
+                let len = if *if_type <= 1 { 6 } else { address.len() };
+                f.write_str("MAC(")?;
+                for b in &address[..len] {
+                    write!(f, "{b:02x}")?;
+                }
+                write!(f, ",{if_type:#x})")
+            }
+            DeviceNode::Ipv4(ip) => {
+                f.write_str("IPv4(")?;
+                write_ip(f, &ip.remote)?;
+                match ip.protocol {
+                    IP_PROTOCOL_TCP => f.write_str(",TCP,")?,
+                    IP_PROTOCOL_UDP => f.write_str(",UDP,")?,
+                    other => write!(f, ",{other:#x},")?,
+                }
+                f.write_str(if ip.static_address {
+                    "Static,"
+                } else {
+                    "DHCP,"
+                })?;
+                write_ip(f, &ip.local)?;
+                f.write_char(',')?;
+                write_ip(f, &ip.gateway)?;
+                f.write_char(',')?;
+                write_ip(f, &ip.subnet_mask)?;
+                f.write_char(')')
+            }
+            DeviceNode::HardDrive(hd) => {
+                write!(f, "HD({},", hd.partition_number)?;
+                match hd.signature_type {
+                    HD_SIGNATURE_MBR => {
+                        let sig = u32::from_le_bytes(array(&hd.signature, 0));
+                        write!(f, "MBR,{sig:#010x},")?
+                    }
+                    HD_SIGNATURE_GUID => write!(f, "GPT,{},", GUID::from_bytes(hd.signature))?,
+                    other => write!(f, "{other},0,")?,
+                }
+                write!(f, "{:#x},{:#x})", hd.partition_start, hd.partition_size)
+            }
+            DeviceNode::FilePath(_) => {
+                for c in self.file_path_chars() {
+                    f.write_char(c)?;
+                }
+                Ok(())
+            }
+            DeviceNode::End { entire: true } => Ok(()),
+            DeviceNode::End { entire: false } => f.write_char(','),
+            DeviceNode::Unknown { ty, sub_type, data } => {
+                write!(f, "Path({ty},{sub_type},")?;
+                for b in *data {
+                    write!(f, "{b:02X}")?;
+                }
+                f.write_char(')')
+            }
+        }
+    }
+}
+
+/// An owned device path, always terminated with an end-of-path node.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DevicePathBuf {
+    bytes: Vec<u8>,
+}
+
+impl Default for DevicePathBuf {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DevicePathBuf {
+    /// The empty path, only the end node.
+    pub fn new() -> Self {
+        let mut bytes = Vec::new();
+        DeviceNode::End { entire: true }.encode(&mut bytes);
+        Self { bytes }
+    }
+
+    pub fn as_path(&self) -> DevicePath<'_> {
+        DevicePath { bytes: &self.bytes }
+    }
+
+    pub fn as_ptr(&self) -> *const EFIDevicePath {
+        self.bytes.as_ptr().cast()
+    }
+
+    fn end_offset(&self) -> usize {
+        self.bytes.len() - HEADER_LEN
+    }
+
+    /// Appends `node` in front of the end node. `End { entire: false }` starts a new instance,
+    /// `End { entire: true }` is ignored.
+    pub fn push(&mut self, node: &DeviceNode) -> &mut Self {
+        if *node == (DeviceNode::End { entire: true }) {
+            return self;
+        }
+        let mut encoded = Vec::new();
+        node.encode(&mut encoded);
+        let at = self.end_offset();
+        self.bytes.splice(at..at, encoded);
+        self
+    }
+
+    /// Appends a file path node holding `path`.
+    pub fn push_file_path(&mut self, path: &str) -> &mut Self {
+        let data: Vec<u8> = path
+            .encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        self.push(&DeviceNode::FilePath(&data))
+    }
+
+    /// Appends every node of `path`, e.g. a file path to the path of the device holding it.
+    pub fn extend(&mut self, path: &DevicePath) -> &mut Self {
+        let nodes = &path.bytes[..path.bytes.len() - HEADER_LEN];
+        let at = self.end_offset();
+        self.bytes.splice(at..at, nodes.iter().copied());
+        self
+    }
+
+    /// Parses the text form, see the module docs. Components that are not `Name(args)` are
+    /// taken as file path nodes.
+    pub fn from_text(s: &str) -> Result<Self, DevicePathParseError> {
+        let mut path = Self::new();
+        let mut depth = 0usize;
+        let mut start = 0;
+        let mut index = 0;
+        for (i, c) in s.char_indices().chain([(s.len(), '/')]) {
+            match c {
+                '(' => depth += 1,
+                ')' => depth = depth.saturating_sub(1),
+                '/' | ',' if depth == 0 => {
+                    let component = &s[start..i];
+                    if !component.is_empty() {
+                        parse_component(&mut path, component, index)?;
+                        index += 1;
+                    }
+                    if c == ',' {
+                        path.push(&DeviceNode::End { entire: false });
+                    }
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl FromStr for DevicePathBuf {
+    type Err = DevicePathParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_text(s)
+    }
+}
+
+impl fmt::Display for DevicePathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_path().fmt(f)
+    }
+}
+
+impl fmt::Debug for DevicePathBuf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_path().fmt(f)
+    }
+}
+
+/// The index is the component (node) that failed, counting from 0.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DevicePathParseError {
+    UnknownNode(usize),
+    InvalidArguments(usize),
+}
+
+fn parse_num(s: &str) -> Option<u64> {
+    let s = s.trim();
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+fn parse_ip(s: &str) -> Option<[u8; 4]> {
+    let mut ip = [0u8; 4];
+    let mut parts = s.trim().split('.');
+    for b in &mut ip {
+        *b = parts.next()?.parse().ok()?;
+    }
+    parts.next().is_none().then_some(ip)
+}
+
+fn hex_byte(pair: &[u8; 2]) -> Option<u8> {
+    u8::from_str_radix(core::str::from_utf8(pair).ok()?, 16).ok()
+}
+
+fn parse_hex_bytes<const N: usize>(s: &str) -> Option<[u8; N]> {
+    let digits: Vec<u8> = s.bytes().filter(|c| *c != b'-').collect();
+    if digits.len() > N * 2 || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut out = [0u8; N];
+    for (b, pair) in out.iter_mut().zip(digits.as_chunks::<2>().0) {
+        *b = hex_byte(pair)?;
+    }
+    Some(out)
+}
+
+fn parse_eisa_id(s: &str) -> Option<u32> {
+    let s = s.trim().as_bytes();
+    if s.len() != 7 || !s[..3].iter().all(u8::is_ascii_uppercase) {
+        return None;
+    }
+    let vendor = s[..3]
+        .iter()
+        .fold(0u16, |acc, c| (acc << 5) | (c - b'@') as u16);
+    let product = u16::from_str_radix(core::str::from_utf8(&s[3..]).ok()?, 16).ok()?;
+    Some(((product as u32) << 16) | vendor as u32)
+}
+
+fn parse_component(
+    path: &mut DevicePathBuf,
+    component: &str,
+    index: usize,
+) -> Result<(), DevicePathParseError> {
+    let node_call = component
+        .strip_suffix(')')
+        .and_then(|s| s.split_once('('))
+        .filter(|(name, _)| !name.is_empty() && name.bytes().all(|c| c.is_ascii_alphanumeric()));
+    let Some((name, args)) = node_call else {
+        path.push_file_path(component);
+        return Ok(());
+    };
+
+    let args: Vec<&str> = args.split(',').map(str::trim).collect();
+    let invalid = DevicePathParseError::InvalidArguments(index);
+    let num = |i: usize| args.get(i).copied().and_then(parse_num);
+    let small = |i: usize| num(i).and_then(|n| u8::try_from(n).ok());
+    let word = |i: usize| num(i).and_then(|n| u16::try_from(n).ok());
+    let dword = |i: usize| num(i).and_then(|n| u32::try_from(n).ok());
+
+    let node = match (name, args.len()) {
+        ("Pci", 2) => DeviceNode::Pci {
+            device: small(0).ok_or(invalid)?,
+            function: small(1).ok_or(invalid)?,
+        },
+        ("PciRoot", 1) => DeviceNode::Acpi {
+            hid: PNP_0A03,
+            uid: dword(0).ok_or(invalid)?,
+        },
+        ("PcieRoot", 1) => DeviceNode::Acpi {
+            hid: PNP_0A08,
+            uid: dword(0).ok_or(invalid)?,
+        },
+        ("Acpi", 2) => DeviceNode::Acpi {
+            hid: parse_eisa_id(args[0]).or_else(|| dword(0)).ok_or(invalid)?,
+            uid: dword(1).ok_or(invalid)?,
+        },
+        ("Sata", 3) => DeviceNode::Sata {
+            hba_port: word(0).ok_or(invalid)?,
+            port_multiplier_port: word(1).ok_or(invalid)?,
+            lun: word(2).ok_or(invalid)?,
+        },
+        ("NVMe", 2) => {
+            let mut eui64 = parse_hex_bytes::<8>(args[1]).ok_or(invalid)?;
+            eui64.reverse();
+            DeviceNode::Nvme {
+                namespace_id: dword(0).ok_or(invalid)?,
+                eui64,
+            }
+        }
+        ("USB", 2) => DeviceNode::Usb {
+            parent_port: small(0).ok_or(invalid)?,
+            interface: small(1).ok_or(invalid)?,
+        },
+        ("MAC", 2) => DeviceNode::MacAddr {
+            address: parse_hex_bytes::<32>(args[0]).ok_or(invalid)?,
+            if_type: small(1).ok_or(invalid)?,
+        },
+        ("IPv4", 1 | 6) => {
+            let arg = |i: usize| args.get(i).copied();
+            let protocol = match arg(1) {
+                None => 0,
+                Some("TCP") => IP_PROTOCOL_TCP,
+                Some("UDP") => IP_PROTOCOL_UDP,
+                Some(_) => word(1).ok_or(invalid)?,
+            };
+            let static_address = match arg(2) {
+                None | Some("DHCP") => false,
+                Some("Static") => true,
+                Some(_) => return Err(invalid),
+            };
+            let ip = |i: usize| match arg(i) {
+                None => Some([0; 4]),
+                Some(s) => parse_ip(s),
+            };
+            DeviceNode::Ipv4(Ipv4Node {
+                remote: ip(0).ok_or(invalid)?,
+                protocol,
+                static_address,
+                local: ip(3).ok_or(invalid)?,
+                gateway: ip(4).ok_or(invalid)?,
+                subnet_mask: ip(5).ok_or(invalid)?,
+                local_port: 0,
+                remote_port: 0,
+            })
+        }
+        ("HD", 5) => {
+            let (partition_format, signature_type, signature) = match args[1] {
+                "MBR" => {
+                    let sig = dword(2).ok_or(invalid)?;
+                    let mut signature = [0u8; 16];
+                    signature[..4].copy_from_slice(&sig.to_le_bytes());
+                    (1, HD_SIGNATURE_MBR, signature)
+                }
+                "GPT" => {
+                    let guid: GUID = args[2].parse().map_err(|_| invalid)?;
+                    (2, HD_SIGNATURE_GUID, guid.to_bytes())
+                }
+                _ => return Err(invalid),
+            };
+            DeviceNode::HardDrive(HardDriveNode {
+                partition_number: dword(0).ok_or(invalid)?,
+                partition_start: num(3).ok_or(invalid)?,
+                partition_size: num(4).ok_or(invalid)?,
+                signature,
+                partition_format,
+                signature_type,
+            })
+        }
+        ("Path", 3) => {
+            let hex = args[2].as_bytes();
+            if !hex.len().is_multiple_of(2) {
+                return Err(invalid);
+            }
+            let data = hex
+                .as_chunks::<2>()
+                .0
+                .iter()
+                .map(hex_byte)
+                .collect::<Option<Vec<u8>>>()
+                .ok_or(invalid)?;
+            path.push(&DeviceNode::Unknown {
+                ty: small(0).ok_or(invalid)?,
+                sub_type: small(1).ok_or(invalid)?,
+                data: &data,
+            });
+            return Ok(());
+        }
+        (
+            "Pci" | "PciRoot" | "PcieRoot" | "Acpi" | "Sata" | "NVMe" | "USB" | "MAC" | "IPv4"
+            | "HD" | "Path",
+            _,
+        ) => return Err(invalid),
+        _ => return Err(DevicePathParseError::UnknownNode(index)),
+    };
+    path.push(&node);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::string::String;
+
+    const BOOT_DISK: &str = "PciRoot(0x0)/Pci(0x1f,0x2)/Sata(0x0,0xffff,0x0)/\
+        HD(1,GPT,8ca5d2b4-9a31-4f5e-8a57-2bd6b3f0c1e2,0x800,0x100000)/\\EFI\\BOOT\\BOOTX64.EFI";
+
+    #[test]
+    fn test_text_round_trip() {
+        let path: DevicePathBuf = BOOT_DISK.parse().unwrap();
+        assert_eq!(format!("{path}"), BOOT_DISK);
+
+        let nodes: Vec<_> = path.as_path().nodes().map(|n| n.decode()).collect();
+        assert_eq!(nodes.len(), 6);
+        assert_eq!(
+            nodes[1],
+            DeviceNode::Pci {
+                function: 2,
+                device: 0x1f
+            }
+        );
+        let DeviceNode::HardDrive(hd) = nodes[3] else {
+            panic!("expected HD node, got {:?}", nodes[3]);
+        };
+        assert_eq!((hd.partition_number, hd.partition_format), (1, 2));
+        assert_eq!(hd.partition_start, 0x800);
+        let file: String = nodes[4].file_path_chars().collect();
+        assert_eq!(file, "\\EFI\\BOOT\\BOOTX64.EFI");
+        assert_eq!(nodes[5], DeviceNode::End { entire: true });
+    }
+
+    #[test]
+    fn test_messaging_nodes_round_trip() {
+        for text in [
+            "PcieRoot(0x1)/Pci(0x0,0x0)/NVMe(0x1,00-11-22-33-44-55-66-77)",
+            "PciRoot(0x0)/Pci(0x1d,0x0)/USB(0x1,0x0)",
+            "PciRoot(0x0)/Pci(0x3,0x0)/MAC(525400123456,0x1)/\
+             IPv4(192.168.0.1,TCP,Static,192.168.0.2,192.168.0.254,255.255.255.0)",
+            "Acpi(PNP0501,0x0)/Path(1,5,A0B1)",
+            "PciRoot(0x0)/Pci(0x2,0x0),PciRoot(0x0)/Pci(0x3,0x0)",
+            "HD(2,MBR,0x0badf00d,0x3f,0x1000)",
+        ] {
+            let path = DevicePathBuf::from_text(text).unwrap();
+            assert_eq!(format!("{path}"), text);
+        }
+    }
+
+    #[test]
+    fn test_builder_encodes_spec_lengths() {
+        let mut path = DevicePathBuf::new();
+        path.push(&DeviceNode::Acpi {
+            hid: PNP_0A03,
+            uid: 0,
+        })
+        .push(&DeviceNode::Pci {
+            function: 1,
+            device: 1,
+        })
+        .push_file_path("\\a");
+        let lengths: Vec<_> = path
+            .as_path()
+            .nodes()
+            .map(|n| n.data.len() + HEADER_LEN)
+            .collect();
+        assert_eq!(lengths, [12, 6, 10, 4]);
+        assert_eq!(format!("{path}"), "PciRoot(0x0)/Pci(0x1,0x1)/\\a");
+
+        let mut device = DevicePathBuf::from_text("PciRoot(0x0)").unwrap();
+        device.extend(&path.as_path());
+        assert_eq!(device.as_path().nodes().count(), 5);
+    }
+
+    #[test]
+    fn test_from_raw_bytes() {
+        let path = DevicePathBuf::from_text("PciRoot(0x0)/\\x").unwrap();
+        let mut bytes = path.as_path().as_bytes().to_vec();
+        bytes.extend([0xaa; 8]);
+        let parsed = DevicePath::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.as_bytes().len(), bytes.len() - 8);
+
+        let from_ptr = unsafe { DevicePath::from_ptr(bytes.as_ptr().cast()) }.unwrap();
+        assert_eq!(from_ptr, parsed);
+
+        // Node claiming to be longer than the buffer, and a path without an end node.
+        bytes[2] = 0xff;
+        assert!(DevicePath::from_bytes(&bytes).is_none());
+        assert!(DevicePath::from_bytes(&[1, 1, 6, 0, 0, 0]).is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            DevicePathBuf::from_text("PciRoot(0x0)/Bogus(1)"),
+            Err(DevicePathParseError::UnknownNode(1))
+        );
+        assert_eq!(
+            DevicePathBuf::from_text("Pci(0x100,0x0)"),
+            Err(DevicePathParseError::InvalidArguments(0))
+        );
+        assert_eq!(
+            DevicePathBuf::from_text("HD(1,GPT,nope,0x0,0x0)"),
+            Err(DevicePathParseError::InvalidArguments(0))
+        );
+    }
+}
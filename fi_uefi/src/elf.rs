@@ -0,0 +1,314 @@
+//! Loading a statically linked, identity mapped ELF64 kernel at its physical addresses.
+//!
+//! ```ignore
+//! let data = fs::read_file_to_vec("\\fi\\kernel.elf")?;
+//! let kernel = ElfImage::parse(&data)?.load(bs)?;
+//! ```
+use crate::{EFIAllocateType, EFIBootServices, EFIMemoryType, EFIStatus};
+
+const PAGE_SIZE: u64 = 4096;
+
+const ELF_MAGIC: [u8; 4] = *b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const PT_LOAD: u32 = 1;
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+#[cfg(target_arch = "x86_64")]
+const EM_HOST: u16 = 0x3e;
+#[cfg(target_arch = "aarch64")]
+const EM_HOST: u16 = 0xb7;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const EM_HOST: u16 = 0;
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// A `PT_LOAD` program header along with its bytes from the file.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Segment<'a> {
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub mem_size: u64,
+    /// Shorter than `mem_size` when the segment ends in `.bss`, the rest is zeroed.
+    pub data: &'a [u8],
+    pub flags: u32,
+}
+
+impl Segment<'_> {
+    pub fn is_executable(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.flags & 2 != 0
+    }
+}
+
+/// A checked ELF64 executable for the machine we run on. Malformed files fail with
+/// `LOAD_ERROR`, well formed ones we can't run (32 bit, big endian, other machines, position
+/// independent) with `UNSUPPORTED`.
+///
+/// No page tables are set up before the jump, so every segment has to be linked at its load
+/// address. A higher half kernel has to start from an identity mapped stub that maps itself,
+/// segments with `vaddr != paddr` are `UNSUPPORTED`.
+#[derive(Clone, Copy, Debug)]
+pub struct ElfImage<'a> {
+    data: &'a [u8],
+    entry: u64,
+    phoff: usize,
+    phentsize: usize,
+    phnum: usize,
+}
+
+impl<'a> ElfImage<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, EFIStatus> {
+        if data.len() < EHDR_SIZE || data[..4] != ELF_MAGIC {
+            return Err(EFIStatus::LOAD_ERROR);
+        }
+        if data[4] != ELFCLASS64
+            || data[5] != ELFDATA2LSB
+            || read_u16(data, 16) != ET_EXEC
+            || read_u16(data, 18) != EM_HOST
+        {
+            return Err(EFIStatus::UNSUPPORTED);
+        }
+        let image = Self {
+            data,
+            entry: read_u64(data, 24),
+            phoff: read_u64(data, 32) as usize,
+            phentsize: read_u16(data, 54) as usize,
+            phnum: read_u16(data, 56) as usize,
+        };
+        let table_end = image
+            .phentsize
+            .checked_mul(image.phnum)
+            .and_then(|len| len.checked_add(image.phoff));
+        if image.phentsize < PHDR_SIZE || table_end.is_none_or(|end| end > data.len()) {
+            return Err(EFIStatus::LOAD_ERROR);
+        }
+        for i in 0..image.phnum {
+            let header = image.phoff + i * image.phentsize;
+            if read_u32(data, header) != PT_LOAD {
+                continue;
+            }
+            let offset = read_u64(data, header + 8);
+            let file_size = read_u64(data, header + 32);
+            let mem_size = read_u64(data, header + 40);
+            let vaddr = read_u64(data, header + 16);
+            let paddr = read_u64(data, header + 24);
+            let in_file = offset
+                .checked_add(file_size)
+                .is_some_and(|end| end <= data.len() as u64);
+            if !in_file || file_size > mem_size || paddr.checked_add(mem_size).is_none() {
+                return Err(EFIStatus::LOAD_ERROR);
+            }
+            if vaddr != paddr {
+                return Err(EFIStatus::UNSUPPORTED);
+            }
+        }
+        Ok(image)
+    }
+
+    /// Virtual address of the entry point, as linked.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    pub fn segments(&self) -> impl Iterator<Item = Segment<'a>> + '_ {
+        (0..self.phnum)
+            .map(|i| self.phoff + i * self.phentsize)
+            .filter(|header| read_u32(self.data, *header) == PT_LOAD)
+            .map(|header| {
+                let offset = read_u64(self.data, header + 8) as usize;
+                let file_size = read_u64(self.data, header + 32) as usize;
+                Segment {
+                    vaddr: read_u64(self.data, header + 16),
+                    paddr: read_u64(self.data, header + 24),
+                    mem_size: read_u64(self.data, header + 40),
+                    data: &self.data[offset..offset + file_size],
+                    flags: read_u32(self.data, header + 4),
+                }
+            })
+    }
+
+    /// Page aligned physical range `start..end` covering every segment, `None` without any.
+    pub fn physical_range(&self) -> Option<(u64, u64)> {
+        let start = self.segments().map(|s| s.paddr).min()?;
+        let end = self.segments().map(|s| s.paddr + s.mem_size).max()?;
+        Some((
+            start / PAGE_SIZE * PAGE_SIZE,
+            end.next_multiple_of(PAGE_SIZE),
+        ))
+    }
+
+    /// The entry point, `None` if no segment contains it. Segments are identity mapped, so it is
+    /// the address to jump to under the firmware's page tables.
+    pub fn physical_entry(&self) -> Option<u64> {
+        self.segments()
+            .find(|s| (s.vaddr..s.vaddr + s.mem_size).contains(&self.entry))
+            .map(|s| self.entry - s.vaddr + s.paddr)
+    }
+
+    /// Lays the segments out in `dest`, which stands for physical memory starting at `base`.
+    /// Gaps between segments are left alone, `.bss` is zeroed.
+    pub fn copy_into(&self, base: u64, dest: &mut [u8]) -> Result<(), EFIStatus> {
+        for segment in self.segments() {
+            let start = segment
+                .paddr
+                .checked_sub(base)
+                .ok_or(EFIStatus::BUFFER_TOO_SMALL)? as usize;
+            let end = start + segment.mem_size as usize;
+            let target = dest
+                .get_mut(start..end)
+                .ok_or(EFIStatus::BUFFER_TOO_SMALL)?;
+            let (file, bss) = target.split_at_mut(segment.data.len());
+            file.copy_from_slice(segment.data);
+            bss.fill(0);
+        }
+        Ok(())
+    }
+
+    /// Allocates the physical range as loader code and copies the segments in. Fails with
+    /// `NOT_FOUND` if the firmware already uses some of it.
+    pub fn load(&self, bs: &EFIBootServices) -> Result<LoadedElf, EFIStatus> {
+        let (start, end) = self.physical_range().ok_or(EFIStatus::LOAD_ERROR)?;
+        let entry = self.physical_entry().ok_or(EFIStatus::LOAD_ERROR)?;
+        let pages = ((end - start) / PAGE_SIZE) as usize;
+        bs.allocate_pages(
+            EFIAllocateType::AllocateAddress,
+            EFIMemoryType::EfiLoaderCode,
+            pages,
+            start,
+        )?;
+        let dest =
+            unsafe { core::slice::from_raw_parts_mut(start as *mut u8, (end - start) as usize) };
+        if let Err(err) = self.copy_into(start, dest) {
+            let _ = bs.free_pages(start, pages);
+            return Err(err);
+        }
+        Ok(LoadedElf {
+            entry,
+            start,
+            pages,
+        })
+    }
+}
+
+/// Where the kernel ended up. The pages stay allocated as loader code, so they are not handed
+/// out again before `exit_boot_services`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LoadedElf {
+    /// Physical address to jump to.
+    pub entry: u64,
+    pub start: u64,
+    pub pages: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    /// `(vaddr, paddr, data, mem_size, flags)` segments, the entry is `entry`.
+    fn build(entry: u64, segments: &[(u64, u64, &[u8], u64, u32)]) -> Vec<u8> {
+        let mut elf = vec![0u8; EHDR_SIZE + PHDR_SIZE * segments.len()];
+        elf[..4].copy_from_slice(&ELF_MAGIC);
+        elf[4] = ELFCLASS64;
+        elf[5] = ELFDATA2LSB;
+        elf[6] = 1;
+        elf[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        elf[18..20].copy_from_slice(&EM_HOST.to_le_bytes());
+        elf[24..32].copy_from_slice(&entry.to_le_bytes());
+        elf[32..40].copy_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        elf[54..56].copy_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        elf[56..58].copy_from_slice(&(segments.len() as u16).to_le_bytes());
+        for (i, (vaddr, paddr, data, mem_size, flags)) in segments.iter().enumerate() {
+            let offset = elf.len() as u64;
+            let header = EHDR_SIZE + i * PHDR_SIZE;
+            let fields = [
+                (0, PT_LOAD as u64 | (*flags as u64) << 32),
+                (8, offset),
+                (16, *vaddr),
+                (24, *paddr),
+                (32, data.len() as u64),
+                (40, *mem_size),
+                (48, PAGE_SIZE),
+            ];
+            for (at, value) in fields {
+                elf[header + at..header + at + 8].copy_from_slice(&value.to_le_bytes());
+            }
+            elf.extend_from_slice(data);
+        }
+        elf
+    }
+
+    #[test]
+    fn test_segments_and_layout() {
+        let elf = build(
+            0x20_0010,
+            &[
+                (0x20_0000, 0x20_0000, b"code", 0x20, 5),
+                (0x20_2000, 0x20_2000, b"data", 0x10, 6),
+            ],
+        );
+        let image = ElfImage::parse(&elf).unwrap();
+        let segments: Vec<_> = image.segments().collect();
+        assert_eq!(segments.len(), 2);
+        assert!(segments[0].is_executable() && !segments[0].is_writable());
+        assert_eq!(segments[1].data, b"data");
+        assert_eq!(image.physical_range(), Some((0x20_0000, 0x20_3000)));
+        assert_eq!(image.physical_entry(), Some(0x20_0010));
+
+        let mut memory = vec![0xaau8; 0x3000];
+        image.copy_into(0x20_0000, &mut memory).unwrap();
+        assert_eq!(&memory[..4], b"code");
+        assert!(memory[4..0x20].iter().all(|b| *b == 0));
+        // The gap between the segments is left alone.
+        assert_eq!(memory[0x20], 0xaa);
+        assert_eq!(&memory[0x2000..0x2004], b"data");
+        assert!(memory[0x2004..0x2010].iter().all(|b| *b == 0));
+        assert_eq!(
+            image.copy_into(0x20_0000, &mut memory[..0x2008]),
+            Err(EFIStatus::BUFFER_TOO_SMALL)
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_images() {
+        assert_eq!(
+            ElfImage::parse(b"MZ not an elf").err(),
+            Some(EFIStatus::LOAD_ERROR)
+        );
+        let mut elf = build(0x1000, &[(0x1000, 0x1000, b"code", 4, 5)]);
+        elf[4] = 1;
+        assert_eq!(ElfImage::parse(&elf).err(), Some(EFIStatus::UNSUPPORTED));
+
+        // File size past the end of the file.
+        let mut elf = build(0x1000, &[(0x1000, 0x1000, b"code", 4, 5)]);
+        let len = elf.len();
+        elf.truncate(len - 1);
+        assert_eq!(ElfImage::parse(&elf).err(), Some(EFIStatus::LOAD_ERROR));
+
+        // Linked in the higher half, nothing would map it.
+        let high = 0xffff_ffff_8000_0000;
+        let elf = build(high, &[(high, 0x1000, b"code", 4, 5)]);
+        assert_eq!(ElfImage::parse(&elf).err(), Some(EFIStatus::UNSUPPORTED));
+
+        // Entry outside every segment.
+        let elf = build(0x9000, &[(0x1000, 0x1000, b"code", 4, 5)]);
+        assert_eq!(ElfImage::parse(&elf).unwrap().physical_entry(), None);
+    }
+}
@@ -0,0 +1,279 @@
+//! Owned events with closure callbacks, timers and TPL guards.
+//!
+//! ```ignore
+//! let timer = OwnedEvent::timer(bs)?;
+//! timer.set_timer(TimerTrigger::Relative(5 * TICKS_PER_SECOND))?;
+//! match wait_any(&[stdin.wait_for_key, timer.raw()])? {
+//!     0 => { /* key pressed */ }
+//!     _ => { /* timed out */ }
+//! }
+//! ```
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::ptr;
+
+use crate::{EFIBootServices, EFIStatus, Event};
+
+/// `ty` bits for `create_event`.
+pub mod event_type {
+    pub const TIMER: u32 = 0x8000_0000;
+    pub const RUNTIME: u32 = 0x4000_0000;
+    pub const NOTIFY_WAIT: u32 = 0x0000_0100;
+    pub const NOTIFY_SIGNAL: u32 = 0x0000_0200;
+    pub const SIGNAL_EXIT_BOOT_SERVICES: u32 = 0x0000_0201;
+    pub const SIGNAL_VIRTUAL_ADDRESS_CHANGE: u32 = 0x6000_0202;
+}
+
+/// Task priority levels.
+pub mod tpl {
+    pub const APPLICATION: usize = 4;
+    pub const CALLBACK: usize = 8;
+    pub const NOTIFY: usize = 16;
+    pub const HIGH_LEVEL: usize = 31;
+}
+
+/// Timer periods are counted in 100ns ticks.
+pub const TICKS_PER_SECOND: u64 = 10_000_000;
+pub const TICKS_PER_MILLISECOND: u64 = 10_000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TimerTrigger {
+    Cancel,
+    /// Fires every so many 100ns ticks, 0 means on every timer tick.
+    Periodic(u64),
+    /// Fires once after so many 100ns ticks, 0 means on the next timer tick.
+    Relative(u64),
+}
+
+impl TimerTrigger {
+    const fn to_raw(self) -> (u32, u64) {
+        match self {
+            TimerTrigger::Cancel => (0, 0),
+            TimerTrigger::Periodic(ticks) => (1, ticks),
+            TimerTrigger::Relative(ticks) => (2, ticks),
+        }
+    }
+}
+
+// Boxed twice so the context handed to the firmware is a thin pointer.
+type Callback = Box<dyn FnMut(Event)>;
+
+unsafe extern "efiapi" fn call_notify(event: Event, context: *mut c_void) {
+    let callback = unsafe { &mut *(context as *mut Callback) };
+    callback(event)
+}
+
+/// An event that is closed on drop, along with its notify closure.
+pub struct OwnedEvent<'a> {
+    bs: &'a EFIBootServices,
+    event: Event,
+    callback: Option<Box<Callback>>,
+}
+
+impl<'a> OwnedEvent<'a> {
+    /// A timer without a notify function, for `wait_any` and `is_signaled`.
+    pub fn timer(bs: &'a EFIBootServices) -> Result<Self, EFIStatus> {
+        let event =
+            unsafe { bs.create_event(event_type::TIMER, tpl::CALLBACK, None, ptr::null_mut()) }?;
+        Ok(Self {
+            bs,
+            event,
+            callback: None,
+        })
+    }
+
+    /// An event of `ty` whose `notify` runs at `notify_tpl`. With `NOTIFY_SIGNAL` it runs when
+    /// the event is signaled, with `NOTIFY_WAIT` whenever the event is checked or waited on
+    /// while not signaled.
+    pub fn with_notify(
+        bs: &'a EFIBootServices,
+        ty: u32,
+        notify_tpl: usize,
+        notify: impl FnMut(Event) + 'static,
+    ) -> Result<Self, EFIStatus> {
+        let mut callback: Box<Callback> = Box::new(Box::new(notify));
+        let context = (&mut *callback as *mut Callback).cast();
+        let event = unsafe { bs.create_event(ty, notify_tpl, Some(call_notify), context) }?;
+        Ok(Self {
+            bs,
+            event,
+            callback: Some(callback),
+        })
+    }
+
+    /// # Safety
+    /// `event` must be open and not owned by anything else, it is closed on drop.
+    pub unsafe fn from_raw(bs: &'a EFIBootServices, event: Event) -> Self {
+        Self {
+            bs,
+            event,
+            callback: None,
+        }
+    }
+
+    pub fn raw(&self) -> Event {
+        self.event
+    }
+
+    /// Only for events created with `event_type::TIMER`.
+    pub fn set_timer(&self, trigger: TimerTrigger) -> Result<(), EFIStatus> {
+        let (ty, ticks) = trigger.to_raw();
+        self.bs.set_timer(self.event, ty, ticks)
+    }
+
+    pub fn signal(&self) -> Result<(), EFIStatus> {
+        self.bs.signal_event(self.event)
+    }
+
+    /// `true` if the event was signaled, which also clears it. Fails for `NOTIFY_SIGNAL`
+    /// events.
+    pub fn is_signaled(&self) -> Result<bool, EFIStatus> {
+        self.bs.check_event(self.event)
+    }
+
+    /// Blocks until the event is signaled.
+    pub fn wait(&self) -> Result<(), EFIStatus> {
+        self.bs.wait_for_event(&[self.event]).map(|_| ())
+    }
+}
+
+impl Drop for OwnedEvent<'_> {
+    fn drop(&mut self) {
+        if EFIBootServices::fetch_global().is_none() {
+            // The firmware could still call the notify, keep the closure alive for good.
+            core::mem::forget(self.callback.take());
+            return;
+        }
+        let _ = self.bs.close_event(self.event);
+    }
+}
+
+/// Blocks until one of `events` is signaled and returns its index. Must be called at
+/// `tpl::APPLICATION`, and none of the events may be `NOTIFY_SIGNAL`.
+pub fn wait_any(events: &[Event]) -> Result<usize, EFIStatus> {
+    let bs = EFIBootServices::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+    bs.wait_for_event(events)
+}
+
+/// Like [`wait_any`], but gives up after `timeout` 100ns ticks and returns `None`.
+pub fn wait_any_timeout(events: &[Event], timeout: u64) -> Result<Option<usize>, EFIStatus> {
+    let bs = EFIBootServices::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+    let timer = OwnedEvent::timer(bs)?;
+    timer.set_timer(TimerTrigger::Relative(timeout))?;
+    let mut all = Vec::with_capacity(events.len() + 1);
+    all.extend_from_slice(events);
+    all.push(timer.raw());
+    let index = bs.wait_for_event(&all)?;
+    Ok((index < events.len()).then_some(index))
+}
+
+/// Raises the TPL and restores the previous one on drop.
+pub struct TplGuard<'a> {
+    bs: &'a EFIBootServices,
+    old_tpl: usize,
+}
+
+impl<'a> TplGuard<'a> {
+    /// `new_tpl` must not be below the current TPL.
+    pub fn raise(bs: &'a EFIBootServices, new_tpl: usize) -> Self {
+        let old_tpl = bs.raise_tpl(new_tpl);
+        Self { bs, old_tpl }
+    }
+
+    /// The TPL that will be restored.
+    pub fn old_tpl(&self) -> usize {
+        self.old_tpl
+    }
+}
+
+impl Drop for TplGuard<'_> {
+    fn drop(&mut self) {
+        self.bs.restore_tpl(self.old_tpl);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockFirmware, WAIT_FOR_KEY, with_state};
+    use alloc::rc::Rc;
+    use core::cell::Cell;
+
+    fn bs() -> &'static EFIBootServices {
+        EFIBootServices::fetch_global().unwrap()
+    }
+
+    #[test]
+    fn test_notify_closure_and_close_on_drop() {
+        let _fw = MockFirmware::install();
+        let count = Rc::new(Cell::new(0));
+        {
+            let seen = count.clone();
+            let event = OwnedEvent::with_notify(
+                bs(),
+                event_type::TIMER | event_type::NOTIFY_SIGNAL,
+                tpl::CALLBACK,
+                move |_| seen.set(seen.get() + 1),
+            )
+            .unwrap();
+            event.signal().unwrap();
+            assert_eq!(count.get(), 1);
+            assert_eq!(event.is_signaled(), Err(EFIStatus::INVALID_PARAMETER));
+
+            // Ten periods of 1ms in 10ms of stalling.
+            event
+                .set_timer(TimerTrigger::Periodic(TICKS_PER_MILLISECOND))
+                .unwrap();
+            bs().stall(10_000).unwrap();
+            assert_eq!(count.get(), 11);
+            event.set_timer(TimerTrigger::Cancel).unwrap();
+            bs().stall(10_000).unwrap();
+            assert_eq!(count.get(), 11);
+        }
+        assert!(with_state(|s| s.events.is_empty()));
+    }
+
+    #[test]
+    fn test_wait_any_timeout() {
+        let fw = MockFirmware::install();
+        let bs = bs();
+        let slow = OwnedEvent::timer(bs).unwrap();
+        let fast = OwnedEvent::timer(bs).unwrap();
+        slow.set_timer(TimerTrigger::Relative(2 * TICKS_PER_SECOND))
+            .unwrap();
+        fast.set_timer(TimerTrigger::Relative(TICKS_PER_SECOND))
+            .unwrap();
+        assert_eq!(wait_any(&[slow.raw(), fast.raw()]), Ok(1));
+        assert_eq!(with_state(|s| s.clock), TICKS_PER_SECOND);
+        assert!(!fast.is_signaled().unwrap());
+        slow.wait().unwrap();
+
+        assert_eq!(
+            wait_any_timeout(&[WAIT_FOR_KEY], 3 * TICKS_PER_SECOND),
+            Ok(None)
+        );
+        assert_eq!(with_state(|s| s.clock), 5 * TICKS_PER_SECOND);
+        fw.push_keys("x");
+        assert_eq!(
+            wait_any_timeout(&[WAIT_FOR_KEY], TICKS_PER_SECOND),
+            Ok(Some(0))
+        );
+        // Only the two timers above are left, the timeout timer was closed.
+        assert_eq!(with_state(|s| s.events.len()), 2);
+    }
+
+    #[test]
+    fn test_tpl_guard_restores() {
+        let _fw = MockFirmware::install();
+        let bs = bs();
+        {
+            let guard = TplGuard::raise(bs, tpl::NOTIFY);
+            assert_eq!(guard.old_tpl(), tpl::APPLICATION);
+            let timer = OwnedEvent::timer(bs).unwrap();
+            // Waiting is only allowed at application level.
+            assert_eq!(timer.wait(), Err(EFIStatus::UNSUPPORTED));
+        }
+        assert_eq!(with_state(|s| s.tpl), tpl::APPLICATION);
+    }
+}
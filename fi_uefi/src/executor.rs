@@ -0,0 +1,388 @@
+//! A single threaded executor that parks in `wait_for_event`.
+//!
+//! Futures register the UEFI events they are waiting on with the executor through
+//! [`poll_event`], and [`block_on`] waits on all of them at once, so a key press, a timer and a
+//! protocol notify event can be raced without a polling loop.
+//!
+//! ```ignore
+//! let choice = block_on(select(next_key(), sleep(Duration::from_secs(5))))?;
+//! match choice {
+//!     Either::Left(key) => handle(key?),
+//!     Either::Right(_) => boot_default(),
+//! }
+//! ```
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::pin::{Pin, pin};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use core::time::Duration;
+
+use crate::event::{OwnedEvent, TimerTrigger};
+use crate::input::{self, Key};
+use crate::{EFIBootServices, EFIStatus, Event};
+
+#[derive(Default)]
+struct Inner {
+    woken: Cell<bool>,
+    /// Events the pending futures wait on, collected during a poll.
+    events: RefCell<Vec<Event>>,
+    /// Event that ended the last wait. `wait_for_event` clears the signal, so the futures
+    /// waiting on it learn about it from here instead of `check_event`, for one whole poll.
+    fired: Cell<Option<Event>>,
+}
+
+// The waker holds an `Rc`, which is fine as long as nothing moves it to another CPU. Boot
+// services only ever run on the boot processor.
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_waker, wake, wake_by_ref, drop_waker);
+
+unsafe fn clone_waker(data: *const ()) -> RawWaker {
+    unsafe { Rc::increment_strong_count(data as *const Inner) };
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn wake(data: *const ()) {
+    unsafe {
+        wake_by_ref(data);
+        drop_waker(data);
+    }
+}
+
+unsafe fn wake_by_ref(data: *const ()) {
+    unsafe { &*(data as *const Inner) }.woken.set(true);
+}
+
+unsafe fn drop_waker(data: *const ()) {
+    drop(unsafe { Rc::from_raw(data as *const Inner) });
+}
+
+/// The executor behind `cx`, `None` when polled by some other executor.
+fn executor<'a>(cx: &Context<'a>) -> Option<&'a Inner> {
+    let waker = cx.waker();
+    if !core::ptr::eq(waker.vtable(), &VTABLE) {
+        return None;
+    }
+    Some(unsafe { &*(waker.data() as *const Inner) })
+}
+
+/// Checks `event` and, if it is not signaled yet, has the executor wait on it. Under a
+/// foreign executor the task is woken right away, which degrades to polling.
+///
+/// Every future waiting on the event that ended the last wait sees it as signaled, so it is up
+/// to them to cope with another waiter getting there first, as [`next_key`] does.
+pub fn poll_event(cx: &mut Context<'_>, event: Event) -> Poll<Result<(), EFIStatus>> {
+    if executor(cx).is_some_and(|inner| inner.fired.get() == Some(event)) {
+        return Poll::Ready(Ok(()));
+    }
+    let Some(bs) = EFIBootServices::fetch_global() else {
+        return Poll::Ready(Err(EFIStatus::NOT_READY));
+    };
+    match bs.check_event(event) {
+        Ok(true) => Poll::Ready(Ok(())),
+        Ok(false) => {
+            wait_on(cx, event);
+            Poll::Pending
+        }
+        Err(err) => Poll::Ready(Err(err)),
+    }
+}
+
+/// Has the executor wait on `event` without checking it first.
+fn wait_on(cx: &Context<'_>, event: Event) {
+    match executor(cx) {
+        Some(inner) => {
+            let mut events = inner.events.borrow_mut();
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+        None => cx.waker().wake_by_ref(),
+    }
+}
+
+/// Runs `future` to completion. Fails with `NOT_READY` if the future is pending without any
+/// event to wait on, since nothing could ever wake it.
+pub fn block_on<F: Future>(future: F) -> Result<F::Output, EFIStatus> {
+    let bs = EFIBootServices::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+    let inner = Rc::new(Inner::default());
+    let data = Rc::into_raw(inner.clone()) as *const ();
+    let waker = unsafe { Waker::from_raw(RawWaker::new(data, &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        inner.woken.set(false);
+        inner.events.borrow_mut().clear();
+        let poll = future.as_mut().poll(&mut cx);
+        // Everyone the last wake was for has been polled, or was dropped in the meantime.
+        inner.fired.set(None);
+        if let Poll::Ready(output) = poll {
+            return Ok(output);
+        }
+        if inner.woken.get() {
+            continue;
+        }
+        let events = inner.events.borrow().clone();
+        if events.is_empty() {
+            return Err(EFIStatus::NOT_READY);
+        }
+        let index = bs.wait_for_event(&events)?;
+        inner.fired.set(Some(events[index]));
+    }
+}
+
+/// Completes once `event` is signaled. Works for any waitable event, e.g. one registered with
+/// `register_protocol_notify`.
+pub fn wait_event(event: Event) -> WaitEvent {
+    WaitEvent { event }
+}
+
+pub struct WaitEvent {
+    event: Event,
+}
+
+impl Future for WaitEvent {
+    type Output = Result<(), EFIStatus>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        poll_event(cx, self.event)
+    }
+}
+
+/// Completes after `duration`, rounded up to the 100ns timer resolution.
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        ticks: duration.as_nanos().div_ceil(100).min(u64::MAX as u128) as u64,
+        timer: None,
+    }
+}
+
+/// The timer is created on the first poll and closed when this is dropped.
+pub struct Sleep {
+    ticks: u64,
+    timer: Option<OwnedEvent<'static>>,
+}
+
+impl Future for Sleep {
+    type Output = Result<(), EFIStatus>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.timer.is_none() {
+            let timer = EFIBootServices::fetch_global()
+                .ok_or(EFIStatus::NOT_READY)
+                .and_then(OwnedEvent::timer)
+                .and_then(|timer| {
+                    timer.set_timer(TimerTrigger::Relative(self.ticks))?;
+                    Ok(timer)
+                });
+            match timer {
+                Ok(timer) => self.timer = Some(timer),
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+        let event = self.timer.as_ref().map(OwnedEvent::raw).unwrap();
+        poll_event(cx, event)
+    }
+}
+
+/// Completes with the next key from the console. With several of these pending, each key goes
+/// to one of them.
+pub fn next_key() -> NextKey {
+    NextKey
+}
+
+pub struct NextKey;
+
+impl Future for NextKey {
+    type Output = Result<Key, EFIStatus>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let wait_for_key = match input::stdin() {
+            Ok(stdin) => stdin.wait_for_key,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+        for _ in 0..2 {
+            match input::try_read_key() {
+                Ok(Some(key)) => return Poll::Ready(Ok(key)),
+                Ok(None) => {}
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+            // A key may have come in between the read and the check, go around again.
+            match poll_event(cx, wait_for_key) {
+                Poll::Ready(Ok(())) => continue,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        // The event fired for a key another waiter has read already.
+        wait_on(cx, wait_for_key);
+        Poll::Pending
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+/// Polls both futures and completes with whichever finishes first, dropping the other. `a`
+/// wins when both are ready.
+pub fn select<A: Future, B: Future>(a: A, b: B) -> Select<A, B> {
+    Select { a, b }
+}
+
+pub struct Select<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: Future, B: Future> Future for Select<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Structural pinning, neither field is moved out while pinned.
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Poll::Ready(a) = unsafe { Pin::new_unchecked(&mut this.a) }.poll(cx) {
+            return Poll::Ready(Either::Left(a));
+        }
+        if let Poll::Ready(b) = unsafe { Pin::new_unchecked(&mut this.b) }.poll(cx) {
+            return Poll::Ready(Either::Right(b));
+        }
+        Poll::Pending
+    }
+}
+
+/// Runs both futures to completion.
+pub fn join<A: Future, B: Future>(a: A, b: B) -> Join<A, B> {
+    Join {
+        a: MaybeDone::Pending(a),
+        b: MaybeDone::Pending(b),
+    }
+}
+
+enum MaybeDone<F: Future> {
+    Pending(F),
+    Done(Option<F::Output>),
+}
+
+impl<F: Future> MaybeDone<F> {
+    /// `true` once the output is in.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> bool {
+        let this = unsafe { self.get_unchecked_mut() };
+        if let MaybeDone::Pending(future) = this {
+            match unsafe { Pin::new_unchecked(future) }.poll(cx) {
+                Poll::Ready(output) => *this = MaybeDone::Done(Some(output)),
+                Poll::Pending => return false,
+            }
+        }
+        true
+    }
+
+    fn take(&mut self) -> F::Output {
+        match self {
+            MaybeDone::Done(output) => output.take().expect("join polled after completion"),
+            MaybeDone::Pending(_) => unreachable!(),
+        }
+    }
+}
+
+pub struct Join<A: Future, B: Future> {
+    a: MaybeDone<A>,
+    b: MaybeDone<B>,
+}
+
+impl<A: Future, B: Future> Future for Join<A, B> {
+    type Output = (A::Output, B::Output);
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        let a_done = unsafe { Pin::new_unchecked(&mut this.a) }.poll(cx);
+        let b_done = unsafe { Pin::new_unchecked(&mut this.b) }.poll(cx);
+        if a_done && b_done {
+            Poll::Ready((this.a.take(), this.b.take()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::TICKS_PER_SECOND;
+    use crate::mock::{MockFirmware, with_state};
+
+    fn clock() -> u64 {
+        with_state(|s| s.clock)
+    }
+
+    #[test]
+    fn test_sleep_and_join() {
+        let _fw = MockFirmware::install();
+        block_on(sleep(Duration::from_secs(1))).unwrap().unwrap();
+        assert_eq!(clock(), TICKS_PER_SECOND);
+
+        let (a, b) = block_on(join(
+            sleep(Duration::from_secs(2)),
+            sleep(Duration::from_millis(500)),
+        ))
+        .unwrap();
+        assert!(a.is_ok() && b.is_ok());
+        assert_eq!(clock(), 3 * TICKS_PER_SECOND);
+        assert!(with_state(|s| s.events.is_empty()));
+    }
+
+    #[test]
+    fn test_select_key_against_timeout() {
+        let fw = MockFirmware::install();
+        let timeout = || sleep(Duration::from_secs(5));
+        assert_eq!(
+            block_on(select(next_key(), timeout())).unwrap(),
+            Either::Right(Ok(()))
+        );
+        assert_eq!(clock(), 5 * TICKS_PER_SECOND);
+
+        fw.push_keys("b");
+        assert_eq!(
+            block_on(select(next_key(), timeout())).unwrap(),
+            Either::Left(Ok(Key::Char('b')))
+        );
+        // The losing timer was dropped with the select.
+        assert!(with_state(|s| s.events.is_empty()));
+
+        // Each key goes to one waiter, the one left without stays pending.
+        fw.push_keys("cd");
+        let keys = block_on(select(join(next_key(), next_key()), timeout())).unwrap();
+        assert_eq!(keys, Either::Left((Ok(Key::Char('c')), Ok(Key::Char('d')))));
+        fw.push_keys("e");
+        let keys = block_on(select(join(next_key(), next_key()), timeout())).unwrap();
+        assert_eq!(keys, Either::Right(Ok(())));
+    }
+
+    #[test]
+    fn test_wait_event_and_stuck_future() {
+        let _fw = MockFirmware::install();
+        let bs = EFIBootServices::fetch_global().unwrap();
+        let timer = OwnedEvent::timer(bs).unwrap();
+        timer
+            .set_timer(TimerTrigger::Relative(TICKS_PER_SECOND))
+            .unwrap();
+        block_on(wait_event(timer.raw())).unwrap().unwrap();
+        assert_eq!(clock(), TICKS_PER_SECOND);
+
+        // Both waiters see the same firing.
+        timer
+            .set_timer(TimerTrigger::Relative(TICKS_PER_SECOND))
+            .unwrap();
+        let (a, b) = block_on(join(wait_event(timer.raw()), wait_event(timer.raw()))).unwrap();
+        assert!(a.is_ok() && b.is_ok());
+        assert_eq!(clock(), 2 * TICKS_PER_SECOND);
+
+        // Pending without an event to wait on, nothing could ever wake it.
+        assert_eq!(
+            block_on(core::future::pending::<()>()),
+            Err(EFIStatus::NOT_READY)
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! `flags!`, for the bit set newtypes over firmware attribute words.
+//!
+//! ```ignore
+//! flags! {
+//!     pub struct EFIMemoryAttribute(u64) {
+//!         const UC = 0x1;
+//!         const WC = 0x2;
+//!     }
+//! }
+//! ```
+
+/// Declares a `#[repr(transparent)]` newtype over an integer with the given flag constants,
+/// `empty`, `bits`, `contains`, `intersects` and the `|`, `|=` and `&` operators. A constant's
+/// value may refer to earlier ones, e.g. `Self::A.0 | Self::B.0`.
+macro_rules! flags {
+    (
+        $(#[$outer:meta])*
+        pub struct $name:ident($bits:ty) {
+            $(
+                $(#[$inner:meta])*
+                const $flag:ident = $value:expr;
+            )*
+        }
+    ) => {
+        $(#[$outer])*
+        #[derive(Clone, Copy, Default, PartialEq, Eq, Hash, Debug)]
+        #[repr(transparent)]
+        pub struct $name(pub $bits);
+
+        impl $name {
+            $(
+                $(#[$inner])*
+                pub const $flag: Self = Self($value);
+            )*
+
+            pub const fn empty() -> Self {
+                Self(0)
+            }
+
+            pub const fn bits(&self) -> $bits {
+                self.0
+            }
+
+            pub const fn contains(&self, other: Self) -> bool {
+                self.0 & other.0 == other.0
+            }
+
+            pub const fn intersects(&self, other: Self) -> bool {
+                self.0 & other.0 != 0
+            }
+        }
+
+        impl core::ops::BitOr for $name {
+            type Output = Self;
+
+            fn bitor(self, rhs: Self) -> Self {
+                Self(self.0 | rhs.0)
+            }
+        }
+
+        impl core::ops::BitOrAssign for $name {
+            fn bitor_assign(&mut self, rhs: Self) {
+                self.0 |= rhs.0;
+            }
+        }
+
+        impl core::ops::BitAnd for $name {
+            type Output = Self;
+
+            fn bitand(self, rhs: Self) -> Self {
+                Self(self.0 & rhs.0)
+            }
+        }
+    };
+}
+
+pub(crate) use flags;
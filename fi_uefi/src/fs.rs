@@ -0,0 +1,601 @@
+//! `EFI_SIMPLE_FILE_SYSTEM_PROTOCOL` and `EFI_FILE_PROTOCOL`.
+//!
+//! ```ignore
+//! let config = fi_uefi::fs::read_file_to_vec("\\EFI\\fi_os\\boot.cfg")?;
+//! ```
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::ptr;
+
+use crate::flags::flags;
+use crate::guid::known;
+use crate::protocol::{Protocol, ScopedProtocol};
+use crate::{EFIBootServices, EFILoadedImageProtocol, EFIStatus, EFITime, GUID, Handle, Wchar};
+
+#[repr(C)]
+pub struct EFISimpleFileSystemProtocol {
+    pub revision: u64,
+    pub open_volume: unsafe extern "efiapi" fn(
+        this: *mut EFISimpleFileSystemProtocol,
+        root: *mut *mut EFIFileProtocol,
+    ) -> EFIStatus,
+}
+
+unsafe impl Protocol for EFISimpleFileSystemProtocol {
+    const GUID: GUID = known::SIMPLE_FILE_SYSTEM_PROTOCOL;
+}
+
+#[repr(C)]
+pub struct EFIFileProtocol {
+    pub revision: u64,
+    pub open: unsafe extern "efiapi" fn(
+        this: *mut EFIFileProtocol,
+        new_handle: *mut *mut EFIFileProtocol,
+        file_name: *const Wchar,
+        open_mode: u64,
+        attributes: u64,
+    ) -> EFIStatus,
+    pub close: unsafe extern "efiapi" fn(this: *mut EFIFileProtocol) -> EFIStatus,
+    pub delete: unsafe extern "efiapi" fn(this: *mut EFIFileProtocol) -> EFIStatus,
+    pub read: unsafe extern "efiapi" fn(
+        this: *mut EFIFileProtocol,
+        buffer_size: *mut usize,
+        buffer: *mut c_void,
+    ) -> EFIStatus,
+    pub write: unsafe extern "efiapi" fn(
+        this: *mut EFIFileProtocol,
+        buffer_size: *mut usize,
+        buffer: *const c_void,
+    ) -> EFIStatus,
+    pub get_position:
+        unsafe extern "efiapi" fn(this: *mut EFIFileProtocol, position: *mut u64) -> EFIStatus,
+    pub set_position:
+        unsafe extern "efiapi" fn(this: *mut EFIFileProtocol, position: u64) -> EFIStatus,
+    pub get_info: unsafe extern "efiapi" fn(
+        this: *mut EFIFileProtocol,
+        information_type: *const GUID,
+        buffer_size: *mut usize,
+        buffer: *mut c_void,
+    ) -> EFIStatus,
+    pub set_info: unsafe extern "efiapi" fn(
+        this: *mut EFIFileProtocol,
+        information_type: *const GUID,
+        buffer_size: usize,
+        buffer: *const c_void,
+    ) -> EFIStatus,
+    pub flush: unsafe extern "efiapi" fn(this: *mut EFIFileProtocol) -> EFIStatus,
+    // Revision 2 asynchronous variants, not bound.
+    pub open_ex: *mut c_void,
+    pub read_ex: *mut c_void,
+    pub write_ex: *mut c_void,
+    pub flush_ex: *mut c_void,
+}
+
+/// Fixed part of `EFI_FILE_INFO`, the null terminated file name follows.
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub(crate) struct EFIFileInfoHeader {
+    pub size: u64,
+    pub file_size: u64,
+    pub physical_size: u64,
+    pub create_time: EFITime,
+    pub last_access_time: EFITime,
+    pub modification_time: EFITime,
+    pub attribute: u64,
+}
+
+const OPEN_READ: u64 = 0x01;
+const OPEN_WRITE: u64 = 0x02;
+const OPEN_CREATE: u64 = 0x8000_0000_0000_0000;
+
+// Position that set_position moves to the end of a file.
+const END_OF_FILE: u64 = u64::MAX;
+
+// Enough for the info header and a 64 character name, bigger names take a second read.
+const INITIAL_INFO_LEN: usize = size_of::<EFIFileInfoHeader>() + 130;
+
+/// The open mode combinations the spec allows.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileMode {
+    Read,
+    ReadWrite,
+    /// Read and write, creating the file first if it does not exist.
+    CreateReadWrite,
+}
+
+impl FileMode {
+    const fn bits(self) -> u64 {
+        match self {
+            FileMode::Read => OPEN_READ,
+            FileMode::ReadWrite => OPEN_READ | OPEN_WRITE,
+            FileMode::CreateReadWrite => OPEN_READ | OPEN_WRITE | OPEN_CREATE,
+        }
+    }
+}
+
+flags! {
+    pub struct FileAttributes(u64) {
+        const READ_ONLY = 0x01;
+        const HIDDEN = 0x02;
+        const SYSTEM = 0x04;
+        const RESERVED = 0x08;
+        const DIRECTORY = 0x10;
+        const ARCHIVE = 0x20;
+    }
+}
+
+/// Decoded `EFI_FILE_INFO`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FileInfo {
+    pub file_size: u64,
+    /// Space taken on the volume.
+    pub physical_size: u64,
+    pub create_time: EFITime,
+    pub last_access_time: EFITime,
+    pub modification_time: EFITime,
+    pub attribute: FileAttributes,
+    pub file_name: String,
+}
+
+impl FileInfo {
+    pub fn is_directory(&self) -> bool {
+        self.attribute.contains(FileAttributes::DIRECTORY)
+    }
+
+    /// `buf` holds an `EFI_FILE_INFO` as written by the firmware, without alignment guarantees.
+    fn parse(buf: &[u8]) -> Result<Self, EFIStatus> {
+        let header_len = size_of::<EFIFileInfoHeader>();
+        if buf.len() < header_len {
+            return Err(EFIStatus::VOLUME_CORRUPTED);
+        }
+        let header = unsafe { ptr::read_unaligned(buf.as_ptr() as *const EFIFileInfoHeader) };
+        let end = (header.size as usize).clamp(header_len, buf.len());
+        let units = buf[header_len..end]
+            .as_chunks::<2>()
+            .0
+            .iter()
+            .map(|c| u16::from_le_bytes(*c))
+            .take_while(|c| *c != 0);
+        Ok(Self {
+            file_size: header.file_size,
+            physical_size: header.physical_size,
+            create_time: header.create_time,
+            last_access_time: header.last_access_time,
+            modification_time: header.modification_time,
+            attribute: FileAttributes(header.attribute),
+            file_name: char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect(),
+        })
+    }
+}
+
+/// UEFI paths use backslashes, forward slashes are accepted and turned around.
+fn encode_path(path: &str) -> Vec<Wchar> {
+    path.encode_utf16()
+        .map(|c| if c == b'/' as u16 { b'\\' as u16 } else { c })
+        .chain([0])
+        .collect()
+}
+
+/// An open file or directory handle, closed on drop.
+pub struct File {
+    raw: *mut EFIFileProtocol,
+}
+
+impl File {
+    /// # Safety
+    /// `raw` must be an open file handle, ownership moves to the returned `File`.
+    pub unsafe fn from_raw(raw: *mut EFIFileProtocol) -> Self {
+        Self { raw }
+    }
+
+    pub fn as_ptr(&self) -> *mut EFIFileProtocol {
+        self.raw
+    }
+
+    /// Opens `path` relative to this handle, or from the volume root when it starts with `\`.
+    pub fn open(
+        &self,
+        path: &str,
+        mode: FileMode,
+        attributes: FileAttributes,
+    ) -> Result<File, EFIStatus> {
+        let name = encode_path(path);
+        let mut raw = ptr::null_mut();
+        unsafe {
+            ((*self.raw).open)(
+                self.raw,
+                &mut raw,
+                name.as_ptr(),
+                mode.bits(),
+                attributes.bits(),
+            )
+        }
+        .to_result()?;
+        Ok(File { raw })
+    }
+
+    pub fn info(&self) -> Result<FileInfo, EFIStatus> {
+        let mut buf = vec![0u8; INITIAL_INFO_LEN];
+        loop {
+            let mut size = buf.len();
+            let status = unsafe {
+                ((*self.raw).get_info)(
+                    self.raw,
+                    &known::FILE_INFO,
+                    &mut size,
+                    buf.as_mut_ptr().cast(),
+                )
+            };
+            match status {
+                EFIStatus::BUFFER_TOO_SMALL => buf.resize(size, 0),
+                status => {
+                    status.to_result()?;
+                    return FileInfo::parse(&buf[..size]);
+                }
+            }
+        }
+    }
+
+    /// Splits on the directory attribute.
+    pub fn into_kind(self) -> Result<FileKind, EFIStatus> {
+        Ok(if self.info()?.is_directory() {
+            FileKind::Directory(Directory(self))
+        } else {
+            FileKind::Regular(RegularFile(self))
+        })
+    }
+
+    /// Writes cached data out to the device.
+    pub fn flush(&mut self) -> Result<(), EFIStatus> {
+        unsafe { ((*self.raw).flush)(self.raw) }.to_result()
+    }
+
+    /// Deletes the file, the handle is closed either way. `WARN_DELETE_FAILURE` when the file
+    /// could not be removed.
+    pub fn delete(self) -> Result<(), EFIStatus> {
+        let raw = self.raw;
+        core::mem::forget(self);
+        match unsafe { ((*raw).delete)(raw) } {
+            EFIStatus::WARN_DELETE_FAILURE => Err(EFIStatus::WARN_DELETE_FAILURE),
+            status => status.to_result(),
+        }
+    }
+
+    fn position(&self) -> Result<u64, EFIStatus> {
+        let mut position = 0;
+        unsafe { ((*self.raw).get_position)(self.raw, &mut position) }.to_result_with(position)
+    }
+
+    fn set_position(&mut self, position: u64) -> Result<(), EFIStatus> {
+        unsafe { ((*self.raw).set_position)(self.raw, position) }.to_result()
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, EFIStatus> {
+        let mut size = buf.len();
+        unsafe { ((*self.raw).read)(self.raw, &mut size, buf.as_mut_ptr().cast()) }
+            .to_result_with(size)
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        // Handles die with boot services.
+        if EFIBootServices::fetch_global().is_none() {
+            return;
+        }
+        let _ = unsafe { ((*self.raw).close)(self.raw) };
+    }
+}
+
+pub enum FileKind {
+    Regular(RegularFile),
+    Directory(Directory),
+}
+
+pub struct RegularFile(File);
+
+impl RegularFile {
+    pub fn file(&self) -> &File {
+        &self.0
+    }
+
+    pub fn into_file(self) -> File {
+        self.0
+    }
+
+    /// Reads from the current position, returns 0 at the end of the file.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, EFIStatus> {
+        self.0.read(buf)
+    }
+
+    /// Reads from the current position to the end.
+    pub fn read_to_end(&mut self) -> Result<Vec<u8>, EFIStatus> {
+        let remaining = self.len()?.saturating_sub(self.position()?);
+        let mut data = vec![0u8; remaining as usize];
+        let mut filled = 0;
+        while filled < data.len() {
+            match self.read(&mut data[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+        data.truncate(filled);
+        Ok(data)
+    }
+
+    /// Writes all of `data`, growing the file as needed.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), EFIStatus> {
+        let mut size = data.len();
+        let raw = self.0.raw;
+        unsafe { ((*raw).write)(raw, &mut size, data.as_ptr().cast()) }.to_result()?;
+        // Firmware reports a short write through the size rather than an error.
+        if size != data.len() {
+            return Err(EFIStatus::VOLUME_FULL);
+        }
+        Ok(())
+    }
+
+    pub fn position(&self) -> Result<u64, EFIStatus> {
+        self.0.position()
+    }
+
+    /// Seeking past the end is allowed, a write there grows the file.
+    pub fn seek(&mut self, position: u64) -> Result<(), EFIStatus> {
+        self.0.set_position(position)
+    }
+
+    pub fn seek_end(&mut self) -> Result<(), EFIStatus> {
+        self.0.set_position(END_OF_FILE)
+    }
+
+    pub fn len(&self) -> Result<u64, EFIStatus> {
+        Ok(self.0.info()?.file_size)
+    }
+
+    pub fn is_empty(&self) -> Result<bool, EFIStatus> {
+        Ok(self.len()? == 0)
+    }
+
+    pub fn flush(&mut self) -> Result<(), EFIStatus> {
+        self.0.flush()
+    }
+
+    pub fn delete(self) -> Result<(), EFIStatus> {
+        self.0.delete()
+    }
+}
+
+pub struct Directory(File);
+
+impl Directory {
+    pub fn file(&self) -> &File {
+        &self.0
+    }
+
+    pub fn into_file(self) -> File {
+        self.0
+    }
+
+    pub fn open(
+        &self,
+        path: &str,
+        mode: FileMode,
+        attributes: FileAttributes,
+    ) -> Result<FileKind, EFIStatus> {
+        self.0.open(path, mode, attributes)?.into_kind()
+    }
+
+    /// `ACCESS_DENIED` if `path` is a directory.
+    pub fn open_file(&self, path: &str, mode: FileMode) -> Result<RegularFile, EFIStatus> {
+        match self.open(path, mode, FileAttributes::empty())? {
+            FileKind::Regular(file) => Ok(file),
+            FileKind::Directory(_) => Err(EFIStatus::ACCESS_DENIED),
+        }
+    }
+
+    /// Opens or, with `CreateReadWrite`, creates a directory. `ACCESS_DENIED` if `path` is a
+    /// regular file.
+    pub fn open_dir(&self, path: &str, mode: FileMode) -> Result<Directory, EFIStatus> {
+        match self.open(path, mode, FileAttributes::DIRECTORY)? {
+            FileKind::Directory(dir) => Ok(dir),
+            FileKind::Regular(_) => Err(EFIStatus::ACCESS_DENIED),
+        }
+    }
+
+    /// Entries from the start of the directory, including `.` and `..` when the file system
+    /// reports them.
+    pub fn entries(&mut self) -> Result<DirEntries<'_>, EFIStatus> {
+        self.0.set_position(0)?;
+        Ok(DirEntries {
+            dir: self,
+            buf: vec![0u8; INITIAL_INFO_LEN],
+            done: false,
+        })
+    }
+
+    pub fn flush(&mut self) -> Result<(), EFIStatus> {
+        self.0.flush()
+    }
+
+    /// Fails with `WARN_DELETE_FAILURE` unless the directory is empty.
+    pub fn delete(self) -> Result<(), EFIStatus> {
+        self.0.delete()
+    }
+}
+
+/// Iterator over the entries of a [`Directory`].
+pub struct DirEntries<'a> {
+    dir: &'a mut Directory,
+    buf: Vec<u8>,
+    done: bool,
+}
+
+impl Iterator for DirEntries<'_> {
+    type Item = Result<FileInfo, EFIStatus>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let mut size = self.buf.len();
+            let raw = self.dir.0.raw;
+            let status = unsafe { ((*raw).read)(raw, &mut size, self.buf.as_mut_ptr().cast()) };
+            match status {
+                EFIStatus::BUFFER_TOO_SMALL => self.buf.resize(size, 0),
+                status => {
+                    if let Err(err) = status.to_result() {
+                        self.done = true;
+                        return Some(Err(err));
+                    }
+                    // A zero sized read marks the end of the directory.
+                    if size == 0 {
+                        self.done = true;
+                        return None;
+                    }
+                    return Some(FileInfo::parse(&self.buf[..size]));
+                }
+            }
+        }
+    }
+}
+
+/// A file system volume, the protocol stays open as long as this lives.
+pub struct Volume<'a> {
+    fs: ScopedProtocol<'a, EFISimpleFileSystemProtocol>,
+}
+
+impl<'a> Volume<'a> {
+    pub fn open(bs: &'a EFIBootServices, handle: Handle) -> Result<Self, EFIStatus> {
+        Ok(Self {
+            fs: bs.open_protocol::<EFISimpleFileSystemProtocol>(handle)?,
+        })
+    }
+
+    /// The volume the running image was loaded from.
+    pub fn boot_volume(bs: &'a EFIBootServices) -> Result<Self, EFIStatus> {
+        let image = EFILoadedImageProtocol::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+        Self::open(bs, image.device_handle)
+    }
+
+    pub fn handle(&self) -> Handle {
+        self.fs.handle()
+    }
+
+    pub fn root(&self) -> Result<Directory, EFIStatus> {
+        let mut raw = ptr::null_mut();
+        unsafe { (self.fs.open_volume)(self.fs.as_ptr(), &mut raw) }.to_result()?;
+        Ok(Directory(File { raw }))
+    }
+}
+
+/// Reads a whole file from the boot volume.
+pub fn read_file_to_vec(path: &str) -> Result<Vec<u8>, EFIStatus> {
+    let bs = EFIBootServices::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+    let volume = Volume::boot_volume(bs)?;
+    volume
+        .root()?
+        .open_file(path, FileMode::Read)?
+        .read_to_end()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockFirmware, with_state};
+
+    fn root() -> Directory {
+        let bs = EFIBootServices::fetch_global().unwrap();
+        Volume::boot_volume(bs).unwrap().root().unwrap()
+    }
+
+    #[test]
+    fn test_read_file_to_vec() {
+        let _fw = MockFirmware::install_with(|state| {
+            state.add_file("\\EFI\\fi_os\\kernel.elf", b"\x7fELF kernel");
+        });
+        assert_eq!(
+            read_file_to_vec("/EFI/fi_os/kernel.elf").unwrap(),
+            b"\x7fELF kernel"
+        );
+        assert_eq!(
+            read_file_to_vec("\\EFI\\missing").err(),
+            Some(EFIStatus::NOT_FOUND)
+        );
+        assert_eq!(
+            read_file_to_vec("\\EFI").err(),
+            Some(EFIStatus::ACCESS_DENIED)
+        );
+        assert_eq!(with_state(|s| s.open_files), 0);
+    }
+
+    #[test]
+    fn test_write_seek_and_delete() {
+        let _fw = MockFirmware::install();
+        let root = root();
+        let mut file = root
+            .open_file("log.txt", FileMode::CreateReadWrite)
+            .unwrap();
+        file.write(b"hello world").unwrap();
+        file.seek(6).unwrap();
+        file.write(b"there").unwrap();
+        file.seek_end().unwrap();
+        file.write(b"!").unwrap();
+        file.flush().unwrap();
+        assert_eq!(file.position().unwrap(), 12);
+        assert_eq!(file.len().unwrap(), 12);
+
+        file.seek(0).unwrap();
+        let mut head = [0u8; 5];
+        assert_eq!(file.read(&mut head).unwrap(), 5);
+        assert_eq!(&head, b"hello");
+        assert_eq!(file.read_to_end().unwrap(), b" there!");
+        assert_eq!(file.read(&mut head).unwrap(), 0);
+
+        let mut read_only = root.open_file("log.txt", FileMode::Read).unwrap();
+        assert_eq!(read_only.write(b"x"), Err(EFIStatus::ACCESS_DENIED));
+
+        file.delete().unwrap();
+        assert_eq!(
+            root.open_file("log.txt", FileMode::Read).err(),
+            Some(EFIStatus::NOT_FOUND)
+        );
+    }
+
+    #[test]
+    fn test_directory_listing() {
+        let _fw = MockFirmware::install_with(|state| {
+            state.add_file("\\EFI\\BOOT\\BOOTX64.EFI", &[0; 300]);
+            state.add_file("\\EFI\\fi_os\\boot.cfg", b"timeout=3");
+        });
+        let root = root();
+        let mut efi = root.open_dir("EFI", FileMode::Read).unwrap();
+        let names: Vec<_> = efi
+            .entries()
+            .unwrap()
+            .map(|e| e.map(|info| (info.is_directory(), info.file_name)))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            names,
+            [(true, String::from("BOOT")), (true, String::from("fi_os"))]
+        );
+
+        let mut boot = efi.open_dir("BOOT", FileMode::Read).unwrap();
+        let info = boot.entries().unwrap().next().unwrap().unwrap();
+        assert_eq!(info.file_name, "BOOTX64.EFI");
+        assert_eq!(info.file_size, 300);
+
+        let new_dir = efi.open_dir("logs", FileMode::CreateReadWrite).unwrap();
+        assert_eq!(efi.entries().unwrap().count(), 3);
+        new_dir.delete().unwrap();
+        assert_eq!(efi.entries().unwrap().count(), 2);
+
+        drop((efi, boot, root));
+        assert_eq!(with_state(|s| s.open_files), 0);
+    }
+}
@@ -0,0 +1,552 @@
+//! `EFI_GRAPHICS_OUTPUT_PROTOCOL`: modes, the linear framebuffer and blt.
+//!
+//! ```ignore
+//! let gop = bs.locate_protocol::<EFIGraphicsOutputProtocol>()?;
+//! if let Some(mode) = gop.find_mode(1024, 768) {
+//!     gop.set_mode(mode.number)?;
+//! }
+//! gop.fill(0, 0, 1024, 768, BltPixel::rgb(0, 0, 0x40))?;
+//! ```
+use core::ptr;
+
+use crate::guid::known;
+use crate::protocol::Protocol;
+use crate::{EFIBootServices, EFIStatus, GUID};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct PixelBitmask {
+    pub red: u32,
+    pub green: u32,
+    pub blue: u32,
+    pub reserved: u32,
+}
+
+impl PixelBitmask {
+    /// Bytes up to the highest bit any of the masks uses, 0 when they are all empty.
+    pub const fn bytes_per_pixel(&self) -> u32 {
+        let bits = self.red | self.green | self.blue | self.reserved;
+        (u32::BITS - bits.leading_zeros()).div_ceil(8)
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct EFIGraphicsOutputModeInformation {
+    pub version: u32,
+    pub horizontal_resolution: u32,
+    pub vertical_resolution: u32,
+    /// See [`PixelFormat`], kept raw since firmware may report values past the enum.
+    pub pixel_format: u32,
+    pub pixel_information: PixelBitmask,
+    pub pixels_per_scan_line: u32,
+}
+
+#[repr(C)]
+pub struct EFIGraphicsOutputProtocolMode {
+    pub max_mode: u32,
+    pub mode: u32,
+    pub info: *mut EFIGraphicsOutputModeInformation,
+    pub size_of_info: usize,
+    pub frame_buffer_base: u64,
+    pub frame_buffer_size: usize,
+}
+
+/// One pixel of a blt buffer, always blue-green-red whatever the framebuffer format.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct BltPixel {
+    pub blue: u8,
+    pub green: u8,
+    pub red: u8,
+    pub reserved: u8,
+}
+
+impl BltPixel {
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Self {
+        Self {
+            blue,
+            green,
+            red,
+            reserved: 0,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct EFIGraphicsOutputProtocol {
+    pub query_mode: unsafe extern "efiapi" fn(
+        this: *mut EFIGraphicsOutputProtocol,
+        mode_number: u32,
+        size_of_info: *mut usize,
+        info: *mut *mut EFIGraphicsOutputModeInformation,
+    ) -> EFIStatus,
+    pub set_mode: unsafe extern "efiapi" fn(
+        this: *mut EFIGraphicsOutputProtocol,
+        mode_number: u32,
+    ) -> EFIStatus,
+    pub blt: unsafe extern "efiapi" fn(
+        this: *mut EFIGraphicsOutputProtocol,
+        blt_buffer: *mut BltPixel,
+        blt_operation: u32,
+        source_x: usize,
+        source_y: usize,
+        destination_x: usize,
+        destination_y: usize,
+        width: usize,
+        height: usize,
+        delta: usize,
+    ) -> EFIStatus,
+    pub mode: *mut EFIGraphicsOutputProtocolMode,
+}
+
+unsafe impl Protocol for EFIGraphicsOutputProtocol {
+    const GUID: GUID = known::GRAPHICS_OUTPUT_PROTOCOL;
+}
+
+// EFI_GRAPHICS_OUTPUT_BLT_OPERATION
+const BLT_VIDEO_FILL: u32 = 0;
+const BLT_VIDEO_TO_BUFFER: u32 = 1;
+const BLT_BUFFER_TO_VIDEO: u32 = 2;
+const BLT_VIDEO_TO_VIDEO: u32 = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    /// Byte 0 red, 1 green, 2 blue.
+    Rgb,
+    /// Byte 0 blue, 1 green, 2 red.
+    Bgr,
+    /// Laid out by [`ModeInfo::mask`].
+    Bitmask,
+    /// No linear framebuffer, only `blt` works.
+    BltOnly,
+}
+
+impl PixelFormat {
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(PixelFormat::Rgb),
+            1 => Some(PixelFormat::Bgr),
+            2 => Some(PixelFormat::Bitmask),
+            3 => Some(PixelFormat::BltOnly),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ModeInfo {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: PixelFormat,
+    /// Only meaningful for `PixelFormat::Bitmask`.
+    pub mask: PixelBitmask,
+    /// Pixels per scan line, can be more than `width`.
+    pub stride: u32,
+}
+
+impl ModeInfo {
+    fn from_raw(info: &EFIGraphicsOutputModeInformation) -> Result<Self, EFIStatus> {
+        Ok(Self {
+            width: info.horizontal_resolution,
+            height: info.vertical_resolution,
+            pixel_format: PixelFormat::from_raw(info.pixel_format).ok_or(EFIStatus::UNSUPPORTED)?,
+            mask: info.pixel_information,
+            stride: info.pixels_per_scan_line,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Mode {
+    pub number: u32,
+    pub info: ModeInfo,
+}
+
+/// Linear framebuffer of the current mode, laid out for handing to the kernel as is.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[repr(C)]
+pub struct Framebuffer {
+    pub base: u64,
+    /// In bytes.
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Pixels per scan line.
+    pub stride: u32,
+    /// 4 for RGB and BGR, whatever the masks cover for a bitmask format.
+    pub bytes_per_pixel: u32,
+    /// Red, green, blue and reserved masks, filled in for the RGB and BGR layouts as well.
+    pub mask: PixelBitmask,
+}
+
+impl Framebuffer {
+    /// Offset in bytes of the pixel at `(x, y)`.
+    pub const fn offset(&self, x: u32, y: u32) -> u64 {
+        (y as u64 * self.stride as u64 + x as u64) * self.bytes_per_pixel as u64
+    }
+}
+
+const RGB_MASK: PixelBitmask = PixelBitmask {
+    red: 0x0000_00ff,
+    green: 0x0000_ff00,
+    blue: 0x00ff_0000,
+    reserved: 0xff00_0000,
+};
+const BGR_MASK: PixelBitmask = PixelBitmask {
+    red: 0x00ff_0000,
+    green: 0x0000_ff00,
+    blue: 0x0000_00ff,
+    reserved: 0xff00_0000,
+};
+
+/// A blt transfer. Coordinates are `(x, y)` and sizes `(width, height)` in pixels, `stride` is
+/// the width of a `buffer` row in pixels.
+pub enum BltOp<'a> {
+    /// Fills a rectangle of the screen with one color.
+    VideoFill {
+        color: BltPixel,
+        dest: (usize, usize),
+        size: (usize, usize),
+    },
+    /// Copies a screen rectangle into `buffer`.
+    VideoToBuffer {
+        buffer: &'a mut [BltPixel],
+        src: (usize, usize),
+        dest: (usize, usize),
+        size: (usize, usize),
+        stride: usize,
+    },
+    /// Draws a rectangle of `buffer` to the screen.
+    BufferToVideo {
+        buffer: &'a [BltPixel],
+        src: (usize, usize),
+        dest: (usize, usize),
+        size: (usize, usize),
+        stride: usize,
+    },
+    /// Moves a rectangle on the screen, overlap is handled by the firmware.
+    VideoToVideo {
+        src: (usize, usize),
+        dest: (usize, usize),
+        size: (usize, usize),
+    },
+}
+
+/// Checks that a `size` rectangle at `at` fits in a buffer of `len` pixels with `stride`.
+fn buffer_fits(len: usize, at: (usize, usize), size: (usize, usize), stride: usize) -> bool {
+    if size.0 == 0 || size.1 == 0 {
+        return true;
+    }
+    let Some(row_end) = at.0.checked_add(size.0).filter(|&end| end <= stride) else {
+        return false;
+    };
+    at.1.checked_add(size.1 - 1)
+        .and_then(|last_row| last_row.checked_mul(stride))
+        .and_then(|start| start.checked_add(row_end))
+        .is_some_and(|end| end <= len)
+}
+
+impl EFIGraphicsOutputProtocol {
+    fn raw_mode(&self) -> &EFIGraphicsOutputProtocolMode {
+        unsafe { &*self.mode }
+    }
+
+    pub fn max_mode(&self) -> u32 {
+        self.raw_mode().max_mode
+    }
+
+    pub fn current_mode(&self) -> Result<Mode, EFIStatus> {
+        let mode = self.raw_mode();
+        let info = unsafe { mode.info.as_ref() }.ok_or(EFIStatus::NOT_STARTED)?;
+        Ok(Mode {
+            number: mode.mode,
+            info: ModeInfo::from_raw(info)?,
+        })
+    }
+
+    /// Describes mode `number` without switching to it.
+    pub fn query_mode(&self, number: u32) -> Result<ModeInfo, EFIStatus> {
+        let bs = EFIBootServices::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+        let mut size = 0;
+        let mut info = ptr::null_mut();
+        let this = ptr::from_ref(self).cast_mut();
+        unsafe { (self.query_mode)(this, number, &mut size, &mut info) }.to_result()?;
+        let result = match unsafe { info.as_ref() } {
+            Some(info) => ModeInfo::from_raw(info),
+            None => Err(EFIStatus::DEVICE_ERROR),
+        };
+        // The firmware allocates the info from pool for every query.
+        if !info.is_null() {
+            let _ = bs.free_pool(info.cast());
+        }
+        result
+    }
+
+    /// Every mode the firmware can describe, in mode number order.
+    pub fn modes(&self) -> impl Iterator<Item = Mode> + '_ {
+        (0..self.max_mode()).filter_map(|number| {
+            let info = self.query_mode(number).ok()?;
+            Some(Mode { number, info })
+        })
+    }
+
+    /// First mode with exactly this resolution.
+    pub fn find_mode(&self, width: u32, height: u32) -> Option<Mode> {
+        self.modes()
+            .find(|m| m.info.width == width && m.info.height == height)
+    }
+
+    /// Switches modes, which also clears the screen to black.
+    pub fn set_mode(&mut self, number: u32) -> Result<(), EFIStatus> {
+        unsafe { (self.set_mode)(self, number) }.to_result()
+    }
+
+    /// `None` for `BltOnly` modes, where there is nothing to map, and for bitmasks without any
+    /// bits set.
+    pub fn framebuffer(&self) -> Option<Framebuffer> {
+        let mode = self.current_mode().ok()?;
+        let mask = match mode.info.pixel_format {
+            PixelFormat::Rgb => RGB_MASK,
+            PixelFormat::Bgr => BGR_MASK,
+            PixelFormat::Bitmask => mode.info.mask,
+            PixelFormat::BltOnly => return None,
+        };
+        let bytes_per_pixel = mask.bytes_per_pixel();
+        if bytes_per_pixel == 0 {
+            return None;
+        }
+        let raw = self.raw_mode();
+        Some(Framebuffer {
+            base: raw.frame_buffer_base,
+            size: raw.frame_buffer_size as u64,
+            width: mode.info.width,
+            height: mode.info.height,
+            stride: mode.info.stride,
+            bytes_per_pixel,
+            mask,
+        })
+    }
+
+    pub fn blt(&mut self, op: BltOp) -> Result<(), EFIStatus> {
+        let pixel = size_of::<BltPixel>();
+        let (buffer, operation, src, dest, size, delta) = match op {
+            BltOp::VideoFill { color, dest, size } => {
+                let mut color = color;
+                let buffer = ptr::from_mut(&mut color);
+                // Only the first pixel of the buffer is read for a fill.
+                return self.raw_blt(buffer, BLT_VIDEO_FILL, (0, 0), dest, size, 0);
+            }
+            BltOp::VideoToBuffer {
+                buffer,
+                src,
+                dest,
+                size,
+                stride,
+            } => {
+                if !buffer_fits(buffer.len(), dest, size, stride) {
+                    return Err(EFIStatus::INVALID_PARAMETER);
+                }
+                let ptr = buffer.as_mut_ptr();
+                let delta = stride
+                    .checked_mul(pixel)
+                    .ok_or(EFIStatus::INVALID_PARAMETER)?;
+                (ptr, BLT_VIDEO_TO_BUFFER, src, dest, size, delta)
+            }
+            BltOp::BufferToVideo {
+                buffer,
+                src,
+                dest,
+                size,
+                stride,
+            } => {
+                if !buffer_fits(buffer.len(), src, size, stride) {
+                    return Err(EFIStatus::INVALID_PARAMETER);
+                }
+                let ptr = buffer.as_ptr().cast_mut();
+                let delta = stride
+                    .checked_mul(pixel)
+                    .ok_or(EFIStatus::INVALID_PARAMETER)?;
+                (ptr, BLT_BUFFER_TO_VIDEO, src, dest, size, delta)
+            }
+            BltOp::VideoToVideo { src, dest, size } => {
+                (ptr::null_mut(), BLT_VIDEO_TO_VIDEO, src, dest, size, 0)
+            }
+        };
+        self.raw_blt(buffer, operation, src, dest, size, delta)
+    }
+
+    fn raw_blt(
+        &mut self,
+        buffer: *mut BltPixel,
+        operation: u32,
+        src: (usize, usize),
+        dest: (usize, usize),
+        size: (usize, usize),
+        delta: usize,
+    ) -> Result<(), EFIStatus> {
+        unsafe {
+            (self.blt)(
+                self, buffer, operation, src.0, src.1, dest.0, dest.1, size.0, size.1, delta,
+            )
+        }
+        .to_result()
+    }
+
+    pub fn fill(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        color: BltPixel,
+    ) -> Result<(), EFIStatus> {
+        self.blt(BltOp::VideoFill {
+            color,
+            dest: (x, y),
+            size: (width, height),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockFirmware, with_state};
+
+    fn gop() -> &'static mut EFIGraphicsOutputProtocol {
+        let bs = EFIBootServices::fetch_global().unwrap();
+        bs.locate_protocol::<EFIGraphicsOutputProtocol>().unwrap()
+    }
+
+    #[test]
+    fn test_modes_and_framebuffer() {
+        let _fw = MockFirmware::install();
+        let gop = gop();
+        let modes: alloc::vec::Vec<_> = gop.modes().collect();
+        assert_eq!(modes.len(), 3);
+        assert!(with_state(|s| s.pool.is_empty()));
+
+        let mode = gop.find_mode(800, 600).unwrap();
+        assert_eq!(mode.info.pixel_format, PixelFormat::Bgr);
+        gop.set_mode(mode.number).unwrap();
+        assert_eq!(gop.current_mode().unwrap(), mode);
+
+        let fb = gop.framebuffer().unwrap();
+        assert_eq!((fb.width, fb.height, fb.stride), (800, 600, 832));
+        assert_eq!(fb.size, 832 * 600 * 4);
+        assert_eq!(fb.mask, BGR_MASK);
+        assert_eq!(fb.bytes_per_pixel, 4);
+        assert_eq!(fb.offset(1, 2), (2 * 832 + 1) * 4);
+
+        let blt_only = gop.find_mode(1024, 768).unwrap();
+        gop.set_mode(blt_only.number).unwrap();
+        assert!(gop.framebuffer().is_none());
+        assert_eq!(gop.set_mode(7), Err(EFIStatus::UNSUPPORTED));
+    }
+
+    #[test]
+    fn test_blt_operations() {
+        let _fw = MockFirmware::install();
+        let gop = gop();
+        let red = BltPixel::rgb(0xff, 0, 0);
+        let blue = BltPixel::rgb(0, 0, 0xff);
+        gop.fill(10, 10, 4, 2, red).unwrap();
+
+        // 2x2 sprite in the bottom right corner of a 3x3 buffer.
+        let mut sprite = [BltPixel::default(); 9];
+        sprite[4] = blue;
+        sprite[8] = blue;
+        gop.blt(BltOp::BufferToVideo {
+            buffer: &sprite,
+            src: (1, 1),
+            dest: (0, 0),
+            size: (2, 2),
+            stride: 3,
+        })
+        .unwrap();
+        gop.blt(BltOp::VideoToVideo {
+            src: (10, 10),
+            dest: (20, 20),
+            size: (4, 2),
+        })
+        .unwrap();
+
+        let mut out = [BltPixel::default(); 8];
+        gop.blt(BltOp::VideoToBuffer {
+            buffer: &mut out,
+            src: (20, 20),
+            dest: (0, 0),
+            size: (4, 2),
+            stride: 4,
+        })
+        .unwrap();
+        assert!(out.iter().all(|p| *p == red));
+        let mut corner = [BltPixel::default(); 4];
+        gop.blt(BltOp::VideoToBuffer {
+            buffer: &mut corner,
+            src: (0, 0),
+            dest: (0, 0),
+            size: (2, 2),
+            stride: 2,
+        })
+        .unwrap();
+        assert_eq!(
+            corner,
+            [blue, BltPixel::default(), BltPixel::default(), blue]
+        );
+
+        // Rectangle running past the end of the buffer.
+        assert_eq!(
+            gop.blt(BltOp::BufferToVideo {
+                buffer: &sprite,
+                src: (2, 2),
+                dest: (0, 0),
+                size: (2, 2),
+                stride: 3,
+            }),
+            Err(EFIStatus::INVALID_PARAMETER)
+        );
+        // Sizes that would overflow the bounds arithmetic.
+        assert_eq!(
+            gop.blt(BltOp::VideoToBuffer {
+                buffer: &mut out,
+                src: (0, 0),
+                dest: (1, 0),
+                size: (usize::MAX, 1),
+                stride: 4,
+            }),
+            Err(EFIStatus::INVALID_PARAMETER)
+        );
+        assert_eq!(
+            gop.blt(BltOp::BufferToVideo {
+                buffer: &sprite,
+                src: (0, 0),
+                dest: (0, 0),
+                size: (1, usize::MAX),
+                stride: 3,
+            }),
+            Err(EFIStatus::INVALID_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn test_bitmask_bytes_per_pixel() {
+        assert_eq!(RGB_MASK.bytes_per_pixel(), 4);
+        let rgb565 = PixelBitmask {
+            red: 0xf800,
+            green: 0x07e0,
+            blue: 0x001f,
+            reserved: 0,
+        };
+        assert_eq!(rgb565.bytes_per_pixel(), 2);
+        let rgb888 = PixelBitmask {
+            red: 0xff_0000,
+            green: 0x00_ff00,
+            blue: 0x00_00ff,
+            reserved: 0,
+        };
+        assert_eq!(rgb888.bytes_per_pixel(), 3);
+        assert_eq!(PixelBitmask::default().bytes_per_pixel(), 0);
+    }
+}
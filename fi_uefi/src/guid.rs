@@ -0,0 +1,265 @@
+use core::fmt;
+use core::str::FromStr;
+
+/// A UEFI GUID in its in-memory (mixed-endian) layout: the first three fields are little endian
+/// integers, `data_4` is a plain byte array.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(C)]
+pub struct GUID {
+    pub data_1: u32,
+    pub data_2: u16,
+    pub data_3: u16,
+    pub data_4: [u8; 8],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GUIDParseError {
+    InvalidLength(usize),
+    InvalidSeparator(usize),
+    InvalidDigit(usize),
+}
+
+const CANONICAL_LEN: usize = 36;
+const SEPARATORS: [usize; 4] = [8, 13, 18, 23];
+
+const fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+impl GUID {
+    pub const fn new(data_1: u32, data_2: u16, data_3: u16, data_4: [u8; 8]) -> Self {
+        Self {
+            data_1,
+            data_2,
+            data_3,
+            data_4,
+        }
+    }
+
+    pub const fn zero() -> Self {
+        Self::new(0, 0, 0, [0; 8])
+    }
+
+    /// Builds a GUID from its 16 byte in-memory form.
+    pub const fn from_bytes(b: [u8; 16]) -> Self {
+        Self::new(
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            u16::from_le_bytes([b[4], b[5]]),
+            u16::from_le_bytes([b[6], b[7]]),
+            [b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]],
+        )
+    }
+
+    pub const fn to_bytes(&self) -> [u8; 16] {
+        let d1 = self.data_1.to_le_bytes();
+        let d2 = self.data_2.to_le_bytes();
+        let d3 = self.data_3.to_le_bytes();
+        let d4 = self.data_4;
+        [
+            d1[0], d1[1], d1[2], d1[3], d2[0], d2[1], d3[0], d3[1], d4[0], d4[1], d4[2], d4[3],
+            d4[4], d4[5], d4[6], d4[7],
+        ]
+    }
+
+    /// Parses the canonical `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx` form, hex digits in either
+    /// case.
+    pub const fn try_parse(s: &str) -> Result<Self, GUIDParseError> {
+        let s = s.as_bytes();
+        if s.len() != CANONICAL_LEN {
+            return Err(GUIDParseError::InvalidLength(s.len()));
+        }
+
+        // Digits in text order, which is also big endian order for every field.
+        let mut digits = [0u8; 16];
+        let mut nibble = 0;
+        let mut i = 0;
+        while i < CANONICAL_LEN {
+            let c = s[i];
+            if i == SEPARATORS[0] || i == SEPARATORS[1] || i == SEPARATORS[2] || i == SEPARATORS[3]
+            {
+                if c != b'-' {
+                    return Err(GUIDParseError::InvalidSeparator(i));
+                }
+            } else {
+                let Some(v) = hex_value(c) else {
+                    return Err(GUIDParseError::InvalidDigit(i));
+                };
+                digits[nibble / 2] = (digits[nibble / 2] << 4) | v;
+                nibble += 1;
+            }
+            i += 1;
+        }
+
+        let d = digits;
+        Ok(Self::new(
+            u32::from_be_bytes([d[0], d[1], d[2], d[3]]),
+            u16::from_be_bytes([d[4], d[5]]),
+            u16::from_be_bytes([d[6], d[7]]),
+            [d[8], d[9], d[10], d[11], d[12], d[13], d[14], d[15]],
+        ))
+    }
+
+    /// `try_parse` for const contexts, panics (a compile error there) on bad input.
+    pub const fn parse_or_panic(s: &str) -> Self {
+        match Self::try_parse(s) {
+            Ok(guid) => guid,
+            Err(_) => panic!("invalid GUID string"),
+        }
+    }
+}
+
+impl Default for GUID {
+    fn default() -> Self {
+        Self::zero()
+    }
+}
+
+impl FromStr for GUID {
+    type Err = GUIDParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_parse(s)
+    }
+}
+
+impl fmt::Display for GUID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let d4 = &self.data_4;
+        write!(
+            f,
+            "{:08x}-{:04x}-{:04x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            self.data_1,
+            self.data_2,
+            self.data_3,
+            d4[0],
+            d4[1],
+            d4[2],
+            d4[3],
+            d4[4],
+            d4[5],
+            d4[6],
+            d4[7]
+        )
+    }
+}
+
+impl fmt::Debug for GUID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "GUID({self})")
+    }
+}
+
+/// Parses a canonical GUID string at compile time.
+///
+/// ```ignore
+/// const GLOBAL_VARIABLE: GUID = guid!("8be4df61-93ca-11d2-aa0d-00e098032b8c");
+/// ```
+#[macro_export]
+macro_rules! guid {
+    ($s:literal) => {{
+        const GUID: $crate::GUID = $crate::GUID::parse_or_panic($s);
+        GUID
+    }};
+}
+
+pub mod known {
+    use super::GUID;
+
+    // Configuration tables
+    pub const ACPI_TABLE: GUID = guid!("eb9d2d30-2d88-11d3-9a16-0090273fc14d");
+    pub const ACPI_20_TABLE: GUID = guid!("8868e871-e4f1-11d3-bc22-0080c73c8881");
+    pub const SMBIOS_TABLE: GUID = guid!("eb9d2d31-2d88-11d3-9a16-0090273fc14d");
+    pub const SMBIOS3_TABLE: GUID = guid!("f2fd1544-9794-4a2c-992e-e5bbcf20e394");
+    pub const DXE_SERVICES_TABLE: GUID = guid!("05ad34ba-6f02-4214-952e-4da0398e2bb9");
+    pub const HOB_LIST: GUID = guid!("7739f24c-93d7-11d4-9a3a-0090273fc14d");
+    pub const MEMORY_ATTRIBUTES_TABLE: GUID = guid!("dcfa911d-26eb-469f-a220-38b7dc461220");
+
+    // Protocols
+    pub const LOADED_IMAGE_PROTOCOL: GUID = guid!("5b1b31a1-9562-11d2-8e3f-00a0c969723b");
+    pub const LOADED_IMAGE_DEVICE_PATH_PROTOCOL: GUID =
+        guid!("bc62157e-3e33-4fec-9920-2d3b36d750df");
+    pub const DEVICE_PATH_PROTOCOL: GUID = guid!("09576e91-6d3f-11d2-8e39-00a0c969723b");
+    pub const DEVICE_PATH_TO_TEXT_PROTOCOL: GUID = guid!("8b843e20-8132-4852-90cc-551a4e4a7f1c");
+    pub const SIMPLE_FILE_SYSTEM_PROTOCOL: GUID = guid!("964e5b22-6459-11d2-8e39-00a0c969723b");
+    pub const BLOCK_IO_PROTOCOL: GUID = guid!("964e5b21-6459-11d2-8e39-00a0c969723b");
+    pub const GRAPHICS_OUTPUT_PROTOCOL: GUID = guid!("9042a9de-23dc-4a38-96fb-7aded080516a");
+    pub const SIMPLE_TEXT_INPUT_PROTOCOL: GUID = guid!("387477c1-69c7-11d2-8e39-00a0c969723b");
+    pub const SIMPLE_TEXT_OUTPUT_PROTOCOL: GUID = guid!("387477c2-69c7-11d2-8e39-00a0c969723b");
+    pub const SERIAL_IO_PROTOCOL: GUID = guid!("bb25cf6f-f1d4-11d2-9a0c-0090273fc1fd");
+    pub const RNG_PROTOCOL: GUID = guid!("3152bca5-eade-433d-862e-c01cdc291f44");
+
+    // File info types
+    pub const FILE_INFO: GUID = guid!("09576e92-6d3f-11d2-8e39-00a0c969723b");
+    pub const FILE_SYSTEM_INFO: GUID = guid!("09576e93-6d3f-11d2-8e39-00a0c969723b");
+
+    // Variable namespaces
+    pub const GLOBAL_VARIABLE: GUID = guid!("8be4df61-93ca-11d2-aa0d-00e098032b8c");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    #[test]
+    fn test_parse_fields() {
+        let guid = known::LOADED_IMAGE_PROTOCOL;
+        assert_eq!(guid.data_1, 0x5b1b31a1);
+        assert_eq!(guid.data_2, 0x9562);
+        assert_eq!(guid.data_3, 0x11d2);
+        assert_eq!(
+            guid.data_4,
+            [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b]
+        );
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let s = "8868e871-e4f1-11d3-bc22-0080c73c8881";
+        let guid: GUID = s.parse().unwrap();
+        assert_eq!(guid, known::ACPI_20_TABLE);
+        assert_eq!(format!("{guid}"), s);
+        assert_eq!(format!("{guid:?}"), format!("GUID({s})"));
+    }
+
+    #[test]
+    fn test_uppercase_accepted() {
+        let guid: GUID = "F2FD1544-9794-4A2C-992E-E5BBCF20E394".parse().unwrap();
+        assert_eq!(guid, known::SMBIOS3_TABLE);
+    }
+
+    #[test]
+    fn test_byte_layout_is_mixed_endian() {
+        let bytes = known::GLOBAL_VARIABLE.to_bytes();
+        assert_eq!(
+            &bytes[..8],
+            &[0x61, 0xdf, 0xe4, 0x8b, 0xca, 0x93, 0xd2, 0x11]
+        );
+        assert_eq!(
+            &bytes[8..],
+            &[0xaa, 0x0d, 0x00, 0xe0, 0x98, 0x03, 0x2b, 0x8c]
+        );
+        assert_eq!(GUID::from_bytes(bytes), known::GLOBAL_VARIABLE);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            GUID::try_parse("1234"),
+            Err(GUIDParseError::InvalidLength(4))
+        );
+        assert_eq!(
+            GUID::try_parse("8be4df61+93ca-11d2-aa0d-00e098032b8c"),
+            Err(GUIDParseError::InvalidSeparator(8))
+        );
+        assert_eq!(
+            GUID::try_parse("8be4df6g-93ca-11d2-aa0d-00e098032b8c"),
+            Err(GUIDParseError::InvalidDigit(7))
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use core::ptr;
+use core::sync::atomic::Ordering;
+
+use crate::graphics::Framebuffer;
+use crate::log::{LOG_BUFFER_LEN, LogBuffer, ring_parts};
+use crate::memory_map::MemoryMap;
+use crate::{
+    BS, EFIBootServices, EFILoadedImageProtocol, EFIRuntimeServices, EFIStatus, EFISystemTable, LIP,
+};
+
+// The map key goes stale whenever the firmware touches the map between the last
+// get_memory_map and exit_boot_services, a timer callback is enough to do it.
+const MAX_EXIT_ATTEMPTS: usize = 8;
+
+/// What is left once boot services are gone.
+pub struct RuntimeContext {
+    pub memory_map: MemoryMap,
+    pub system_table: *mut EFISystemTable,
+    pub runtime_services: *mut EFIRuntimeServices,
+}
+
+impl RuntimeContext {
+    pub fn runtime_services(&self) -> &EFIRuntimeServices {
+        unsafe { &*self.runtime_services }
+    }
+}
+
+/// What the loader hands the kernel, a pointer to it is the entry point's only argument. It
+/// lives in loader data, which the kernel may reclaim once it has copied out what it needs.
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct BootInfo {
+    /// The final memory map, descriptors are `desc_size` bytes apart.
+    pub memory_map: *const u8,
+    pub memory_map_size: usize,
+    pub desc_size: usize,
+    /// `base` is 0 when there is no linear framebuffer.
+    pub framebuffer: Framebuffer,
+    /// Physical address of the RSDP, 0 without ACPI.
+    pub rsdp: u64,
+    /// Only runtime services are still usable through it.
+    pub system_table: *mut EFISystemTable,
+    /// The kernel command line in UTF-8, not null terminated.
+    pub cmdline: *const u8,
+    pub cmdline_len: usize,
+    /// The loader's log ring, `log_buffer_len` bytes in the loader image, null without one.
+    /// `log_written` bytes went into it in total, see [`BootInfo::log`].
+    pub log_buffer: *const u8,
+    pub log_buffer_len: usize,
+    pub log_written: u64,
+}
+
+impl BootInfo {
+    /// Points the map fields at `ctx.memory_map`, which has to stay alive for the kernel.
+    pub fn set_runtime_context(&mut self, ctx: &RuntimeContext) {
+        self.memory_map = ctx.memory_map.as_bytes().as_ptr();
+        self.memory_map_size = ctx.memory_map.as_bytes().len();
+        self.desc_size = ctx.memory_map.meta().desc_size;
+        self.system_table = ctx.system_table;
+    }
+
+    /// Hands over `buffer` as it is now. Call it last thing before jumping to the kernel, so
+    /// the records about exiting boot services make it too.
+    pub fn set_log_buffer(&mut self, buffer: &LogBuffer) {
+        self.log_buffer = buffer.as_ptr();
+        self.log_buffer_len = LOG_BUFFER_LEN;
+        self.log_written = buffer.written();
+    }
+
+    /// The loader's log records, oldest first, split where the ring wraps.
+    ///
+    /// # Safety
+    ///
+    /// The log fields have to be null or describe memory that is still mapped and has not been
+    /// reused, the loader image is ordinary loader code to the memory map.
+    pub unsafe fn log(&self) -> (&[u8], &[u8]) {
+        if self.log_buffer.is_null() {
+            return (&[], &[]);
+        }
+        let data = unsafe { core::slice::from_raw_parts(self.log_buffer, self.log_buffer_len) };
+        ring_parts(data, self.log_written)
+    }
+}
+
+/// Leaves boot services for good, handing back the final memory map.
+///
+/// Once this returns `Ok`, `EFIBootServices::fetch_global` returns `None` and
+/// [`UefiAllocator`](crate::allocator::UefiAllocator) stops handing out memory. Anything still
+/// allocated (including the returned memory map) is left in place as loader data. Calling it a
+/// second time returns `EFIStatus::UNSUPPORTED`.
+pub fn exit_boot_services() -> Result<RuntimeContext, EFIStatus> {
+    let bs = EFIBootServices::fetch_global().ok_or(EFIStatus::UNSUPPORTED)?;
+    let st = EFISystemTable::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+    let image = EFILoadedImageProtocol::global_image_handle().ok_or(EFIStatus::NOT_READY)?;
+
+    // The only allocation in here, after the first exit attempt the pool is off limits.
+    let mut memory_map = MemoryMap::snapshot(bs)?;
+    let mut attempts = 0;
+    loop {
+        match unsafe { bs.exit_boot_services(image, memory_map.map_key()) } {
+            Ok(()) => break,
+            Err(EFIStatus::INVALID_PARAMETER) if attempts < MAX_EXIT_ATTEMPTS => {
+                attempts += 1;
+                memory_map.refresh(bs)?;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    BS.store(ptr::null_mut(), Ordering::Release);
+    // The loaded image protocol lives in boot services memory.
+    LIP.store(ptr::null_mut(), Ordering::Release);
+
+    Ok(RuntimeContext {
+        memory_map,
+        system_table: ptr::from_ref(st).cast_mut(),
+        runtime_services: st.runtime_services,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockFirmware, with_state};
+
+    #[test]
+    fn test_retries_stale_map_key() {
+        let _fw = MockFirmware::install_with(|s| s.stale_exits = 2);
+        let ctx = exit_boot_services().unwrap();
+        with_state(|s| {
+            assert!(s.exited);
+            assert_eq!(s.exit_attempts, 3);
+            assert_eq!(ctx.memory_map.map_key(), s.map_key);
+        });
+        assert_eq!(ctx.memory_map.len(), 4);
+        assert!(EFIBootServices::fetch_global().is_none());
+        assert_eq!(exit_boot_services().err(), Some(EFIStatus::UNSUPPORTED));
+    }
+
+    #[test]
+    fn test_gives_up_after_max_attempts() {
+        let _fw = MockFirmware::install_with(|s| s.stale_exits = usize::MAX);
+        assert_eq!(
+            exit_boot_services().err(),
+            Some(EFIStatus::INVALID_PARAMETER)
+        );
+        assert!(EFIBootServices::fetch_global().is_some());
+    }
+}
@@ -0,0 +1,204 @@
+//! Loading and starting other UEFI images: shells, bootloaders, diagnostic tools.
+//!
+//! ```ignore
+//! let path = boot_volume_file_path("\\EFI\\tools\\shell.efi")?;
+//! let mut image = bs.load_image_from_path(&path.as_path())?;
+//! image.set_load_options("shell.efi -nostartup")?;
+//! let exit = image.start();
+//! ```
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ptr;
+
+use crate::device_path::{DevicePath, DevicePathBuf};
+use crate::protocol::ScopedProtocol;
+use crate::{EFIBootServices, EFIDevicePath, EFILoadedImageProtocol, EFIStatus, Handle};
+
+/// How a started image came back.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ImageExit {
+    pub status: EFIStatus,
+    /// The string at the start of the exit data, images usually leave it out on success.
+    pub exit_data: Option<String>,
+}
+
+impl ImageExit {
+    /// Decodes and frees the exit data handed back by `start_image`.
+    ///
+    /// # Safety
+    /// `data` must be null or a pool allocation of `size` bytes.
+    pub(crate) unsafe fn from_raw(
+        bs: &EFIBootServices,
+        status: EFIStatus,
+        size: usize,
+        data: *mut u16,
+    ) -> Self {
+        if data.is_null() {
+            return Self {
+                status,
+                exit_data: None,
+            };
+        }
+        let units = unsafe { core::slice::from_raw_parts(data, size / 2) };
+        // A null terminated string, optionally followed by binary data.
+        let text = units.split(|c| *c == 0).next().unwrap_or(&[]);
+        let exit_data = char::decode_utf16(text.iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        let _ = bs.free_pool(data.cast());
+        Self {
+            status,
+            exit_data: Some(exit_data),
+        }
+    }
+
+    pub fn to_result(&self) -> Result<(), EFIStatus> {
+        self.status.to_result()
+    }
+}
+
+/// An image that was loaded but not started yet, unloaded on drop.
+pub struct LoadedImage<'a> {
+    bs: &'a EFIBootServices,
+    handle: Handle,
+    // Has to stay put until the child is done with it.
+    load_options: Option<Vec<u16>>,
+}
+
+impl<'a> LoadedImage<'a> {
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    /// The child's loaded image protocol, e.g. to check `image_code_type` before starting.
+    pub fn info(&self) -> Result<ScopedProtocol<'a, EFILoadedImageProtocol>, EFIStatus> {
+        self.bs.open_protocol::<EFILoadedImageProtocol>(self.handle)
+    }
+
+    /// Hands `cmdline` to the child as a null terminated UTF-16 string. By convention the
+    /// first word is the image name, the way the UEFI shell passes arguments.
+    pub fn set_load_options(&mut self, cmdline: &str) -> Result<(), EFIStatus> {
+        let options: Vec<u16> = cmdline.encode_utf16().chain([0]).collect();
+        let mut info = self.info()?;
+        info.load_options = options.as_ptr().cast_mut().cast();
+        info.load_options_size = (options.len() * 2) as u32;
+        self.load_options = Some(options);
+        Ok(())
+    }
+
+    /// Runs the image until it exits. Applications are unloaded by the firmware once they
+    /// return, drivers stay resident.
+    pub fn start(mut self) -> ImageExit {
+        let handle = self.handle;
+        // The firmware owns the image from here on.
+        self.handle = Handle::null();
+        let exit = self.bs.start_image(handle);
+        // Nobody reads the options after the child is done.
+        drop(self.load_options.take());
+        exit
+    }
+}
+
+impl Drop for LoadedImage<'_> {
+    fn drop(&mut self) {
+        if self.handle.is_null() || EFIBootServices::fetch_global().is_none() {
+            return;
+        }
+        let _ = self.bs.unload_image(self.handle);
+    }
+}
+
+impl EFIBootServices {
+    /// Loads the image at `path`, which has to name the device as well as the file, see
+    /// [`boot_volume_file_path`].
+    pub fn load_image_from_path(&self, path: &DevicePath) -> Result<LoadedImage<'_>, EFIStatus> {
+        self.load_child(path.as_ptr(), None)
+    }
+
+    /// Loads an image that is already in memory, e.g. read with `fs::read_file_to_vec`.
+    pub fn load_image_from_buffer(&self, image: &[u8]) -> Result<LoadedImage<'_>, EFIStatus> {
+        self.load_child(ptr::null(), Some(image))
+    }
+
+    fn load_child(
+        &self,
+        path: *const EFIDevicePath,
+        source: Option<&[u8]>,
+    ) -> Result<LoadedImage<'_>, EFIStatus> {
+        let parent = EFILoadedImageProtocol::global_image_handle().ok_or(EFIStatus::NOT_READY)?;
+        let handle = unsafe { self.load_image(false, parent, path, source) }?;
+        Ok(LoadedImage {
+            bs: self,
+            handle,
+            load_options: None,
+        })
+    }
+}
+
+/// Full device path of `file` on the volume the running image was loaded from.
+pub fn boot_volume_file_path(file: &str) -> Result<DevicePathBuf, EFIStatus> {
+    let bs = EFIBootServices::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+    let image = EFILoadedImageProtocol::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+    let device = bs.open_protocol::<EFIDevicePath>(image.device_handle)?;
+    let device =
+        unsafe { DevicePath::from_ptr(device.as_ptr()) }.ok_or(EFIStatus::VOLUME_CORRUPTED)?;
+    let mut path = device.to_path_buf();
+    path.push_file_path(file);
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockFirmware, with_state};
+    use alloc::string::ToString;
+
+    fn bs() -> &'static EFIBootServices {
+        EFIBootServices::fetch_global().unwrap()
+    }
+
+    #[test]
+    fn test_chainload_from_path_with_options() {
+        let _fw = MockFirmware::install_with(|state| {
+            state.add_file("\\EFI\\tools\\shell.efi", b"MZ echo");
+        });
+        let path = boot_volume_file_path("\\EFI\\tools\\shell.efi").unwrap();
+        assert!(
+            path.to_string()
+                .starts_with("PciRoot(0x0)/Pci(0x1f,0x2)/Sata(0x0,0xffff,0x0)/HD(1,GPT,")
+        );
+        let mut image = bs().load_image_from_path(&path.as_path()).unwrap();
+        assert_eq!(image.info().unwrap().parent_handle, crate::mock::IMAGE);
+        image.set_load_options("shell.efi -nostartup").unwrap();
+        let exit = image.start();
+        assert_eq!(exit.to_result(), Ok(()));
+        // The mock echo image hands its load options back as exit data.
+        assert_eq!(exit.exit_data.as_deref(), Some("shell.efi -nostartup"));
+        assert!(with_state(|s| s.pool.is_empty() && s.images.is_empty()));
+
+        let missing = boot_volume_file_path("\\EFI\\nope.efi").unwrap();
+        assert_eq!(
+            bs().load_image_from_path(&missing.as_path()).err(),
+            Some(EFIStatus::NOT_FOUND)
+        );
+    }
+
+    #[test]
+    fn test_buffer_image_exit_status_and_unload() {
+        let _fw = MockFirmware::install();
+        let exit = bs().load_image_from_buffer(b"MZ fail").unwrap().start();
+        assert_eq!(exit.status, EFIStatus::ABORTED);
+        assert_eq!(exit.exit_data.as_deref(), Some("diagnostics failed"));
+
+        // Dropped without starting.
+        let image = bs().load_image_from_buffer(b"MZ echo").unwrap();
+        assert_eq!(with_state(|s| s.images.len()), 1);
+        drop(image);
+        assert!(with_state(|s| s.images.is_empty()));
+
+        assert_eq!(
+            bs().load_image_from_buffer(b"not a PE file").err(),
+            Some(EFIStatus::LOAD_ERROR)
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use crate::console::Console;
+use crate::{EFIBootServices, EFIInputKey, EFIStatus, EFISystemTable, SimpleInputInterface};
+
+const CHAR_BACKSPACE: char = '\u{8}';
+const CHAR_CARRIAGE_RETURN: char = '\r';
+const CHAR_LINEFEED: char = '\n';
+
+const HISTORY_LEN: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ScanCode {
+    Up,
+    Down,
+    Right,
+    Left,
+    Home,
+    End,
+    Insert,
+    Delete,
+    PageUp,
+    PageDown,
+    /// F1 through F12.
+    Function(u8),
+    Escape,
+    Unknown(u16),
+}
+
+impl From<u16> for ScanCode {
+    fn from(value: u16) -> Self {
+        match value {
+            0x01 => ScanCode::Up,
+            0x02 => ScanCode::Down,
+            0x03 => ScanCode::Right,
+            0x04 => ScanCode::Left,
+            0x05 => ScanCode::Home,
+            0x06 => ScanCode::End,
+            0x07 => ScanCode::Insert,
+            0x08 => ScanCode::Delete,
+            0x09 => ScanCode::PageUp,
+            0x0a => ScanCode::PageDown,
+            0x0b..=0x16 => ScanCode::Function((value - 0x0a) as u8),
+            0x17 => ScanCode::Escape,
+            other => ScanCode::Unknown(other),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Key {
+    Char(char),
+    Special(ScanCode),
+}
+
+impl From<EFIInputKey> for Key {
+    /// A zero `unicode_char` means the key is only described by its scan code.
+    fn from(key: EFIInputKey) -> Self {
+        match key.unicode_char {
+            0 => Key::Special(ScanCode::from(key.scan_code)),
+            c => Key::Char(char::from_u32(c as u32).unwrap_or(char::REPLACEMENT_CHARACTER)),
+        }
+    }
+}
+
+pub(crate) fn stdin() -> Result<&'static mut SimpleInputInterface, EFIStatus> {
+    EFIBootServices::fetch_global().ok_or(EFIStatus::UNSUPPORTED)?;
+    let st = EFISystemTable::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+    unsafe { st.stdin.as_mut() }.ok_or(EFIStatus::NOT_READY)
+}
+
+/// Returns `Ok(None)` right away when no key is waiting.
+pub fn try_read_key() -> Result<Option<Key>, EFIStatus> {
+    Ok(stdin()?.read_key_stroke()?.map(Key::from))
+}
+
+/// Blocks on the `wait_for_key` event until a key is available.
+pub fn read_key() -> Result<Key, EFIStatus> {
+    let bs = EFIBootServices::fetch_global().ok_or(EFIStatus::UNSUPPORTED)?;
+    loop {
+        let stdin = stdin()?;
+        if let Some(key) = stdin.read_key_stroke()? {
+            return Ok(Key::from(key));
+        }
+        bs.wait_for_event(&[stdin.wait_for_key])?;
+    }
+}
+
+/// Line editor state, keep it around between `read_line` calls to get history.
+#[derive(Default)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    history: VecDeque<String>,
+    // None while editing a fresh line, otherwise the history entry being shown.
+    history_index: Option<usize>,
+    draft: Vec<char>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn history(&self) -> impl Iterator<Item = &str> {
+        self.history.iter().map(String::as_str)
+    }
+
+    pub fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    fn backspaces(echo: &mut impl Write, count: usize) -> fmt::Result {
+        for _ in 0..count {
+            echo.write_char(CHAR_BACKSPACE)?;
+        }
+        Ok(())
+    }
+
+    // Redraws from the cursor to the end of the line, blanking `erase` stale cells, and puts the
+    // cursor back.
+    fn redraw_tail(&self, echo: &mut impl Write, erase: usize) -> fmt::Result {
+        for c in &self.line[self.cursor..] {
+            echo.write_char(*c)?;
+        }
+        for _ in 0..erase {
+            echo.write_char(' ')?;
+        }
+        Self::backspaces(echo, self.line.len() - self.cursor + erase)
+    }
+
+    fn replace_line(&mut self, new: Vec<char>, echo: &mut impl Write) -> fmt::Result {
+        Self::backspaces(echo, self.cursor)?;
+        let old_len = self.line.len();
+        self.line = new;
+        self.cursor = 0;
+        self.redraw_tail(echo, old_len.saturating_sub(self.line.len()))?;
+        self.move_to(self.line.len(), echo)
+    }
+
+    fn move_to(&mut self, pos: usize, echo: &mut impl Write) -> fmt::Result {
+        if pos < self.cursor {
+            Self::backspaces(echo, self.cursor - pos)?;
+        } else {
+            for c in &self.line[self.cursor..pos] {
+                echo.write_char(*c)?;
+            }
+        }
+        self.cursor = pos;
+        Ok(())
+    }
+
+    fn show_history(&mut self, index: Option<usize>, echo: &mut impl Write) -> fmt::Result {
+        if self.history_index.is_none() {
+            self.draft = self.line.clone();
+        }
+        self.history_index = index;
+        let new = match index {
+            Some(i) => self.history[i].chars().collect(),
+            None => core::mem::take(&mut self.draft),
+        };
+        self.replace_line(new, echo)
+    }
+
+    fn finish(&mut self) -> String {
+        let line = self.line();
+        if !line.is_empty() && self.history.back() != Some(&line) {
+            if self.history.len() == HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back(line.clone());
+        }
+        self.line.clear();
+        self.draft.clear();
+        self.cursor = 0;
+        self.history_index = None;
+        line
+    }
+
+    /// Applies one key press, echoing the screen update to `echo`. Returns the finished line on
+    /// Enter.
+    pub fn apply(&mut self, key: Key, echo: &mut impl Write) -> Result<Option<String>, fmt::Error> {
+        match key {
+            Key::Char(CHAR_CARRIAGE_RETURN | CHAR_LINEFEED) => {
+                echo.write_char(CHAR_LINEFEED)?;
+                return Ok(Some(self.finish()));
+            }
+            Key::Char(CHAR_BACKSPACE) => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                    echo.write_char(CHAR_BACKSPACE)?;
+                    self.redraw_tail(echo, 1)?;
+                }
+            }
+            Key::Char(c) if !c.is_control() => {
+                self.line.insert(self.cursor, c);
+                self.cursor += 1;
+                echo.write_char(c)?;
+                self.redraw_tail(echo, 0)?;
+            }
+            Key::Char(_) => {}
+            Key::Special(ScanCode::Delete) => {
+                if self.cursor < self.line.len() {
+                    self.line.remove(self.cursor);
+                    self.redraw_tail(echo, 1)?;
+                }
+            }
+            Key::Special(ScanCode::Left) if self.cursor > 0 => {
+                self.move_to(self.cursor - 1, echo)?
+            }
+            Key::Special(ScanCode::Right) if self.cursor < self.line.len() => {
+                self.move_to(self.cursor + 1, echo)?
+            }
+            Key::Special(ScanCode::Home) => self.move_to(0, echo)?,
+            Key::Special(ScanCode::End) => self.move_to(self.line.len(), echo)?,
+            Key::Special(ScanCode::Up) => {
+                let index = match self.history_index {
+                    Some(0) => None,
+                    Some(i) => Some(i - 1),
+                    None => self.history.len().checked_sub(1),
+                };
+                if let Some(index) = index {
+                    self.show_history(Some(index), echo)?;
+                }
+            }
+            Key::Special(ScanCode::Down) => {
+                if let Some(i) = self.history_index {
+                    let next = Some(i + 1).filter(|next| *next < self.history.len());
+                    self.show_history(next, echo)?;
+                }
+            }
+            Key::Special(_) => {}
+        }
+        Ok(None)
+    }
+}
+
+/// Reads a line from stdin with echo on stdout.
+pub fn read_line(editor: &mut LineEditor) -> Result<String, EFIStatus> {
+    let mut echo = Console::stdout();
+    loop {
+        let key = read_key()?;
+        if let Some(line) = editor
+            .apply(key, &mut echo)
+            .map_err(|_| EFIStatus::DEVICE_ERROR)?
+        {
+            return Ok(line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_str(editor: &mut LineEditor, s: &str, echo: &mut String) -> Option<String> {
+        let mut out = None;
+        for c in s.chars() {
+            out = editor.apply(Key::Char(c), echo).unwrap();
+        }
+        out
+    }
+
+    #[test]
+    fn test_scan_codes() {
+        assert_eq!(ScanCode::from(0x01), ScanCode::Up);
+        assert_eq!(ScanCode::from(0x0b), ScanCode::Function(1));
+        assert_eq!(ScanCode::from(0x16), ScanCode::Function(12));
+        assert_eq!(ScanCode::from(0x17), ScanCode::Escape);
+        assert_eq!(ScanCode::from(0x48), ScanCode::Unknown(0x48));
+    }
+
+    #[test]
+    fn test_key_from_input_key() {
+        let key = EFIInputKey {
+            scan_code: 0,
+            unicode_char: 'x' as u16,
+        };
+        assert_eq!(Key::from(key), Key::Char('x'));
+        let key = EFIInputKey {
+            scan_code: 0x05,
+            unicode_char: 0,
+        };
+        assert_eq!(Key::from(key), Key::Special(ScanCode::Home));
+    }
+
+    #[test]
+    fn test_insert_and_enter() {
+        let mut editor = LineEditor::new();
+        let mut echo = String::new();
+        assert_eq!(
+            type_str(&mut editor, "boot\r", &mut echo),
+            Some("boot".into())
+        );
+        assert_eq!(echo, "boot\n");
+        assert_eq!(editor.line(), "");
+    }
+
+    #[test]
+    fn test_cursor_movement_and_backspace() {
+        let mut editor = LineEditor::new();
+        let mut echo = String::new();
+        type_str(&mut editor, "abd", &mut echo);
+        editor
+            .apply(Key::Special(ScanCode::Left), &mut echo)
+            .unwrap();
+        type_str(&mut editor, "c", &mut echo);
+        assert_eq!(editor.line(), "abcd");
+        assert_eq!(editor.cursor(), 3);
+
+        editor
+            .apply(Key::Special(ScanCode::Home), &mut echo)
+            .unwrap();
+        editor
+            .apply(Key::Special(ScanCode::Delete), &mut echo)
+            .unwrap();
+        editor
+            .apply(Key::Special(ScanCode::End), &mut echo)
+            .unwrap();
+        type_str(&mut editor, "\u{8}", &mut echo);
+        assert_eq!(editor.line(), "bc");
+        assert_eq!(editor.cursor(), 2);
+    }
+
+    #[test]
+    fn test_history() {
+        let mut editor = LineEditor::new();
+        let mut echo = String::new();
+        type_str(&mut editor, "one\r", &mut echo);
+        type_str(&mut editor, "two\r", &mut echo);
+        type_str(&mut editor, "dra", &mut echo);
+
+        editor.apply(Key::Special(ScanCode::Up), &mut echo).unwrap();
+        assert_eq!(editor.line(), "two");
+        editor.apply(Key::Special(ScanCode::Up), &mut echo).unwrap();
+        assert_eq!(editor.line(), "one");
+        editor.apply(Key::Special(ScanCode::Up), &mut echo).unwrap();
+        assert_eq!(editor.line(), "one");
+        editor
+            .apply(Key::Special(ScanCode::Down), &mut echo)
+            .unwrap();
+        editor
+            .apply(Key::Special(ScanCode::Down), &mut echo)
+            .unwrap();
+        assert_eq!(editor.line(), "dra");
+        assert_eq!(editor.cursor(), 3);
+
+        let history: Vec<_> = editor.history().collect();
+        assert_eq!(history, ["one", "two"]);
+    }
+
+    #[test]
+    fn test_read_line_from_firmware() {
+        let fw = crate::mock::MockFirmware::install();
+        fw.push_keys("ls");
+        fw.push_scan_code(0x04);
+        fw.push_keys("x\r");
+        let mut editor = LineEditor::new();
+        assert_eq!(read_line(&mut editor).unwrap(), "lxs");
+        assert!(fw.stdout().ends_with("\r\n"));
+        assert_eq!(try_read_key(), Ok(None));
+        // Nothing scripted, the mock reports NOT_READY instead of blocking.
+        assert_eq!(read_key(), Err(EFIStatus::NOT_READY));
+    }
+}
@@ -1,5 +1,37 @@
 #![no_std]
+extern crate alloc;
+
+pub mod acpi;
+pub mod allocator;
+pub mod args;
+pub mod console;
+pub mod device_path;
+pub mod elf;
+pub mod event;
+pub mod executor;
+mod flags;
+pub mod fs;
+pub mod graphics;
+pub mod guid;
+pub mod handoff;
+pub mod image;
+pub mod input;
+pub mod log;
+pub mod memory_map;
+#[cfg(test)]
+mod mock;
+pub mod protocol;
+pub mod reset;
+pub mod serial;
+pub mod smbios;
+pub mod status;
+pub mod time;
+pub mod variable;
+
 use core::ffi::c_void;
+pub use guid::GUID;
+pub use handoff::{RuntimeContext, exit_boot_services};
+pub use status::EFIStatus;
 pub type Wchar = u16;
 
 #[repr(C)]
@@ -9,21 +41,50 @@ pub struct EFIDevicePath {
     len: [u8; 2],
 }
 
-#[repr(C)]
-pub struct EFIOpenProtocolInfoEntry {
-    agenct_handle: *mut c_void,
-    controller_handle: *mut c_void,
-    attributes: u32,
-    open_count: u32,
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[repr(transparent)]
+pub struct Handle(pub *mut c_void);
+
+impl Handle {
+    pub const fn null() -> Self {
+        Self(core::ptr::null_mut())
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0.is_null()
+    }
+
+    pub const fn as_ptr(&self) -> *mut c_void {
+        self.0
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[repr(transparent)]
+pub struct Event(pub *mut c_void);
+
+impl Event {
+    pub const fn null() -> Self {
+        Self(core::ptr::null_mut())
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0.is_null()
+    }
+
+    pub const fn as_ptr(&self) -> *mut c_void {
+        self.0
+    }
+}
+
+pub type EFIEventNotify = unsafe extern "efiapi" fn(event: Event, context: *mut c_void);
+
 #[repr(C)]
-pub struct GUID {
-    data_1: u32,
-    data_2: u16,
-    data_3: u16,
-    data_4: [u8; 8],
+pub struct EFIOpenProtocolInfoEntry {
+    pub agent_handle: Handle,
+    pub controller_handle: Handle,
+    pub attributes: u32,
+    pub open_count: u32,
 }
 
 #[repr(C)]
@@ -44,11 +105,12 @@ pub enum EFIAllocateType {
     MaxAllocateType,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C)]
 pub enum EFIMemoryType {
     EfiReservedMemoryType,
     EfiLoaderCode,
+    #[default]
     EfiLoaderData,
     EfiBootServicesCode,
     EfiBootServicesData,
@@ -66,9 +128,32 @@ pub enum EFIMemoryType {
     EfiMaxMemoryType,
 }
 
-impl Default for EFIMemoryType {
-    fn default() -> Self {
-        EFIMemoryType::EfiLoaderData
+impl TryFrom<u32> for EFIMemoryType {
+    type Error = u32;
+
+    /// OEM and OS loader defined types (`0x7000_0000` and up) have no variant and are
+    /// returned as the error.
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        use EFIMemoryType::*;
+        const TYPES: [EFIMemoryType; 16] = [
+            EfiReservedMemoryType,
+            EfiLoaderCode,
+            EfiLoaderData,
+            EfiBootServicesCode,
+            EfiBootServicesData,
+            EfiRuntimeServicesCode,
+            EfiRuntimeServicesData,
+            EfiConventionalMemory,
+            EfiUnusableMemory,
+            EfiACPIReclaimMemory,
+            EfiACPIMemoryNVS,
+            EfiMemoryMappedIO,
+            EfiMemoryMappedIOPortSpace,
+            EfiPalCode,
+            EfiPersistentMemory,
+            EfiUnacceptedMemoryType,
+        ];
+        TYPES.get(value as usize).copied().ok_or(value)
     }
 }
 
@@ -90,9 +175,9 @@ pub struct TableHeader {
 #[repr(C)]
 pub struct EFILoadedImageProtocol {
     pub revision: u32,
-    pub parent_handle: u64,
+    pub parent_handle: Handle,
     pub system_table: *mut c_void,
-    pub device_handle: u64,
+    pub device_handle: Handle,
     pub file_path: *mut EFIDevicePath,
     pub reserved: *mut c_void,
     pub load_options_size: u32,
@@ -103,27 +188,40 @@ pub struct EFILoadedImageProtocol {
     pub image_data_type: u32,
 }
 use core::sync::atomic::{AtomicPtr, Ordering};
-static LIP: AtomicPtr<c_void> = AtomicPtr::new(core::ptr::null_mut());
+static LIP: AtomicPtr<EFILoadedImageProtocol> = AtomicPtr::new(core::ptr::null_mut());
+static IMAGE_HANDLE: AtomicPtr<c_void> = AtomicPtr::new(core::ptr::null_mut());
 static ST: AtomicPtr<EFISystemTable> = AtomicPtr::new(core::ptr::null_mut());
 static BS: AtomicPtr<EFIBootServices> = AtomicPtr::new(core::ptr::null_mut());
 
 impl EFILoadedImageProtocol {
-    pub unsafe fn from_image_handle(image_handle: *mut c_void) {
-        LIP.store(image_handle, Ordering::Release);
+    /// # Safety
+    /// `image_handle` must be the image handle passed to `efi_main`.
+    pub unsafe fn from_image_handle(image_handle: Handle) {
+        IMAGE_HANDLE.store(image_handle.as_ptr(), Ordering::Release);
+        LIP.store(core::ptr::null_mut(), Ordering::Release);
     }
 
+    pub fn global_image_handle() -> Option<Handle> {
+        let handle = Handle(IMAGE_HANDLE.load(Ordering::Acquire));
+        if handle.is_null() { None } else { Some(handle) }
+    }
+
+    /// Looks the protocol up on the global image handle the first time through, which needs
+    /// both `from_image_handle` and `EFISystemTable::set_system_table` to have run.
     pub fn fetch_global() -> Option<&'static Self> {
         let ptr = LIP.load(Ordering::Acquire);
-        if ptr.is_null() {
-            None
-        } else {
-            let p_self = ptr as *mut Self;
-            Some(unsafe { &*p_self })
+        if !ptr.is_null() {
+            return Some(unsafe { &*ptr });
         }
+
+        let bs = EFIBootServices::fetch_global()?;
+        let lip = bs
+            .open_protocol::<Self>(Self::global_image_handle()?)
+            .ok()?
+            .leak();
+        LIP.store(lip, Ordering::Release);
+        Some(lip)
     }
-    // pub fn from_image_handle(handle: *mut c_void) -> Option<&'static Self> {
-    //     unsafe { LIP.as_ref() }
-    // }
 
     pub fn global_image_data_type() -> u32 {
         match Self::fetch_global() {
@@ -132,17 +230,34 @@ impl EFILoadedImageProtocol {
         }
     }
 }
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub struct EFIInputKey {
-    scan_code: u16,
-    unicode_char: Wchar,
+    pub scan_code: u16,
+    pub unicode_char: Wchar,
 }
 
 #[repr(C)]
 pub struct SimpleInputInterface {
-    reset: unsafe extern "efiapi" fn(this: *mut c_void, u8) -> u64,
-    read_key_stroke: unsafe extern "efiapi" fn(this: *mut c_void, key: *mut EFIInputKey) -> u64,
-    wait_for_key: *mut c_void,
+    pub reset: unsafe extern "efiapi" fn(this: *mut Self, extended_verification: u8) -> EFIStatus,
+    pub read_key_stroke:
+        unsafe extern "efiapi" fn(this: *mut Self, key: *mut EFIInputKey) -> EFIStatus,
+    pub wait_for_key: Event,
+}
+
+impl SimpleInputInterface {
+    pub fn reset(&mut self, extended_verification: bool) -> Result<(), EFIStatus> {
+        unsafe { (self.reset)(self, extended_verification as u8) }.to_result()
+    }
+
+    /// `Ok(None)` when no key is waiting.
+    pub fn read_key_stroke(&mut self) -> Result<Option<EFIInputKey>, EFIStatus> {
+        let mut key = EFIInputKey::default();
+        match unsafe { (self.read_key_stroke)(self, &mut key) } {
+            EFIStatus::NOT_READY => Ok(None),
+            status => status.to_result_with(Some(key)),
+        }
+    }
 }
 
 #[repr(C)]
@@ -157,26 +272,69 @@ pub struct SimpleTextOutputMode {
 
 #[repr(C)]
 pub struct SimpleTextOutputInterface {
-    pub reset: unsafe extern "efiapi" fn(this: *mut Self, extended_verification: u8) -> u64,
-    pub output_string: unsafe extern "efiapi" fn(this: *mut Self, string: *mut Wchar) -> u64,
-    pub test_string: unsafe extern "efiapi" fn(this: *mut Self, string: *mut Wchar) -> u64,
-    pub query_mode: unsafe extern "efiapi" fn(this: *mut Self, mode_number: u64) -> u64,
-    pub set_mode: unsafe extern "efiapi" fn(this: *mut Self, mode_number: u64) -> u64,
-    pub set_attribute: unsafe extern "efiapi" fn(this: *mut Self, attribute: u64) -> u64,
-    pub clear_screen: unsafe extern "efiapi" fn(this: *mut Self) -> u64,
+    pub reset: unsafe extern "efiapi" fn(this: *mut Self, extended_verification: u8) -> EFIStatus,
+    pub output_string: unsafe extern "efiapi" fn(this: *mut Self, string: *mut Wchar) -> EFIStatus,
+    pub test_string: unsafe extern "efiapi" fn(this: *mut Self, string: *mut Wchar) -> EFIStatus,
+    pub query_mode: unsafe extern "efiapi" fn(
+        this: *mut Self,
+        mode_number: u64,
+        columns: *mut usize,
+        rows: *mut usize,
+    ) -> EFIStatus,
+    pub set_mode: unsafe extern "efiapi" fn(this: *mut Self, mode_number: u64) -> EFIStatus,
+    pub set_attribute: unsafe extern "efiapi" fn(this: *mut Self, attribute: u64) -> EFIStatus,
+    pub clear_screen: unsafe extern "efiapi" fn(this: *mut Self) -> EFIStatus,
     pub set_cursor_position:
-        unsafe extern "efiapi" fn(this: *mut Self, column: u64, row: u64) -> u64,
-    pub enable_cursor: unsafe extern "efiapi" fn(this: *mut Self, enable: u8) -> u64,
+        unsafe extern "efiapi" fn(this: *mut Self, column: u64, row: u64) -> EFIStatus,
+    pub enable_cursor: unsafe extern "efiapi" fn(this: *mut Self, enable: u8) -> EFIStatus,
     pub mode: *mut SimpleTextOutputMode,
 }
 
 impl SimpleTextOutputInterface {
-    pub fn output_string(&mut self, s: &[u16]) {
-        //TODO err handling
-        unsafe { (self.output_string)(self, s.as_ptr() as *mut Wchar) };
+    pub fn reset(&mut self, extended_verification: bool) -> Result<(), EFIStatus> {
+        unsafe { (self.reset)(self, extended_verification as u8) }.to_result()
+    }
+
+    /// `s` must be null terminated.
+    pub fn output_string(&mut self, s: &[u16]) -> Result<(), EFIStatus> {
+        unsafe { (self.output_string)(self, s.as_ptr() as *mut Wchar) }.to_result()
+    }
+
+    /// `s` must be null terminated.
+    pub fn test_string(&mut self, s: &[u16]) -> Result<(), EFIStatus> {
+        unsafe { (self.test_string)(self, s.as_ptr() as *mut Wchar) }.to_result()
+    }
+
+    /// Columns and rows of text mode `mode_number`, `UNSUPPORTED` for modes the current
+    /// display can't show.
+    pub fn query_mode(&mut self, mode_number: u64) -> Result<(usize, usize), EFIStatus> {
+        let (mut columns, mut rows) = (0, 0);
+        unsafe { (self.query_mode)(self, mode_number, &mut columns, &mut rows) }
+            .to_result_with((columns, rows))
+    }
+
+    pub fn set_mode(&mut self, mode_number: u64) -> Result<(), EFIStatus> {
+        unsafe { (self.set_mode)(self, mode_number) }.to_result()
+    }
+
+    pub fn set_attribute(&mut self, attribute: u64) -> Result<(), EFIStatus> {
+        unsafe { (self.set_attribute)(self, attribute) }.to_result()
+    }
+
+    pub fn clear_screen(&mut self) -> Result<(), EFIStatus> {
+        unsafe { (self.clear_screen)(self) }.to_result()
+    }
+
+    pub fn set_cursor_position(&mut self, column: u64, row: u64) -> Result<(), EFIStatus> {
+        unsafe { (self.set_cursor_position)(self, column, row) }.to_result()
+    }
+
+    pub fn enable_cursor(&mut self, enable: bool) -> Result<(), EFIStatus> {
+        unsafe { (self.enable_cursor)(self, enable as u8) }.to_result()
     }
 }
 
+#[derive(Clone, Copy, Default, Debug)]
 #[repr(C)]
 pub struct EFITimeCapabilities {
     pub resolution: u32,
@@ -184,6 +342,7 @@ pub struct EFITimeCapabilities {
     pub sets_to_zero: u8,
 }
 
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
 #[repr(C)]
 pub struct EFITime {
     pub year: u16,
@@ -199,11 +358,13 @@ pub struct EFITime {
     pub pad_2: u8,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(C)]
 pub enum EFIResetType {
     EfiResetCold,
     EfiResetWarm,
     EfiResetShutdown,
+    EfiResetPlatformSpecific,
 }
 
 #[repr(C)]
@@ -220,64 +381,144 @@ pub struct EFIRuntimeServices {
     pub get_time: unsafe extern "efiapi" fn(
         time: *mut EFITime,
         capabilities: *mut EFITimeCapabilities,
-    ) -> u64,
-    pub set_time: unsafe extern "efiapi" fn(time: *mut EFITime) -> u64,
-    pub get_wakeup_time:
-        unsafe extern "efiapi" fn(enable: *mut u8, pending: *mut u8, time: *mut EFITime) -> u64,
-    pub set_wakeup_time: unsafe extern "efiapi" fn(enable: u8, time: *mut EFITime) -> u64,
+    ) -> EFIStatus,
+    pub set_time: unsafe extern "efiapi" fn(time: *mut EFITime) -> EFIStatus,
+    pub get_wakeup_time: unsafe extern "efiapi" fn(
+        enable: *mut u8,
+        pending: *mut u8,
+        time: *mut EFITime,
+    ) -> EFIStatus,
+    pub set_wakeup_time: unsafe extern "efiapi" fn(enable: u8, time: *mut EFITime) -> EFIStatus,
     pub set_virtual_address_map: unsafe extern "efiapi" fn(
         memory_map_size: u64,
         descriptor_size: u64,
         descriptor_version: u32,
         virtual_map: *mut EFIMemoryDescriptor,
-    ) -> u64,
+    ) -> EFIStatus,
     pub convert_pointer:
-        unsafe extern "efiapi" fn(debug_disposition: u64, address: *mut *mut c_void) -> u64,
+        unsafe extern "efiapi" fn(debug_disposition: u64, address: *mut *mut c_void) -> EFIStatus,
     pub get_variable: unsafe extern "efiapi" fn(
-        variable_name: Wchar,
-        vendor_guid: *mut GUID,
+        variable_name: *const Wchar,
+        vendor_guid: *const GUID,
         attributes: *mut u32,
         data_size: *mut u64,
         data: *mut c_void,
-    ) -> u64,
+    ) -> EFIStatus,
     pub get_next_variable: unsafe extern "efiapi" fn(
         variable_name_size: *mut u64,
         variable_name: *mut Wchar,
         vendor_guid: *mut GUID,
-    ) -> u64,
+    ) -> EFIStatus,
     pub set_variable: unsafe extern "efiapi" fn(
         variable_name: *mut Wchar,
         vendor_guid: *mut GUID,
         attributes: u32,
         data_size: u64,
         data: *mut c_void,
-    ) -> u64,
-    pub get_next_high_mono: unsafe extern "efiapi" fn(count: *mut u64) -> u64,
+    ) -> EFIStatus,
+    pub get_next_high_mono: unsafe extern "efiapi" fn(count: *mut u64) -> EFIStatus,
     pub reset_system_type: unsafe extern "efiapi" fn(
         reset_type: EFIResetType,
         reset_status: u64,
         data_size: u64,
         reset_data: *mut Wchar,
-    ) -> u64,
+    ) -> EFIStatus,
     pub update_capsule: unsafe extern "efiapi" fn(
         capsule_header_array: *mut *mut EFICapsuleHeader,
         capsule_count: u64,
         scatter_gather_list: u64,
-    ) -> u64,
+    ) -> EFIStatus,
     pub query_capsule_capabilities: unsafe extern "efiapi" fn(
         capsule_header_array: *mut *mut EFICapsuleHeader,
         capsule_count: u64,
         maximum_capsule_size: u64,
         reset_type: *mut EFIResetType,
-    ) -> u64,
+    ) -> EFIStatus,
     pub query_variable_info: unsafe extern "efiapi" fn(
         attributes: u32,
         maximum_variable_storage_size: *mut u64,
         remaining_variable_storage_size: *mut u64,
         maximum_variable_size: *mut u64,
-    ) -> u64,
+    ) -> EFIStatus,
 }
 
+impl EFIRuntimeServices {
+    pub fn get_time(&self) -> Result<(EFITime, EFITimeCapabilities), EFIStatus> {
+        let mut time = EFITime::default();
+        let mut capabilities = EFITimeCapabilities::default();
+        unsafe { (self.get_time)(&mut time, &mut capabilities) }
+            .to_result_with((time, capabilities))
+    }
+
+    /// Rejects invalid times with `INVALID_PARAMETER` before they reach the firmware.
+    pub fn set_time(&self, time: &EFITime) -> Result<(), EFIStatus> {
+        if !time.is_valid() {
+            return Err(EFIStatus::INVALID_PARAMETER);
+        }
+        let mut time = *time;
+        unsafe { (self.set_time)(&mut time) }.to_result()
+    }
+
+    /// Returns `(enabled, pending, time)`.
+    pub fn get_wakeup_time(&self) -> Result<(bool, bool, EFITime), EFIStatus> {
+        let mut enabled = 0u8;
+        let mut pending = 0u8;
+        let mut time = EFITime::default();
+        unsafe { (self.get_wakeup_time)(&mut enabled, &mut pending, &mut time) }.to_result_with((
+            enabled != 0,
+            pending != 0,
+            time,
+        ))
+    }
+
+    /// Passing `None` disables the wakeup timer.
+    pub fn set_wakeup_time(&self, time: Option<&EFITime>) -> Result<(), EFIStatus> {
+        let status = match time {
+            Some(time) if !time.is_valid() => return Err(EFIStatus::INVALID_PARAMETER),
+            Some(time) => {
+                let mut time = *time;
+                unsafe { (self.set_wakeup_time)(1, &mut time) }
+            }
+            None => unsafe { (self.set_wakeup_time)(0, core::ptr::null_mut()) },
+        };
+        status.to_result()
+    }
+
+    pub fn get_next_high_monotonic_count(&self) -> Result<u32, EFIStatus> {
+        let mut count = 0u64;
+        unsafe { (self.get_next_high_mono)(&mut count) }.to_result_with(count as u32)
+    }
+
+    /// Returns `(maximum_storage, remaining_storage, maximum_variable_size)`.
+    pub fn query_variable_info(&self, attributes: u32) -> Result<(u64, u64, u64), EFIStatus> {
+        let mut max_storage = 0;
+        let mut remaining_storage = 0;
+        let mut max_variable_size = 0;
+        unsafe {
+            (self.query_variable_info)(
+                attributes,
+                &mut max_storage,
+                &mut remaining_storage,
+                &mut max_variable_size,
+            )
+        }
+        .to_result_with((max_storage, remaining_storage, max_variable_size))
+    }
+
+    /// # Safety
+    /// `address` must point to a runtime memory region that was described in the map passed to
+    /// `set_virtual_address_map`.
+    pub unsafe fn convert_pointer(
+        &self,
+        debug_disposition: u64,
+        address: *mut c_void,
+    ) -> Result<*mut c_void, EFIStatus> {
+        let mut address = address;
+        unsafe { (self.convert_pointer)(debug_disposition, &mut address) }.to_result_with(address)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct EFIConfigurationTable {
     pub vendor_guid: GUID,
@@ -289,11 +530,11 @@ pub struct EFISystemTable {
     pub header: TableHeader,
     pub firmware_vendor: *mut u16,
     pub firmware_revision: u32,
-    pub stdin_handle: *mut c_void,
+    pub stdin_handle: Handle,
     pub stdin: *mut SimpleInputInterface,
-    pub stdout_handle: *mut c_void,
+    pub stdout_handle: Handle,
     pub stdout: *mut SimpleTextOutputInterface,
-    pub stderr_handle: *mut c_void,
+    pub stderr_handle: Handle,
     pub stderr: *mut SimpleTextOutputInterface,
     pub runtime_services: *mut EFIRuntimeServices,
     pub boot_services: *mut EFIBootServices,
@@ -302,6 +543,8 @@ pub struct EFISystemTable {
 }
 
 impl EFISystemTable {
+    /// # Safety
+    /// `ptr` must be the system table passed to `efi_main`.
     pub unsafe fn set_system_table(ptr: *const EFISystemTable) {
         ST.store(ptr.cast_mut(), Ordering::Release);
         let st = Self::fetch_global().expect("Failed to set ST");
@@ -319,16 +562,44 @@ impl EFISystemTable {
         if ptr.is_null() {
             None
         } else {
-            let p_self = ptr as *mut Self;
-            Some(unsafe { &*p_self })
+            Some(unsafe { &*ptr })
+        }
+    }
+
+    /// Tables the firmware published, ACPI, SMBIOS and friends.
+    pub fn config_tables(&self) -> &[EFIConfigurationTable] {
+        if self.configuration_table.is_null() {
+            return &[];
+        }
+        unsafe {
+            core::slice::from_raw_parts(
+                self.configuration_table,
+                self.number_of_table_entries as usize,
+            )
         }
     }
 
+    /// `vendor_table` of the first entry published under `guid`.
+    pub fn find_config_table(&self, guid: &GUID) -> Option<*mut c_void> {
+        self.config_tables()
+            .iter()
+            .find(|table| table.vendor_guid == *guid)
+            .map(|table| table.vendor_table)
+    }
+
     pub fn test_print(&self, s: &[u16]) {
         let stdout = unsafe { self.stdout.as_mut().unwrap() };
-        stdout.output_string(s);
+        let _ = stdout.output_string(s);
     }
 }
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MemoryMapMeta {
+    pub map_size: usize,
+    pub map_key: usize,
+    pub desc_size: usize,
+    pub desc_version: u32,
+}
+
 #[repr(C)]
 pub struct EFIBootServices {
     pub hdr: TableHeader,
@@ -344,166 +615,178 @@ pub struct EFIBootServices {
         mem_ty: EFIMemoryType,
         count: usize,
         addr: *mut u64,
-    ) -> u64,
-    pub free_pages: unsafe extern "efiapi" fn(addr: u64, pages: usize) -> u64,
+    ) -> EFIStatus,
+    pub free_pages: unsafe extern "efiapi" fn(addr: u64, pages: usize) -> EFIStatus,
     pub get_memory_map: unsafe extern "efiapi" fn(
         size: *mut usize,
         map: *mut EFIMemoryDescriptor,
         key: *mut usize,
         desc_size: *mut usize,
         desc_version: *mut u32,
-    ) -> u64,
-    pub allocate_pool:
-        unsafe extern "efiapi" fn(pool_type: u32, size: usize, buffer: *mut *mut c_void) -> u64,
-    pub free_pool: unsafe extern "efiapi" fn(buffer: *mut c_void) -> u64,
+    ) -> EFIStatus,
+    pub allocate_pool: unsafe extern "efiapi" fn(
+        pool_type: u32,
+        size: usize,
+        buffer: *mut *mut c_void,
+    ) -> EFIStatus,
+    pub free_pool: unsafe extern "efiapi" fn(buffer: *mut c_void) -> EFIStatus,
 
     pub create_event: unsafe extern "efiapi" fn(
         ty: u32,
         notify_tpl: u64,
-        notify_func: Option<unsafe extern "efiapi" fn(event: *mut c_void, context: *mut c_void)>,
+        notify_func: Option<EFIEventNotify>,
         notify_ctx: *mut c_void,
-        out_event: *mut c_void,
-    ) -> u64,
-    pub set_timer: unsafe extern "efiapi" fn(event: c_void, ty: i32, trigger_time: u64) -> u64,
+        out_event: *mut Event,
+    ) -> EFIStatus,
+    pub set_timer: unsafe extern "efiapi" fn(event: Event, ty: u32, trigger_time: u64) -> EFIStatus,
     pub wait_for_event: unsafe extern "efiapi" fn(
-        number_of_events: u64,
-        events: *mut c_void,
-        out_index: *mut u64,
-    ) -> u64,
-    pub signal_event: unsafe extern "efiapi" fn(event: c_void) -> u64,
-    pub close_event: unsafe extern "efiapi" fn(event: c_void) -> u64,
-    pub check_event: unsafe extern "efiapi" fn(event: c_void) -> u64,
+        number_of_events: usize,
+        events: *const Event,
+        out_index: *mut usize,
+    ) -> EFIStatus,
+    pub signal_event: unsafe extern "efiapi" fn(event: Event) -> EFIStatus,
+    pub close_event: unsafe extern "efiapi" fn(event: Event) -> EFIStatus,
+    pub check_event: unsafe extern "efiapi" fn(event: Event) -> EFIStatus,
 
     // Protocol handlers
     pub install_protocol_interface: unsafe extern "efiapi" fn(
-        handle: *mut c_void,
+        handle: *mut Handle,
         guid: *const GUID,
         interface_type: u32,
         interface: *const c_void,
-    ) -> u64,
+    ) -> EFIStatus,
     pub reinstall_protocol_interface: unsafe extern "efiapi" fn(
-        handle: c_void,
+        handle: Handle,
         protocol: *const GUID,
         old_interface: *const c_void,
         new_interface: *const c_void,
-    ) -> u64,
+    ) -> EFIStatus,
     pub uninstall_protocol_interface: unsafe extern "efiapi" fn(
-        handle: c_void,
+        handle: Handle,
         protocol: *const GUID,
         interface: *const c_void,
-    ) -> u64,
+    ) -> EFIStatus,
     pub handle_protocol: unsafe extern "efiapi" fn(
-        handle: c_void,
+        handle: Handle,
         protocol: *const GUID,
         out_proto: *mut *mut c_void,
-    ) -> u64,
+    ) -> EFIStatus,
     pub reserved: *mut c_void,
     pub register_protocol_notify: unsafe extern "efiapi" fn(
         protocol: *const GUID,
-        event: c_void,
-        registration: *mut *const c_void,
-    ) -> u64,
+        event: Event,
+        registration: *mut *mut c_void,
+    ) -> EFIStatus,
     pub locate_handle: unsafe extern "efiapi" fn(
         search_ty: i32,
         protocol: *const GUID,
         key: *const c_void,
-        buf_sz: *mut u64,
-        buf: *mut c_void,
-    ) -> u64,
+        buf_sz: *mut usize,
+        buf: *mut Handle,
+    ) -> EFIStatus,
     pub locate_device_path: unsafe extern "efiapi" fn(
         protocol: *const GUID,
         device: *mut *const EFIDevicePath,
-        out_handle: *mut c_void,
-    ) -> u64,
+        out_handle: *mut Handle,
+    ) -> EFIStatus,
     pub install_configuration_table:
-        unsafe extern "efiapi" fn(guid_entry: *const GUID, table_ptr: *const c_void) -> u64,
+        unsafe extern "efiapi" fn(guid_entry: *const GUID, table_ptr: *const c_void) -> EFIStatus,
 
     // Image services
     pub load_image: unsafe extern "efiapi" fn(
         boot_policy: u8,
-        parent_image_handle: *mut c_void,
+        parent_image_handle: Handle,
         device: *const EFIDevicePath,
         source_buffer: *const u8,
-        source_size: u64,
-        image_handle: *mut *mut c_void,
-    ) -> u64,
+        source_size: usize,
+        image_handle: *mut Handle,
+    ) -> EFIStatus,
     pub start_image: unsafe extern "efiapi" fn(
-        image_handle: *mut c_void,
-        exit_data_size: *mut u64,
+        image_handle: Handle,
+        exit_data_size: *mut usize,
         exit_data: *mut *mut u16,
-    ) -> u64,
+    ) -> EFIStatus,
     pub exit: unsafe extern "efiapi" fn(
-        image_handle: c_void,
-        exit_status: u64,
-        exit_data_size: u64,
+        image_handle: Handle,
+        exit_status: EFIStatus,
+        exit_data_size: usize,
         exit_data: *mut u16,
     ) -> !,
-    pub unload_image: unsafe extern "efiapi" fn(image_handle: c_void) -> u64,
-    pub exit_boot_services: unsafe extern "efiapi" fn(image_handle: c_void, map_key: u64) -> u64,
+    pub unload_image: unsafe extern "efiapi" fn(image_handle: Handle) -> EFIStatus,
+    pub exit_boot_services:
+        unsafe extern "efiapi" fn(image_handle: Handle, map_key: usize) -> EFIStatus,
 
     // Misc services
-    pub get_next_monotonic_count: unsafe extern "efiapi" fn(count: *mut u64) -> u64,
-    pub stall: unsafe extern "efiapi" fn(microseconds: u64) -> u64,
+    pub get_next_monotonic_count: unsafe extern "efiapi" fn(count: *mut u64) -> EFIStatus,
+    pub stall: unsafe extern "efiapi" fn(microseconds: u64) -> EFIStatus,
     pub set_watchdog_timer: unsafe extern "efiapi" fn(
         timeout: u64,
         watchdog_code: u64,
         data_size: u64,
         watchdog_data: *const u16,
-    ) -> u64,
+    ) -> EFIStatus,
 
     // Driver support services
     pub connect_controller: unsafe extern "efiapi" fn(
-        controller: c_void,
-        driver_image: c_void,
+        controller: Handle,
+        driver_image: *const Handle,
         remaining_device_path: *const EFIDevicePath,
         recursive: u8,
-    ) -> u64,
-    pub disconnect_controller:
-        unsafe extern "efiapi" fn(controller: c_void, driver_image: c_void, child: c_void) -> u64,
+    ) -> EFIStatus,
+    pub disconnect_controller: unsafe extern "efiapi" fn(
+        controller: Handle,
+        driver_image: Handle,
+        child: Handle,
+    ) -> EFIStatus,
 
     // Protocol open / close services
     pub open_protocol: unsafe extern "efiapi" fn(
-        handle: c_void,
+        handle: Handle,
         protocol: *const GUID,
         interface: *mut *mut c_void,
-        agent_handle: c_void,
-        controller_handle: c_void,
+        agent_handle: Handle,
+        controller_handle: Handle,
         attributes: u32,
-    ) -> u64,
+    ) -> EFIStatus,
     pub close_protocol: unsafe extern "efiapi" fn(
-        handle: c_void,
+        handle: Handle,
         protocol: *const GUID,
-        agent_handle: c_void,
-        controller_handle: c_void,
-    ) -> u64,
+        agent_handle: Handle,
+        controller_handle: Handle,
+    ) -> EFIStatus,
     pub open_protocol_information: unsafe extern "efiapi" fn(
-        handle: c_void,
+        handle: Handle,
         protocol: *const GUID,
-        entry_buffer: *mut *const EFIOpenProtocolInfoEntry,
-        entry_count: *mut u64,
-    ) -> u64,
+        entry_buffer: *mut *mut EFIOpenProtocolInfoEntry,
+        entry_count: *mut usize,
+    ) -> EFIStatus,
     pub protocols_per_handle: unsafe extern "efiapi" fn(
-        handle: c_void,
+        handle: Handle,
         protocol_buffer: *mut *mut *const GUID,
-        protocol_buffer_count: *mut u64,
-    ) -> u64,
+        protocol_buffer_count: *mut usize,
+    ) -> EFIStatus,
     pub locate_handle_buffer: unsafe extern "efiapi" fn(
         search_ty: i32,
         protocol: *const GUID,
         key: *const c_void,
-        no_handles: *mut u64,
-        buf: *mut *mut c_void,
-    ) -> u64,
+        no_handles: *mut usize,
+        buf: *mut *mut Handle,
+    ) -> EFIStatus,
     pub locate_protocol: unsafe extern "efiapi" fn(
         protocol: *const GUID,
         registration: *mut c_void,
         out_proto: *mut *mut c_void,
-    ) -> u64,
+    ) -> EFIStatus,
 
-    pub install_multiple_protocol_interfaces: unsafe extern "C" fn(handle: *mut c_void, ...) -> u64,
-    pub uninstall_multiple_protocol_interfaces: unsafe extern "C" fn(handle: c_void, ...) -> u64,
-    pub calculate_crc32:
-        unsafe extern "efiapi" fn(data: *const c_void, data_size: u64, crc32: *mut u32) -> u64,
+    pub install_multiple_protocol_interfaces:
+        unsafe extern "C" fn(handle: *mut Handle, ...) -> EFIStatus,
+    pub uninstall_multiple_protocol_interfaces:
+        unsafe extern "C" fn(handle: Handle, ...) -> EFIStatus,
+    pub calculate_crc32: unsafe extern "efiapi" fn(
+        data: *const c_void,
+        data_size: u64,
+        crc32: *mut u32,
+    ) -> EFIStatus,
     pub copy_mem: unsafe extern "efiapi" fn(dest: *mut u8, src: *const u8, len: u64),
     pub set_mem: unsafe extern "efiapi" fn(buffer: *mut u8, len: u64, value: u8),
 
@@ -511,11 +794,11 @@ pub struct EFIBootServices {
     pub create_event_ex: unsafe extern "efiapi" fn(
         ty: u32,
         notify_tpl: u64,
-        notify_fn: Option<unsafe extern "efiapi" fn(event: *mut c_void, context: *mut c_void)>,
+        notify_fn: Option<EFIEventNotify>,
         notify_ctx: *mut c_void,
-        event_group: *mut GUID,
-        out_event: *mut c_void,
-    ) -> u64,
+        event_group: *const GUID,
+        out_event: *mut Event,
+    ) -> EFIStatus,
 }
 
 impl EFIBootServices {
@@ -524,30 +807,241 @@ impl EFIBootServices {
         if ptr.is_null() {
             None
         } else {
-            let p_self = ptr as *mut Self;
-            Some(unsafe { &*p_self })
+            Some(unsafe { &*ptr })
         }
     }
 
-    pub fn allocate_pool(&self, pool_type: u32, size: usize) -> *mut u8 {
-        macro_rules! cstr_16 {
-            [$($n:literal),*] => {
-                [$($n as u16),*]
-            };
+    pub fn raise_tpl(&self, new_tpl: usize) -> usize {
+        unsafe { (self.raise_tpl)(new_tpl) }
+    }
+
+    pub fn restore_tpl(&self, old_tpl: usize) {
+        unsafe { (self.restor_tpl)(old_tpl) };
+    }
+
+    pub fn allocate_pages(
+        &self,
+        alloc_ty: EFIAllocateType,
+        mem_ty: EFIMemoryType,
+        count: usize,
+        addr: u64,
+    ) -> Result<u64, EFIStatus> {
+        let mut addr = addr;
+        unsafe { (self.allocate_pages)(alloc_ty, mem_ty, count, &mut addr) }.to_result_with(addr)
+    }
+
+    pub fn free_pages(&self, addr: u64, pages: usize) -> Result<(), EFIStatus> {
+        unsafe { (self.free_pages)(addr, pages) }.to_result()
+    }
+
+    /// On `EFIStatus::BUFFER_TOO_SMALL` the required size can be fetched with
+    /// [`EFIBootServices::memory_map_size`].
+    pub fn get_memory_map(&self, buffer: &mut [u8]) -> Result<MemoryMapMeta, EFIStatus> {
+        let mut meta = MemoryMapMeta {
+            map_size: buffer.len(),
+            ..MemoryMapMeta::default()
+        };
+        unsafe {
+            (self.get_memory_map)(
+                &mut meta.map_size,
+                buffer.as_mut_ptr() as *mut EFIMemoryDescriptor,
+                &mut meta.map_key,
+                &mut meta.desc_size,
+                &mut meta.desc_version,
+            )
         }
-        let st = EFISystemTable::fetch_global().unwrap();
+        .to_result_with(meta)
+    }
+
+    pub fn memory_map_size(&self) -> MemoryMapMeta {
+        let mut meta = MemoryMapMeta::default();
+        unsafe {
+            (self.get_memory_map)(
+                &mut meta.map_size,
+                core::ptr::null_mut(),
+                &mut meta.map_key,
+                &mut meta.desc_size,
+                &mut meta.desc_version,
+            )
+        };
+        meta
+    }
+
+    pub fn allocate_pool(&self, pool_type: u32, size: usize) -> Result<*mut u8, EFIStatus> {
         let mut buffer = core::ptr::null_mut();
-        unsafe { (self.allocate_pool)(2, size, &mut buffer) };
+        unsafe { (self.allocate_pool)(pool_type, size, &mut buffer) }
+            .to_result_with(buffer as *mut u8)
+    }
 
-        buffer as *mut u8
+    pub fn free_pool(&self, buffer: *mut u8) -> Result<(), EFIStatus> {
+        unsafe { (self.free_pool)(buffer as *mut c_void) }.to_result()
     }
-    pub fn free_pool(&self, buffer: *mut u8) {
-        unsafe { (self.free_pool)(buffer as *mut c_void) };
+
+    /// Passing a null `table` removes the entry for `guid`.
+    ///
+    /// # Safety
+    /// `table` must stay valid for as long as the entry is installed.
+    pub unsafe fn install_configuration_table(
+        &self,
+        guid: &GUID,
+        table: *const c_void,
+    ) -> Result<(), EFIStatus> {
+        unsafe { (self.install_configuration_table)(guid, table) }.to_result()
+    }
+
+    /// Prefer [`crate::exit_boot_services`], which also takes care of the memory map and the
+    /// globals.
+    ///
+    /// # Safety
+    /// On success every boot service, including this table, is gone.
+    pub unsafe fn exit_boot_services(
+        &self,
+        image_handle: Handle,
+        map_key: usize,
+    ) -> Result<(), EFIStatus> {
+        unsafe { (self.exit_boot_services)(image_handle, map_key) }.to_result()
     }
 
-    // : unsafe extern "efiapi" fn(
-    //     pool_type: EFIMemoryType,
-    //     size: u64,
-    //     buffer: *mut *mut c_void,
-    // ) -> u64,
+    /// # Safety
+    /// `notify_fn` is called with `notify_ctx` at `notify_tpl`, both have to stay valid until
+    /// the event is closed.
+    pub unsafe fn create_event(
+        &self,
+        ty: u32,
+        notify_tpl: usize,
+        notify_fn: Option<EFIEventNotify>,
+        notify_ctx: *mut c_void,
+    ) -> Result<Event, EFIStatus> {
+        let mut event = Event::null();
+        unsafe { (self.create_event)(ty, notify_tpl as u64, notify_fn, notify_ctx, &mut event) }
+            .to_result_with(event)
+    }
+
+    /// # Safety
+    /// Same as [`EFIBootServices::create_event`].
+    pub unsafe fn create_event_ex(
+        &self,
+        ty: u32,
+        notify_tpl: usize,
+        notify_fn: Option<EFIEventNotify>,
+        notify_ctx: *mut c_void,
+        event_group: Option<&GUID>,
+    ) -> Result<Event, EFIStatus> {
+        let mut event = Event::null();
+        let group = event_group.map_or(core::ptr::null(), |g| g as *const GUID);
+        unsafe {
+            (self.create_event_ex)(
+                ty,
+                notify_tpl as u64,
+                notify_fn,
+                notify_ctx,
+                group,
+                &mut event,
+            )
+        }
+        .to_result_with(event)
+    }
+
+    /// `ty` is 0 to cancel, 1 for periodic and 2 for relative, `trigger_time` is in 100ns units.
+    pub fn set_timer(&self, event: Event, ty: u32, trigger_time: u64) -> Result<(), EFIStatus> {
+        unsafe { (self.set_timer)(event, ty, trigger_time) }.to_result()
+    }
+
+    pub fn signal_event(&self, event: Event) -> Result<(), EFIStatus> {
+        unsafe { (self.signal_event)(event) }.to_result()
+    }
+
+    pub fn close_event(&self, event: Event) -> Result<(), EFIStatus> {
+        unsafe { (self.close_event)(event) }.to_result()
+    }
+
+    /// `Ok(true)` if the event was signaled, which also clears it.
+    pub fn check_event(&self, event: Event) -> Result<bool, EFIStatus> {
+        match unsafe { (self.check_event)(event) } {
+            EFIStatus::NOT_READY => Ok(false),
+            status => status.to_result_with(true),
+        }
+    }
+
+    /// Blocks until one of `events` is signaled and returns its index.
+    pub fn wait_for_event(&self, events: &[Event]) -> Result<usize, EFIStatus> {
+        let mut index = 0;
+        unsafe { (self.wait_for_event)(events.len(), events.as_ptr(), &mut index) }
+            .to_result_with(index)
+    }
+
+    /// `source` loads from memory, otherwise the image is read from `device_path`.
+    ///
+    /// # Safety
+    /// `device_path` must be null or point to a valid device path.
+    pub unsafe fn load_image(
+        &self,
+        boot_policy: bool,
+        parent: Handle,
+        device_path: *const EFIDevicePath,
+        source: Option<&[u8]>,
+    ) -> Result<Handle, EFIStatus> {
+        let mut image = Handle::null();
+        let (buf, len) = source.map_or((core::ptr::null(), 0), |s| (s.as_ptr(), s.len()));
+        unsafe { (self.load_image)(boot_policy as u8, parent, device_path, buf, len, &mut image) }
+            .to_result_with(image)
+    }
+
+    /// Runs a loaded image and returns its exit status along with the exit data string.
+    pub fn start_image(&self, image: Handle) -> image::ImageExit {
+        let mut exit_data_size = 0;
+        let mut exit_data = core::ptr::null_mut();
+        let status = unsafe { (self.start_image)(image, &mut exit_data_size, &mut exit_data) };
+        unsafe { image::ImageExit::from_raw(self, status, exit_data_size, exit_data) }
+    }
+
+    pub fn unload_image(&self, image: Handle) -> Result<(), EFIStatus> {
+        unsafe { (self.unload_image)(image) }.to_result()
+    }
+
+    /// # Safety
+    /// `exit_data` has to come from `allocate_pool`, ownership passes to the firmware.
+    pub unsafe fn exit(
+        &self,
+        image: Handle,
+        status: EFIStatus,
+        exit_data: Option<&mut [u16]>,
+    ) -> ! {
+        let (ptr, len) = exit_data.map_or((core::ptr::null_mut(), 0), |d| {
+            (d.as_mut_ptr(), core::mem::size_of_val(d))
+        });
+        unsafe { (self.exit)(image, status, len, ptr) }
+    }
+
+    pub fn get_next_monotonic_count(&self) -> Result<u64, EFIStatus> {
+        let mut count = 0;
+        unsafe { (self.get_next_monotonic_count)(&mut count) }.to_result_with(count)
+    }
+
+    pub fn stall(&self, microseconds: u64) -> Result<(), EFIStatus> {
+        unsafe { (self.stall)(microseconds) }.to_result()
+    }
+
+    /// A `timeout` of 0 disables the watchdog.
+    pub fn set_watchdog_timer(&self, timeout: u64, watchdog_code: u64) -> Result<(), EFIStatus> {
+        unsafe { (self.set_watchdog_timer)(timeout, watchdog_code, 0, core::ptr::null()) }
+            .to_result()
+    }
+
+    pub fn calculate_crc32(&self, data: &[u8]) -> Result<u32, EFIStatus> {
+        let mut crc = 0;
+        unsafe {
+            (self.calculate_crc32)(data.as_ptr() as *const c_void, data.len() as u64, &mut crc)
+        }
+        .to_result_with(crc)
+    }
+
+    pub fn copy_mem(&self, dest: &mut [u8], src: &[u8]) {
+        let len = dest.len().min(src.len());
+        unsafe { (self.copy_mem)(dest.as_mut_ptr(), src.as_ptr(), len as u64) };
+    }
+
+    pub fn set_mem(&self, buffer: &mut [u8], value: u8) {
+        unsafe { (self.set_mem)(buffer.as_mut_ptr(), buffer.len() as u64, value) };
+    }
 }
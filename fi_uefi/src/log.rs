@@ -0,0 +1,642 @@
+//! Leveled logging to the console, a serial port and a ring buffer handed to the kernel.
+//!
+//! ```ignore
+//! log::init(Sinks::ALL);
+//! info!("read {} bytes", data.len());
+//! debug!(target: "fi::boot", "entry at {:#x}", entry);
+//! ```
+//!
+//! The filter is a comma separated list of a default level and `target=level` overrides, e.g.
+//! `warn,fi_uefi::fs=trace`. A target matches its own name and everything below it, the longest
+//! match wins, `off` silences. Anything else is an error, so a misspelled level is not mistaken
+//! for a target.
+//!
+//! Logging never allocates, so it keeps working into the ring buffer after
+//! `exit_boot_services`. The console and serial sinks go quiet at that point.
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Write};
+use core::ptr;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicPtr, AtomicU8, AtomicU64, Ordering};
+
+use crate::args::{Flags, args};
+use crate::console::Console;
+use crate::event::{TplGuard, tpl};
+use crate::flags::flags;
+use crate::serial::EFISerialIoProtocol;
+use crate::{EFIBootServices, EFIRuntimeServices, GUID};
+
+/// The variable [`init`] reads the filter from, as ASCII.
+pub const LOG_VARIABLE: &str = "LogLevel";
+/// The load option [`init`] reads the filter from, ahead of the variable.
+pub const LOG_OPTION: &str = "loglevel";
+pub const LOG_VARIABLE_VENDOR: GUID = crate::guid!("7a9c943a-79d2-47f7-bf3f-8904cfda0086");
+pub const LOG_BUFFER_LEN: usize = 64 * 1024;
+
+// Longest record kept, including the level and target. It is formatted on the stack, the rest
+// is cut.
+const RECORD_LEN: usize = 512;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, FilterError> {
+        match parse_level(s)? {
+            Some(level) => Ok(level),
+            None => Err(FilterError(String::from(s))),
+        }
+    }
+}
+
+/// `None` for `off`, case is ignored.
+fn parse_level(s: &str) -> Result<Option<Level>, FilterError> {
+    Ok(Some(match s.to_ascii_lowercase().as_str() {
+        "off" => return Ok(None),
+        "error" => Level::Error,
+        "warn" | "warning" => Level::Warn,
+        "info" => Level::Info,
+        "debug" => Level::Debug,
+        "trace" => Level::Trace,
+        _ => return Err(FilterError(String::from(s))),
+    }))
+}
+
+/// The part of a filter that didn't parse.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FilterError(pub String);
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid log filter '{}'", self.0)
+    }
+}
+
+/// Which records get through. Levels are ordered, `None` (off) sorts below all of them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Filter {
+    pub level: Option<Level>,
+    /// Target prefixes and their levels.
+    pub targets: Vec<(String, Option<Level>)>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::new(Some(Level::Info))
+    }
+}
+
+impl Filter {
+    pub const fn new(level: Option<Level>) -> Self {
+        Self {
+            level,
+            targets: Vec::new(),
+        }
+    }
+
+    pub fn level_for(&self, target: &str) -> Option<Level> {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.level, |(_, level)| *level)
+    }
+
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        Some(level) <= self.level_for(target)
+    }
+
+    /// The most verbose level any target gets.
+    fn max_level(&self) -> Option<Level> {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.level, Ord::max)
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, FilterError> {
+        let mut filter = Filter::default();
+        for part in s.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            match part.split_once('=') {
+                Some((target, level)) if !target.trim().is_empty() => filter
+                    .targets
+                    .push((String::from(target.trim()), parse_level(level.trim())?)),
+                Some(_) => return Err(FilterError(String::from(part))),
+                None => filter.level = parse_level(part)?,
+            }
+        }
+        Ok(filter)
+    }
+}
+
+flags! {
+    pub struct Sinks(u8) {
+        /// Standard error of the system table, until boot services exit.
+        const CONSOLE = 0x01;
+        /// The first serial port the firmware knows of, until boot services exit.
+        const SERIAL = 0x02;
+        /// The ring buffer from [`buffer`].
+        const BUFFER = 0x04;
+        const ALL = Self::CONSOLE.0 | Self::SERIAL.0 | Self::BUFFER.0;
+    }
+}
+
+/// Splits a ring of `data.len()` bytes, into which `written` bytes went in total, into the
+/// retained bytes in order, oldest first. Once it has wrapped the first record is usually cut.
+pub fn ring_parts(data: &[u8], written: u64) -> (&[u8], &[u8]) {
+    let capacity = data.len();
+    let retained = written.min(capacity as u64) as usize;
+    if retained == 0 {
+        return (&[], &[]);
+    }
+    let start = ((written - retained as u64) % capacity as u64) as usize;
+    let first = &data[start..capacity.min(start + retained)];
+    (first, &data[..retained - first.len()])
+}
+
+/// A byte ring the log records are appended to, the oldest bytes are overwritten once it is
+/// full.
+pub struct LogBuffer {
+    data: UnsafeCell<[u8; LOG_BUFFER_LEN]>,
+    written: AtomicU64,
+}
+
+// Writers and readers raise the TPL to HIGH_LEVEL while boot services are up, there is only one
+// thread of execution afterwards.
+unsafe impl Sync for LogBuffer {}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogBuffer {
+    pub const fn new() -> Self {
+        Self {
+            data: UnsafeCell::new([0; LOG_BUFFER_LEN]),
+            written: AtomicU64::new(0),
+        }
+    }
+
+    /// Keeps event notify functions from logging into the middle of an access.
+    fn lock() -> Option<TplGuard<'static>> {
+        EFIBootServices::fetch_global().map(|bs| TplGuard::raise(bs, tpl::HIGH_LEVEL))
+    }
+
+    pub fn push(&self, bytes: &[u8]) {
+        let _guard = Self::lock();
+        let written = self.written.load(Ordering::Relaxed);
+        // Of a record longer than the ring only the tail would survive anyway.
+        let skipped = bytes.len().saturating_sub(LOG_BUFFER_LEN);
+        let tail = &bytes[skipped..];
+        let start = ((written + skipped as u64) % LOG_BUFFER_LEN as u64) as usize;
+        let first = tail.len().min(LOG_BUFFER_LEN - start);
+        let data = self.data.get().cast::<u8>();
+        unsafe {
+            ptr::copy_nonoverlapping(tail.as_ptr(), data.add(start), first);
+            ptr::copy_nonoverlapping(tail[first..].as_ptr(), data, tail.len() - first);
+        }
+        self.written
+            .store(written + bytes.len() as u64, Ordering::Release);
+    }
+
+    /// Bytes pushed since the start, including the ones overwritten since.
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Acquire)
+    }
+
+    /// Bytes still in the ring.
+    pub fn len(&self) -> usize {
+        self.written().min(LOG_BUFFER_LEN as u64) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.written() == 0
+    }
+
+    /// Copies the newest `dest.len()` retained bytes into `dest`, oldest first, returns how
+    /// many that were.
+    pub fn read(&self, dest: &mut [u8]) -> usize {
+        let _guard = Self::lock();
+        let data = unsafe { &*self.data.get() };
+        let (first, second) = ring_parts(data, self.written());
+        // Drop the oldest bytes that don't fit.
+        let skip = (first.len() + second.len()).saturating_sub(dest.len());
+        let (first, second) = match first.get(skip..) {
+            Some(first) => (first, second),
+            None => (&[][..], &second[skip - first.len()..]),
+        };
+        let len = first.len() + second.len();
+        dest[..first.len()].copy_from_slice(first);
+        dest[first.len()..len].copy_from_slice(second);
+        len
+    }
+
+    /// Start of the ring, which is [`LOG_BUFFER_LEN`] bytes long.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.data.get().cast()
+    }
+}
+
+static BUFFER: LogBuffer = LogBuffer::new();
+// Checked before the filter so disabled levels cost one load.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+// Null until the first `set_filter`, the default filter is all MAX_LEVEL has to say.
+static FILTER: AtomicPtr<Filter> = AtomicPtr::new(ptr::null_mut());
+static SINKS: AtomicU8 = AtomicU8::new(0);
+
+/// The ring every record goes to with [`Sinks::BUFFER`]. It lives in the loader image, so it is
+/// still there for the kernel after `exit_boot_services`.
+pub fn buffer() -> &'static LogBuffer {
+    &BUFFER
+}
+
+/// Nothing is logged until the first call.
+pub fn set_sinks(sinks: Sinks) {
+    SINKS.store(sinks.bits(), Ordering::Relaxed);
+}
+
+pub fn sinks() -> Sinks {
+    Sinks(SINKS.load(Ordering::Relaxed))
+}
+
+/// Replaces the filter, needs boot services to allocate.
+pub fn set_filter(filter: Filter) {
+    let max_level = filter.max_level().map_or(0, |level| level as u8);
+    // The old filter is leaked, an event notify that interrupted a log call may still be
+    // looking at it. Filters are set a handful of times per boot.
+    FILTER.swap(Box::into_raw(Box::new(filter)), Ordering::AcqRel);
+    MAX_LEVEL.store(max_level, Ordering::Release);
+}
+
+pub fn filter() -> Filter {
+    match unsafe { FILTER.load(Ordering::Acquire).as_ref() } {
+        Some(filter) => filter.clone(),
+        None => Filter::default(),
+    }
+}
+
+pub fn enabled(level: Level, target: &str) -> bool {
+    if level as u8 > MAX_LEVEL.load(Ordering::Relaxed) {
+        return false;
+    }
+    match unsafe { FILTER.load(Ordering::Acquire).as_ref() } {
+        Some(filter) => filter.enabled(level, target),
+        None => true,
+    }
+}
+
+fn variable_filter() -> Option<String> {
+    let rt = EFIRuntimeServices::fetch_global()?;
+    let (data, _) = rt
+        .variables()
+        .get(LOG_VARIABLE, &LOG_VARIABLE_VENDOR)
+        .ok()?;
+    let text = String::from_utf8(data).ok()?;
+    // `dmpstore` and friends tend to store the terminator too.
+    Some(String::from(text.trim_end_matches('\0')))
+}
+
+/// Starts logging to `sinks` with the filter from the [`LOG_OPTION`] load option, or else the
+/// [`LOG_VARIABLE`] variable, `info` without either. Other load options are left alone. A filter
+/// that doesn't parse is logged as a warning and skipped.
+///
+/// An application that parses its load options with [`Flags`] should declare [`LOG_OPTION`]
+/// as well, so it isn't rejected as unknown there.
+pub fn init(sinks: Sinks) {
+    set_sinks(sinks);
+    let mut problems = Vec::new();
+    let mut filter = match variable_filter().map(|spec| spec.parse()) {
+        Some(Ok(filter)) => filter,
+        Some(Err(err)) => {
+            problems.push(format!("{err}, logging at info"));
+            Filter::default()
+        }
+        None => Filter::default(),
+    };
+    // Words that don't split are the application's to report.
+    if let Ok(args) = args()
+        && let Err(err) = Flags::new()
+            .value(LOG_OPTION, "", &mut filter)
+            .ignore_unknown()
+            .parse(&args)
+    {
+        problems.push(format!("{err}"));
+    }
+    set_filter(filter);
+    for problem in problems {
+        crate::warn!("{problem}");
+    }
+}
+
+/// A record being formatted, cut at [`RECORD_LEN`] with room left for the newline.
+struct Record {
+    buf: [u8; RECORD_LEN],
+    len: usize,
+}
+
+impl Write for Record {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = RECORD_LEN - 1 - self.len;
+        let mut len = s.len().min(room);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+impl Record {
+    fn finish(&mut self) -> &str {
+        self.buf[self.len] = b'\n';
+        self.len += 1;
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+}
+
+fn write_serial(text: &str) {
+    // Located for every record, the port could have been disconnected since the last one.
+    let Some(serial) = EFIBootServices::fetch_global()
+        .and_then(|bs| bs.locate_protocol::<EFISerialIoProtocol>().ok())
+    else {
+        return;
+    };
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 && serial.write(b"\r\n").is_err() {
+            return;
+        }
+        if serial.write(line.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, target: &str, args: fmt::Arguments) {
+    let sinks = sinks();
+    if sinks == Sinks::empty() || !enabled(level, target) {
+        return;
+    }
+    let mut record = Record {
+        buf: [0; RECORD_LEN],
+        len: 0,
+    };
+    let _ = write!(record, "{level:<5} {target}: {args}");
+    let text = record.finish();
+    if sinks.contains(Sinks::BUFFER) {
+        BUFFER.push(text.as_bytes());
+    }
+    if sinks.contains(Sinks::CONSOLE) {
+        let _ = Console::stderr().write_str(text);
+    }
+    if sinks.contains(Sinks::SERIAL) {
+        write_serial(text);
+    }
+}
+
+/// Logs at a [`Level`], the target defaults to the module path.
+#[macro_export]
+macro_rules! log {
+    (target: $target:expr, $level:expr, $($arg:tt)+) => {
+        $crate::log::_log($level, $target, format_args!($($arg)+))
+    };
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::_log($level, module_path!(), format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Error, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Error, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Warn, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Warn, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Info, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Info, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Debug, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Debug, $($arg)+)
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    (target: $target:expr, $($arg:tt)+) => {
+        $crate::log!(target: $target, $crate::log::Level::Trace, $($arg)+)
+    };
+    ($($arg:tt)+) => {
+        $crate::log!($crate::log::Level::Trace, $($arg)+)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockFirmware;
+    use crate::variable::VariableAttributes;
+    use alloc::vec;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16()
+            .chain([0])
+            .flat_map(u16::to_le_bytes)
+            .collect()
+    }
+
+    fn set_log_variable(value: &str) {
+        EFIRuntimeServices::fetch_global()
+            .unwrap()
+            .variables()
+            .set(
+                LOG_VARIABLE,
+                &LOG_VARIABLE_VENDOR,
+                VariableAttributes::NV_BS_RT,
+                value.as_bytes(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_filter_targets() {
+        let filter: Filter = "warn, fi_uefi::fs=trace, fi_uefi=OFF, fi::boot=trace"
+            .parse()
+            .unwrap();
+        assert_eq!(filter.level, Some(Level::Warn));
+        assert_eq!(filter.level_for("fi_uefi::fs::dir"), Some(Level::Trace));
+        assert_eq!(filter.level_for("fi_uefi::fsck"), None);
+        assert_eq!(filter.level_for("fi_uefi"), None);
+        assert_eq!(filter.level_for("fi::boot"), Some(Level::Trace));
+        assert_eq!(filter.level_for("fi_os"), Some(Level::Warn));
+        assert!(filter.enabled(Level::Error, "fi_os"));
+        assert!(!filter.enabled(Level::Info, "fi_os"));
+        assert!(!filter.enabled(Level::Error, "fi_uefi::image"));
+        assert_eq!(filter.max_level(), Some(Level::Trace));
+
+        assert_eq!("".parse::<Filter>(), Ok(Filter::default()));
+        assert_eq!(
+            "info,fs=loud".parse::<Filter>(),
+            Err(FilterError("loud".into()))
+        );
+        assert!("=debug".parse::<Filter>().is_err());
+        // A typo is not a target.
+        assert_eq!("degub".parse::<Filter>(), Err(FilterError("degub".into())));
+        assert_eq!(
+            "info,fi_uefi::fs".parse::<Filter>(),
+            Err(FilterError("fi_uefi::fs".into()))
+        );
+        assert_eq!("Debug".parse::<Level>(), Ok(Level::Debug));
+        assert!("off".parse::<Level>().is_err());
+    }
+
+    #[test]
+    fn test_ring_buffer_wraps() {
+        let _fw = MockFirmware::install();
+        let ring = Box::new(LogBuffer::new());
+        assert!(ring.is_empty());
+        ring.push(&vec![b'a'; LOG_BUFFER_LEN - 4]);
+        ring.push(b"0123456789");
+        assert_eq!(ring.written(), LOG_BUFFER_LEN as u64 + 6);
+        assert_eq!(ring.len(), LOG_BUFFER_LEN);
+
+        let data = unsafe { &*ring.data.get() };
+        let (first, second) = ring_parts(data, ring.written());
+        assert_eq!(first.len(), LOG_BUFFER_LEN - 6);
+        assert!(first.ends_with(b"aaa0123"));
+        assert_eq!(second, b"456789");
+
+        // Too small for everything, gets the newest bytes.
+        let mut tail = [0; 8];
+        assert_eq!(ring.read(&mut tail), 8);
+        assert_eq!(&tail, b"23456789");
+        let mut all = vec![0; LOG_BUFFER_LEN + 10];
+        assert_eq!(ring.read(&mut all), LOG_BUFFER_LEN);
+        assert!(all[..LOG_BUFFER_LEN].ends_with(b"aa0123456789"));
+    }
+
+    #[test]
+    fn test_load_option_beats_variable() {
+        let fw = MockFirmware::install_with(|state| {
+            state.load_options =
+                utf16("fi.efi --kernel \\k.elf --loglevel debug,fi_uefi::fs=off menu");
+        });
+        set_log_variable("trace");
+        init(Sinks::ALL);
+        let before = buffer().written();
+
+        debug!(target: "fi::boot", "entry at {:#x}", 0x1000);
+        trace!(target: "fi::boot", "not shown");
+        info!(target: "fi_uefi::fs::dir", "not shown either");
+        let record = "DEBUG fi::boot: entry at 0x1000";
+        assert_eq!(fw.stderr(), format!("{record}\r\n"));
+        // Longer than one mock serial write.
+        assert_eq!(fw.serial(), format!("{record}\r\n"));
+        assert_eq!(buffer().written() - before, record.len() as u64 + 1);
+        let mut tail = [0; 32];
+        buffer().read(&mut tail);
+        assert_eq!(&tail, format!("{record}\n").as_bytes());
+        set_sinks(Sinks::empty());
+    }
+
+    #[test]
+    fn test_variable_filter_and_bad_filter() {
+        {
+            let fw = MockFirmware::install();
+            set_log_variable("warn\0");
+            init(Sinks::CONSOLE);
+            info!("quiet");
+            warn!(target: "fi", "loud");
+            assert_eq!(fw.stderr(), "WARN  fi: loud\r\n");
+            assert_eq!(fw.serial(), "");
+        }
+
+        let fw = MockFirmware::install();
+        set_log_variable("fs=loud");
+        init(Sinks::SERIAL);
+        assert_eq!(
+            fw.serial(),
+            "WARN  fi_uefi::log: invalid log filter 'loud', logging at info\r\n"
+        );
+        assert_eq!(filter(), Filter::default());
+        set_sinks(Sinks::empty());
+        drop(fw);
+
+        // A bad load option leaves the variable's filter in place.
+        let fw = MockFirmware::install_with(|state| state.load_options = utf16("loglevel=loud"));
+        set_log_variable("warn");
+        init(Sinks::SERIAL);
+        assert_eq!(
+            fw.serial(),
+            "WARN  fi_uefi::log: invalid value 'loud' for option 'loglevel'\r\n"
+        );
+        assert_eq!(filter(), Filter::new(Some(Level::Warn)));
+        set_sinks(Sinks::empty());
+    }
+}
@@ -0,0 +1,362 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+use crate::allocator::PAGE_SIZE;
+use crate::flags::flags;
+use crate::{EFIBootServices, EFIMemoryDescriptor, EFIMemoryType, EFIStatus, MemoryMapMeta};
+
+flags! {
+    pub struct EFIMemoryAttribute(u64) {
+        const UC = 0x1;
+        const WC = 0x2;
+        const WT = 0x4;
+        const WB = 0x8;
+        const UCE = 0x10;
+        const WP = 0x1000;
+        const RP = 0x2000;
+        const XP = 0x4000;
+        const NV = 0x8000;
+        const MORE_RELIABLE = 0x1_0000;
+        const RO = 0x2_0000;
+        const SP = 0x4_0000;
+        const CPU_CRYPTO = 0x8_0000;
+        const ISA_MASK = 0x0fff_f000_0000_0000;
+        const ISA_VALID = 0x4000_0000_0000_0000;
+        const RUNTIME = 0x8000_0000_0000_0000;
+    }
+}
+
+impl EFIMemoryType {
+    /// Memory the OS is free to use once boot services have exited.
+    pub fn is_usable_after_exit(&self) -> bool {
+        matches!(
+            self,
+            EFIMemoryType::EfiConventionalMemory
+                | EFIMemoryType::EfiBootServicesCode
+                | EFIMemoryType::EfiBootServicesData
+        )
+    }
+
+    pub fn is_runtime(&self) -> bool {
+        matches!(
+            self,
+            EFIMemoryType::EfiRuntimeServicesCode | EFIMemoryType::EfiRuntimeServicesData
+        )
+    }
+}
+
+impl EFIMemoryDescriptor {
+    pub fn memory_type(&self) -> Result<EFIMemoryType, u32> {
+        EFIMemoryType::try_from(self.ty)
+    }
+
+    pub fn attributes(&self) -> EFIMemoryAttribute {
+        EFIMemoryAttribute(self.attribute)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.number_of_pages * PAGE_SIZE as u64
+    }
+
+    pub fn physical_end(&self) -> u64 {
+        self.physical_start + self.size()
+    }
+}
+
+/// Owned copy of the firmware memory map.
+///
+/// Descriptors are kept in the firmware layout, `desc_size` apart, so the buffer can be handed
+/// to `set_virtual_address_map` untouched.
+pub struct MemoryMap {
+    // u64 backing keeps every descriptor 8 byte aligned, desc_size is always a multiple of 8.
+    buf: Vec<u64>,
+    meta: MemoryMapMeta,
+}
+
+impl MemoryMap {
+    pub fn snapshot(bs: &EFIBootServices) -> Result<Self, EFIStatus> {
+        let mut needed = bs.memory_map_size();
+        loop {
+            // Allocating the buffer can split a free region, leave room for a few more entries.
+            let size = needed.map_size + 4 * needed.desc_size.max(size_of::<EFIMemoryDescriptor>());
+            let mut buf = vec![0u64; size.div_ceil(size_of::<u64>())];
+            let bytes = unsafe {
+                core::slice::from_raw_parts_mut(
+                    buf.as_mut_ptr() as *mut u8,
+                    buf.len() * size_of::<u64>(),
+                )
+            };
+            match bs.get_memory_map(bytes) {
+                Ok(meta) => return Ok(Self::from_raw_parts(buf, meta)),
+                Err(EFIStatus::BUFFER_TOO_SMALL) => needed = bs.memory_map_size(),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Re-reads the map into the existing buffer without allocating, which is the only thing
+    /// allowed between a failed `exit_boot_services` and the retry.
+    pub fn refresh(&mut self, bs: &EFIBootServices) -> Result<(), EFIStatus> {
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(
+                self.buf.as_mut_ptr() as *mut u8,
+                self.buf.len() * size_of::<u64>(),
+            )
+        };
+        self.meta = bs.get_memory_map(bytes)?;
+        Ok(())
+    }
+
+    /// `meta.map_size` bytes of `buf` must hold descriptors spaced `meta.desc_size` apart.
+    pub fn from_raw_parts(buf: Vec<u64>, meta: MemoryMapMeta) -> Self {
+        assert!(meta.desc_size >= size_of::<EFIMemoryDescriptor>());
+        assert!(meta.desc_size.is_multiple_of(size_of::<u64>()));
+        assert!(meta.map_size <= buf.len() * size_of::<u64>());
+        Self { buf, meta }
+    }
+
+    pub fn meta(&self) -> MemoryMapMeta {
+        self.meta
+    }
+
+    pub fn map_key(&self) -> usize {
+        self.meta.map_key
+    }
+
+    pub fn len(&self) -> usize {
+        self.meta.map_size / self.meta.desc_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Raw descriptor bytes, `desc_size` stride.
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.buf.as_ptr() as *const u8, self.meta.map_size) }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&EFIMemoryDescriptor> {
+        if index >= self.len() {
+            return None;
+        }
+        let offset = index * self.meta.desc_size / size_of::<u64>();
+        Some(unsafe { &*(self.buf.as_ptr().add(offset) as *const EFIMemoryDescriptor) })
+    }
+
+    fn get_mut(&mut self, index: usize) -> &mut EFIMemoryDescriptor {
+        assert!(index < self.len());
+        let offset = index * self.meta.desc_size / size_of::<u64>();
+        unsafe { &mut *(self.buf.as_mut_ptr().add(offset) as *mut EFIMemoryDescriptor) }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        let words = self.meta.desc_size / size_of::<u64>();
+        for i in 0..words {
+            self.buf.swap(a * words + i, b * words + i);
+        }
+    }
+
+    fn copy_within(&mut self, src: usize, dest: usize) {
+        let words = self.meta.desc_size / size_of::<u64>();
+        self.buf
+            .copy_within(src * words..(src + 1) * words, dest * words);
+    }
+
+    pub fn iter(&self) -> MemoryMapIter<'_> {
+        MemoryMapIter {
+            map: self,
+            index: 0,
+        }
+    }
+
+    /// Sorts descriptors by physical start address.
+    pub fn sort(&mut self) {
+        // Insertion sort, firmware maps are short and usually close to sorted already.
+        for i in 1..self.len() {
+            let mut j = i;
+            while j > 0 && self.get_mut(j - 1).physical_start > self.get_mut(j).physical_start {
+                self.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+    }
+
+    /// Sorts, then coalesces physically contiguous descriptors with the same type and
+    /// attributes. The map key is no longer valid for `exit_boot_services` afterwards.
+    pub fn merge(&mut self) {
+        self.sort();
+        if self.is_empty() {
+            return;
+        }
+
+        let mut last = 0;
+        for i in 1..self.len() {
+            let (end, ty, attribute) = {
+                let prev = self.get_mut(last);
+                (prev.physical_end(), prev.ty, prev.attribute)
+            };
+            let cur = self.get_mut(i);
+            if cur.physical_start == end && cur.ty == ty && cur.attribute == attribute {
+                let pages = cur.number_of_pages;
+                self.get_mut(last).number_of_pages += pages;
+            } else {
+                last += 1;
+                if last != i {
+                    self.copy_within(i, last);
+                }
+            }
+        }
+        self.meta.map_size = (last + 1) * self.meta.desc_size;
+    }
+
+    pub fn total_pages_of(&self, ty: EFIMemoryType) -> u64 {
+        self.iter()
+            .filter(|desc| desc.memory_type() == Ok(ty))
+            .map(|desc| desc.number_of_pages)
+            .sum()
+    }
+
+    /// Bytes of `EfiConventionalMemory`.
+    pub fn total_conventional_memory(&self) -> u64 {
+        self.total_pages_of(EFIMemoryType::EfiConventionalMemory) * PAGE_SIZE as u64
+    }
+
+    /// Bytes the OS can reclaim once boot services are gone.
+    pub fn total_usable_after_exit(&self) -> u64 {
+        self.iter()
+            .filter(|desc| desc.memory_type().is_ok_and(|ty| ty.is_usable_after_exit()))
+            .map(|desc| desc.size())
+            .sum()
+    }
+}
+
+pub struct MemoryMapIter<'a> {
+    map: &'a MemoryMap,
+    index: usize,
+}
+
+impl<'a> Iterator for MemoryMapIter<'a> {
+    type Item = &'a EFIMemoryDescriptor;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let desc = self.map.get(self.index)?;
+        self.index += 1;
+        Some(desc)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.map.len() - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl ExactSizeIterator for MemoryMapIter<'_> {}
+
+impl<'a> IntoIterator for &'a MemoryMap {
+    type Item = &'a EFIMemoryDescriptor;
+    type IntoIter = MemoryMapIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Firmware is allowed to report a descriptor bigger than the struct, OVMF uses 48.
+    const DESC_SIZE: usize = 48;
+
+    fn build_map(entries: &[(EFIMemoryType, u64, u64)]) -> MemoryMap {
+        let words = DESC_SIZE / 8;
+        let mut buf = vec![0xdead_beefu64; entries.len() * words];
+        for (i, (ty, start, pages)) in entries.iter().enumerate() {
+            let desc = &mut buf[i * words..];
+            desc[0] = *ty as u64;
+            desc[1] = *start;
+            desc[2] = *start;
+            desc[3] = *pages;
+            desc[4] = EFIMemoryAttribute::WB.bits();
+        }
+        let meta = MemoryMapMeta {
+            map_size: entries.len() * DESC_SIZE,
+            map_key: 7,
+            desc_size: DESC_SIZE,
+            desc_version: 1,
+        };
+        MemoryMap::from_raw_parts(buf, meta)
+    }
+
+    #[test]
+    fn test_iter_uses_desc_size_stride() {
+        let map = build_map(&[
+            (EFIMemoryType::EfiLoaderCode, 0x1000, 1),
+            (EFIMemoryType::EfiConventionalMemory, 0x2000, 4),
+        ]);
+        let descs: Vec<_> = map.iter().collect();
+        assert_eq!(descs.len(), 2);
+        assert_eq!(descs[1].physical_start, 0x2000);
+        assert_eq!(
+            descs[1].memory_type(),
+            Ok(EFIMemoryType::EfiConventionalMemory)
+        );
+        assert!(descs[1].attributes().contains(EFIMemoryAttribute::WB));
+    }
+
+    #[test]
+    fn test_sort_and_merge() {
+        let mut map = build_map(&[
+            (EFIMemoryType::EfiConventionalMemory, 0x4000, 2),
+            (EFIMemoryType::EfiConventionalMemory, 0x1000, 1),
+            (EFIMemoryType::EfiBootServicesData, 0x6000, 1),
+            (EFIMemoryType::EfiConventionalMemory, 0x2000, 2),
+        ]);
+        map.merge();
+        let descs: Vec<_> = map
+            .iter()
+            .map(|d| (d.physical_start, d.number_of_pages))
+            .collect();
+        assert_eq!(descs, [(0x1000, 5), (0x6000, 1)]);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn test_totals() {
+        let map = build_map(&[
+            (EFIMemoryType::EfiConventionalMemory, 0x1000, 3),
+            (EFIMemoryType::EfiBootServicesCode, 0x4000, 1),
+            (EFIMemoryType::EfiACPIMemoryNVS, 0x5000, 1),
+        ]);
+        assert_eq!(map.total_conventional_memory(), 3 * 4096);
+        assert_eq!(map.total_usable_after_exit(), 4 * 4096);
+    }
+
+    #[test]
+    fn test_unknown_memory_type() {
+        let mut map = build_map(&[(EFIMemoryType::EfiLoaderData, 0, 1)]);
+        map.get_mut(0).ty = 0x8000_0001;
+        assert_eq!(map.get(0).unwrap().memory_type(), Err(0x8000_0001));
+    }
+
+    #[test]
+    fn test_snapshot_from_firmware() {
+        let _fw = crate::mock::MockFirmware::install();
+        let bs = EFIBootServices::fetch_global().unwrap();
+        let mut map = MemoryMap::snapshot(bs).unwrap();
+        assert_eq!(map.meta().desc_size, crate::mock::DESC_SIZE);
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.total_conventional_memory(), 0x100 * PAGE_SIZE as u64);
+
+        crate::mock::with_state(|s| {
+            s.memory_map
+                .push((EFIMemoryType::EfiConventionalMemory, 0x200_000, 0x10));
+            s.map_key += 1;
+        });
+        map.refresh(bs).unwrap();
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.map_key(), crate::mock::with_state(|s| s.map_key));
+    }
+}
@@ -0,0 +1,2107 @@
+//! In-memory firmware for host side unit tests.
+//!
+//! [`MockFirmware::install`] builds a system table whose services are Rust stubs and points the
+//! crate globals at it. The stubs keep their state in a process wide static, so installing also
+//! takes a lock that serializes every test using the mock.
+extern crate std;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::ptr;
+use core::sync::atomic::Ordering;
+use std::alloc::{Layout, alloc_zeroed, dealloc};
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::device_path::{DeviceNode, DevicePath, DevicePathBuf};
+use crate::fs::{EFIFileInfoHeader, EFIFileProtocol, EFISimpleFileSystemProtocol};
+use crate::graphics::{
+    BltPixel, EFIGraphicsOutputModeInformation, EFIGraphicsOutputProtocol,
+    EFIGraphicsOutputProtocolMode, PixelBitmask,
+};
+use crate::guid::known;
+use crate::serial::{EFISerialIoMode, EFISerialIoProtocol};
+use crate::{
+    BS, EFIAllocateType, EFIBootServices, EFICapsuleHeader, EFIConfigurationTable, EFIDevicePath,
+    EFIEventNotify, EFIInputKey, EFILoadedImageProtocol, EFIMemoryDescriptor, EFIMemoryType,
+    EFIOpenProtocolInfoEntry, EFIResetType, EFIRuntimeServices, EFIStatus, EFISystemTable, EFITime,
+    EFITimeCapabilities, Event, GUID, Handle, IMAGE_HANDLE, LIP, ST, SimpleInputInterface,
+    SimpleTextOutputInterface, SimpleTextOutputMode, TableHeader, Wchar,
+};
+
+pub const IMAGE: Handle = Handle(0x1000 as *mut c_void);
+pub const CONSOLE_IN: Handle = Handle(0x1001 as *mut c_void);
+pub const CONSOLE_OUT: Handle = Handle(0x1002 as *mut c_void);
+/// Device the image was loaded from, carries the simple file system.
+pub const VOLUME: Handle = Handle(0x1003 as *mut c_void);
+const VOLUME_PATH: &str = "PciRoot(0x0)/Pci(0x1f,0x2)/Sata(0x0,0xffff,0x0)/\
+    HD(1,GPT,8ca5d2b4-9a31-4f5e-8a57-2bd6b3f0c1e2,0x800,0x100000)";
+pub const DISPLAY: Handle = Handle(0x1004 as *mut c_void);
+/// A serial port that takes at most [`SERIAL_CHUNK`] bytes per write.
+pub const SERIAL: Handle = Handle(0x1005 as *mut c_void);
+pub const SERIAL_CHUNK: usize = 16;
+/// Columns and rows of the text modes, 80x25 is the one every console has to support.
+const TEXT_MODES: [(usize, usize); 2] = [(80, 25), (80, 50)];
+pub const WAIT_FOR_KEY: Event = Event(0x2000 as *mut c_void);
+
+/// `(width, height, pixels per scan line, pixel format)`, the last one is blt only.
+const GOP_MODES: [(u32, u32, u32, u32); 3] =
+    [(640, 480, 640, 1), (800, 600, 832, 1), (1024, 768, 1024, 3)];
+
+// Firmware reports a bigger descriptor than the struct, exercise the stride handling.
+pub const DESC_SIZE: usize = 48;
+const PAGE_SIZE: usize = 4096;
+
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+static STATE: Mutex<Option<MockState>> = Mutex::new(None);
+
+pub struct MockState {
+    pub stdout: String,
+    /// What stdout drew where, by `(row, column)`.
+    pub screen: BTreeMap<(usize, usize), char>,
+    pub clear_screens: usize,
+    pub stderr: String,
+    pub keys: VecDeque<EFIInputKey>,
+    /// `(type, physical start, pages)`
+    pub memory_map: Vec<(EFIMemoryType, u64, u64)>,
+    pub map_key: usize,
+    /// How many `exit_boot_services` calls fail with a stale key before one succeeds.
+    pub stale_exits: usize,
+    pub exit_attempts: usize,
+    pub exited: bool,
+    pub pool: HashMap<usize, Layout>,
+    pub pages: HashMap<u64, Layout>,
+    pub protocols: Vec<(Handle, GUID, *mut c_void)>,
+    pub open_protocols: usize,
+    pub next_handle: usize,
+    pub time: EFITime,
+    pub wakeup: Option<EFITime>,
+    /// `(name without terminator, vendor, attributes, data)` in creation order.
+    pub variables: Vec<(Vec<u16>, GUID, u32, Vec<u8>)>,
+    /// `get_next_variable` fills the whole buffer and leaves out the terminator.
+    pub unterminated_names: bool,
+    pub config_tables: Vec<EFIConfigurationTable>,
+    /// Volume contents by absolute path (`\\EFI\\BOOT`), `None` for directories.
+    pub files: BTreeMap<String, Option<Vec<u8>>>,
+    pub open_files: usize,
+    /// Pixels of the current mode, also backs blt only modes.
+    pub framebuffer: Vec<BltPixel>,
+    pub gop_mode: *mut EFIGraphicsOutputProtocolMode,
+    /// Mock clock in 100ns units, moved by `stall` and by waiting on timers.
+    pub clock: u64,
+    pub tpl: usize,
+    pub events: Vec<MockEvent>,
+    pub next_event: usize,
+    /// Loaded child images and their contents, see `start_image` for what they do.
+    pub images: Vec<(Handle, Vec<u8>)>,
+    /// Raw load options of the running image.
+    pub load_options: Vec<u8>,
+    /// Everything written to the serial port.
+    pub serial: Vec<u8>,
+    pub stdout_ptr: *mut SimpleTextOutputInterface,
+}
+
+pub struct MockEvent {
+    pub event: Event,
+    pub ty: u32,
+    notify: Option<(EFIEventNotify, *mut c_void)>,
+    pub signaled: bool,
+    /// `(deadline, period)`, the period is 0 for one shot timers.
+    pub timer: Option<(u64, u64)>,
+}
+
+// The raw pointers in here only ever point at leaked mock tables.
+unsafe impl Send for MockState {}
+
+impl MockState {
+    fn new() -> Self {
+        Self {
+            stdout: String::new(),
+            screen: BTreeMap::new(),
+            clear_screens: 0,
+            stderr: String::new(),
+            keys: VecDeque::new(),
+            memory_map: Vec::from([
+                (EFIMemoryType::EfiBootServicesCode, 0x0, 0x10),
+                (EFIMemoryType::EfiConventionalMemory, 0x10_000, 0x100),
+                (EFIMemoryType::EfiLoaderData, 0x110_000, 0x20),
+                (EFIMemoryType::EfiACPIMemoryNVS, 0x130_000, 0x4),
+            ]),
+            map_key: 1,
+            stale_exits: 0,
+            exit_attempts: 0,
+            exited: false,
+            pool: HashMap::new(),
+            pages: HashMap::new(),
+            protocols: Vec::new(),
+            open_protocols: 0,
+            next_handle: 0x3000,
+            time: EFITime {
+                year: 2024,
+                month: 2,
+                day: 29,
+                hour: 12,
+                minute: 30,
+                second: 15,
+                timezone: 0x7ff,
+                ..EFITime::default()
+            },
+            wakeup: None,
+            variables: Vec::new(),
+            unterminated_names: false,
+            config_tables: Vec::new(),
+            files: BTreeMap::new(),
+            open_files: 0,
+            framebuffer: Vec::new(),
+            gop_mode: ptr::null_mut(),
+            clock: 0,
+            tpl: 4,
+            events: Vec::new(),
+            next_event: 0x4000,
+            images: Vec::new(),
+            load_options: Vec::new(),
+            serial: Vec::new(),
+            stdout_ptr: ptr::null_mut(),
+        }
+    }
+
+    /// Adds a file to the volume along with its parent directories.
+    pub fn add_file(&mut self, path: &str, data: &[u8]) {
+        let mut dir = String::new();
+        let mut parts: Vec<&str> = path.split('\\').filter(|p| !p.is_empty()).collect();
+        let name = parts.pop().expect("empty path");
+        for part in parts {
+            dir.push('\\');
+            dir.push_str(part);
+            self.files.insert(dir.clone(), None);
+        }
+        self.files
+            .insert(format!("{dir}\\{name}"), Some(data.to_vec()));
+    }
+}
+
+pub fn with_state<R>(f: impl FnOnce(&mut MockState) -> R) -> R {
+    let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    f(state.as_mut().expect("mock firmware not installed"))
+}
+
+fn header(signature: u64) -> TableHeader {
+    TableHeader {
+        signature,
+        revision: 2 << 16 | 70,
+        header_size: 0,
+        crc_32: 0,
+        reserved: 0,
+    }
+}
+
+fn decode_utf16(s: *const Wchar) -> String {
+    let s = unsafe { utf16_slice(s) };
+    char::decode_utf16(s.iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+unsafe fn utf16_slice<'a>(s: *const Wchar) -> &'a [u16] {
+    let mut len = 0;
+    unsafe {
+        while *s.add(len) != 0 {
+            len += 1;
+        }
+        core::slice::from_raw_parts(s, len)
+    }
+}
+
+/// Installed firmware, the globals are cleared again on drop.
+pub struct MockFirmware {
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl MockFirmware {
+    pub fn install() -> Self {
+        Self::install_with(|_| {})
+    }
+
+    /// `setup` runs on the fresh state before the globals are pointed at the mock.
+    pub fn install_with(setup: impl FnOnce(&mut MockState)) -> Self {
+        let lock = TEST_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        let mode = Box::into_raw(Box::new(SimpleTextOutputMode {
+            max_mode: TEXT_MODES.len() as i32,
+            mode: 0,
+            attribute: 0x07,
+            cursor_column: 0,
+            cursor_row: 0,
+            cursor_visible: 1,
+        }));
+        let stdout = Box::into_raw(Box::new(text_output(mode)));
+        let stderr = Box::into_raw(Box::new(text_output(mode)));
+        let stdin = Box::into_raw(Box::new(SimpleInputInterface {
+            reset: input_reset,
+            read_key_stroke,
+            wait_for_key: WAIT_FOR_KEY,
+        }));
+        let rt = Box::into_raw(Box::new(runtime_services()));
+        let bs = Box::into_raw(Box::new(boot_services()));
+        let st = Box::into_raw(Box::new(EFISystemTable {
+            header: header(0x5453_5953_2049_4249),
+            firmware_vendor: ptr::null_mut(),
+            firmware_revision: 0x10000,
+            stdin_handle: CONSOLE_IN,
+            stdin,
+            stdout_handle: CONSOLE_OUT,
+            stdout,
+            stderr_handle: CONSOLE_OUT,
+            stderr,
+            runtime_services: rt,
+            boot_services: bs,
+            number_of_table_entries: 0,
+            configuration_table: ptr::null_mut(),
+        }));
+        let lip = Box::into_raw(Box::new(EFILoadedImageProtocol {
+            revision: 0x1000,
+            parent_handle: Handle::null(),
+            system_table: st as *mut c_void,
+            device_handle: VOLUME,
+            file_path: ptr::null_mut(),
+            reserved: ptr::null_mut(),
+            load_options_size: 0,
+            load_options: ptr::null_mut(),
+            image_base: ptr::null_mut(),
+            image_size: 0,
+            image_code_type: EFIMemoryType::EfiLoaderCode as u32,
+            image_data_type: EFIMemoryType::EfiLoaderData as u32,
+        }));
+
+        let fs = Box::into_raw(Box::new(EFISimpleFileSystemProtocol {
+            revision: 0x10000,
+            open_volume,
+        }));
+        let infos = Box::leak(Box::new(GOP_MODES.map(
+            |(width, height, stride, format)| EFIGraphicsOutputModeInformation {
+                version: 0,
+                horizontal_resolution: width,
+                vertical_resolution: height,
+                pixel_format: format,
+                pixel_information: PixelBitmask::default(),
+                pixels_per_scan_line: stride,
+            },
+        )));
+        let gop_mode = Box::into_raw(Box::new(EFIGraphicsOutputProtocolMode {
+            max_mode: GOP_MODES.len() as u32,
+            mode: 0,
+            info: infos.as_mut_ptr(),
+            size_of_info: size_of::<EFIGraphicsOutputModeInformation>(),
+            frame_buffer_base: 0,
+            frame_buffer_size: 0,
+        }));
+        let gop = Box::into_raw(Box::new(EFIGraphicsOutputProtocol {
+            query_mode: gop_query_mode,
+            set_mode: gop_set_mode,
+            blt: gop_blt,
+            mode: gop_mode,
+        }));
+
+        let serial = Box::into_raw(Box::new(EFISerialIoProtocol {
+            revision: 0x10000,
+            reset: serial_reset,
+            set_attributes: serial_set_attributes,
+            set_control: serial_set_control,
+            get_control: serial_get_control,
+            write: serial_write,
+            read: serial_read,
+            mode: Box::into_raw(Box::new(EFISerialIoMode {
+                baud_rate: 115_200,
+                data_bits: 8,
+                ..EFISerialIoMode::default()
+            })),
+        }));
+
+        let volume_path = DevicePathBuf::from_text(VOLUME_PATH).unwrap();
+        let volume_path = volume_path
+            .as_path()
+            .as_bytes()
+            .to_vec()
+            .leak()
+            .as_mut_ptr();
+
+        let mut state = MockState::new();
+        state.stdout_ptr = stdout;
+        state.gop_mode = gop_mode;
+        switch_gop_mode(&mut state, 0);
+        state.protocols.extend([
+            (IMAGE, known::LOADED_IMAGE_PROTOCOL, lip as *mut c_void),
+            (
+                CONSOLE_IN,
+                known::SIMPLE_TEXT_INPUT_PROTOCOL,
+                stdin as *mut c_void,
+            ),
+            (
+                CONSOLE_OUT,
+                known::SIMPLE_TEXT_OUTPUT_PROTOCOL,
+                stdout as *mut c_void,
+            ),
+            (
+                VOLUME,
+                known::SIMPLE_FILE_SYSTEM_PROTOCOL,
+                fs as *mut c_void,
+            ),
+            (
+                VOLUME,
+                known::DEVICE_PATH_PROTOCOL,
+                volume_path as *mut c_void,
+            ),
+            (DISPLAY, known::GRAPHICS_OUTPUT_PROTOCOL, gop as *mut c_void),
+            (SERIAL, known::SERIAL_IO_PROTOCOL, serial as *mut c_void),
+        ]);
+        setup(&mut state);
+        if !state.load_options.is_empty() {
+            let options = state.load_options.clone().leak();
+            unsafe {
+                (*lip).load_options_size = options.len() as u32;
+                (*lip).load_options = options.as_mut_ptr() as *mut c_void;
+            }
+        }
+        unsafe {
+            let tables = Box::leak(state.config_tables.clone().into_boxed_slice());
+            (*st).number_of_table_entries = tables.len() as u64;
+            (*st).configuration_table = tables.as_mut_ptr();
+        }
+        *STATE.lock().unwrap_or_else(|e| e.into_inner()) = Some(state);
+
+        unsafe {
+            EFILoadedImageProtocol::from_image_handle(IMAGE);
+            EFISystemTable::set_system_table(st);
+        }
+
+        Self { _lock: lock }
+    }
+
+    pub fn stdout(&self) -> String {
+        with_state(|s| s.stdout.clone())
+    }
+
+    /// Row `row` of the mock screen, without trailing blanks.
+    pub fn screen_row(&self, row: usize) -> String {
+        let line: String = with_state(|s| {
+            (0..TEXT_MODES[0].0)
+                .map(|column| s.screen.get(&(row, column)).copied().unwrap_or(' '))
+                .collect()
+        });
+        String::from(line.trim_end())
+    }
+
+    pub fn stderr(&self) -> String {
+        with_state(|s| s.stderr.clone())
+    }
+
+    pub fn serial(&self) -> String {
+        with_state(|s| String::from_utf8_lossy(&s.serial).into_owned())
+    }
+
+    pub fn push_keys(&self, s: &str) {
+        with_state(|state| {
+            state.keys.extend(s.encode_utf16().map(|c| EFIInputKey {
+                scan_code: 0,
+                unicode_char: c,
+            }))
+        });
+    }
+
+    pub fn push_scan_code(&self, scan_code: u16) {
+        with_state(|state| {
+            state.keys.push_back(EFIInputKey {
+                scan_code,
+                unicode_char: 0,
+            })
+        });
+    }
+}
+
+impl Drop for MockFirmware {
+    fn drop(&mut self) {
+        ST.store(ptr::null_mut(), Ordering::Release);
+        BS.store(ptr::null_mut(), Ordering::Release);
+        LIP.store(ptr::null_mut(), Ordering::Release);
+        IMAGE_HANDLE.store(ptr::null_mut(), Ordering::Release);
+        // Tables and outstanding allocations are leaked, tests are short lived.
+        *STATE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+// Simple text output
+
+fn text_output(mode: *mut SimpleTextOutputMode) -> SimpleTextOutputInterface {
+    SimpleTextOutputInterface {
+        reset: output_reset,
+        output_string,
+        test_string,
+        query_mode,
+        set_mode,
+        set_attribute,
+        clear_screen,
+        set_cursor_position,
+        enable_cursor,
+        mode,
+    }
+}
+
+unsafe extern "efiapi" fn output_reset(_: *mut SimpleTextOutputInterface, _: u8) -> EFIStatus {
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn output_string(
+    this: *mut SimpleTextOutputInterface,
+    string: *mut Wchar,
+) -> EFIStatus {
+    let s = decode_utf16(string);
+    let mode = unsafe { &mut *(*this).mode };
+    with_state(|state| {
+        if this == state.stdout_ptr {
+            for c in s.chars() {
+                match c {
+                    '\r' => mode.cursor_column = 0,
+                    '\n' => mode.cursor_row += 1,
+                    c => {
+                        let at = (mode.cursor_row as usize, mode.cursor_column as usize);
+                        state.screen.insert(at, c);
+                        mode.cursor_column += 1;
+                    }
+                }
+            }
+            state.stdout.push_str(&s);
+        } else {
+            state.stderr.push_str(&s);
+        }
+    });
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn test_string(
+    _: *mut SimpleTextOutputInterface,
+    _: *mut Wchar,
+) -> EFIStatus {
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn query_mode(
+    _: *mut SimpleTextOutputInterface,
+    mode: u64,
+    columns: *mut usize,
+    rows: *mut usize,
+) -> EFIStatus {
+    let Some((c, r)) = TEXT_MODES.get(mode as usize) else {
+        return EFIStatus::UNSUPPORTED;
+    };
+    unsafe {
+        *columns = *c;
+        *rows = *r;
+    }
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn set_mode(this: *mut SimpleTextOutputInterface, mode: u64) -> EFIStatus {
+    let output_mode = unsafe { &mut *(*this).mode };
+    if mode >= output_mode.max_mode as u64 {
+        return EFIStatus::UNSUPPORTED;
+    }
+    output_mode.mode = mode as i32;
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn set_attribute(
+    this: *mut SimpleTextOutputInterface,
+    attribute: u64,
+) -> EFIStatus {
+    unsafe { (*(*this).mode).attribute = attribute as i32 };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn clear_screen(this: *mut SimpleTextOutputInterface) -> EFIStatus {
+    with_state(|state| {
+        state.screen.clear();
+        state.clear_screens += 1;
+    });
+    let mode = unsafe { &mut *(*this).mode };
+    mode.cursor_column = 0;
+    mode.cursor_row = 0;
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn set_cursor_position(
+    this: *mut SimpleTextOutputInterface,
+    column: u64,
+    row: u64,
+) -> EFIStatus {
+    let mode = unsafe { &mut *(*this).mode };
+    mode.cursor_column = column as i32;
+    mode.cursor_row = row as i32;
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn enable_cursor(
+    this: *mut SimpleTextOutputInterface,
+    enable: u8,
+) -> EFIStatus {
+    unsafe { (*(*this).mode).cursor_visible = enable };
+    EFIStatus::SUCCESS
+}
+
+// Simple text input
+
+unsafe extern "efiapi" fn input_reset(_: *mut SimpleInputInterface, _: u8) -> EFIStatus {
+    with_state(|state| state.keys.clear());
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn read_key_stroke(
+    _: *mut SimpleInputInterface,
+    key: *mut EFIInputKey,
+) -> EFIStatus {
+    match with_state(|state| state.keys.pop_front()) {
+        Some(k) => {
+            unsafe { *key = k };
+            EFIStatus::SUCCESS
+        }
+        None => EFIStatus::NOT_READY,
+    }
+}
+
+// Runtime services
+
+fn runtime_services() -> EFIRuntimeServices {
+    EFIRuntimeServices {
+        header: header(0x5652_4553_544e_5552),
+        get_time,
+        set_time,
+        get_wakeup_time,
+        set_wakeup_time,
+        set_virtual_address_map,
+        convert_pointer,
+        get_variable,
+        get_next_variable,
+        set_variable,
+        get_next_high_mono,
+        reset_system_type,
+        update_capsule,
+        query_capsule_capabilities,
+        query_variable_info,
+    }
+}
+
+unsafe extern "efiapi" fn get_time(
+    time: *mut EFITime,
+    capabilities: *mut EFITimeCapabilities,
+) -> EFIStatus {
+    unsafe {
+        *time = with_state(|state| state.time);
+        if !capabilities.is_null() {
+            *capabilities = EFITimeCapabilities {
+                resolution: 1,
+                accuracy: 50_000_000,
+                sets_to_zero: 0,
+            };
+        }
+    }
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn set_time(time: *mut EFITime) -> EFIStatus {
+    let time = unsafe { *time };
+    if !time.is_valid() {
+        return EFIStatus::INVALID_PARAMETER;
+    }
+    with_state(|state| state.time = time);
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn get_wakeup_time(
+    enabled: *mut u8,
+    pending: *mut u8,
+    time: *mut EFITime,
+) -> EFIStatus {
+    let wakeup = with_state(|state| state.wakeup);
+    unsafe {
+        *enabled = wakeup.is_some() as u8;
+        *pending = 0;
+        *time = wakeup.unwrap_or_default();
+    }
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn set_wakeup_time(enable: u8, time: *mut EFITime) -> EFIStatus {
+    let wakeup = match enable {
+        0 => None,
+        _ => Some(unsafe { *time }),
+    };
+    with_state(|state| state.wakeup = wakeup);
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn set_virtual_address_map(
+    _: u64,
+    _: u64,
+    _: u32,
+    _: *mut EFIMemoryDescriptor,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+unsafe extern "efiapi" fn convert_pointer(_: u64, _: *mut *mut c_void) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+const VARIABLE_STORAGE: u64 = 0x1_0000;
+const MAX_VARIABLE_SIZE: u64 = 0x8000;
+const APPEND_WRITE: u32 = 0x40;
+
+fn find_variable(state: &MockState, name: &[u16], vendor: &GUID) -> Option<usize> {
+    state
+        .variables
+        .iter()
+        .position(|(n, v, _, _)| n == name && v == vendor)
+}
+
+unsafe extern "efiapi" fn get_variable(
+    name: *const Wchar,
+    vendor: *const GUID,
+    attributes: *mut u32,
+    size: *mut u64,
+    data: *mut c_void,
+) -> EFIStatus {
+    let name = unsafe { utf16_slice(name) };
+    with_state(|state| unsafe {
+        let Some(i) = find_variable(state, name, &*vendor) else {
+            return EFIStatus::NOT_FOUND;
+        };
+        let (_, _, attrs, value) = &state.variables[i];
+        let available = *size;
+        *size = value.len() as u64;
+        if available < value.len() as u64 {
+            return EFIStatus::BUFFER_TOO_SMALL;
+        }
+        if !attributes.is_null() {
+            *attributes = *attrs;
+        }
+        ptr::copy_nonoverlapping(value.as_ptr(), data as *mut u8, value.len());
+        EFIStatus::SUCCESS
+    })
+}
+
+unsafe extern "efiapi" fn get_next_variable(
+    size: *mut u64,
+    name: *mut Wchar,
+    vendor: *mut GUID,
+) -> EFIStatus {
+    let current = unsafe { utf16_slice(name) };
+    with_state(|state| unsafe {
+        let next = if current.is_empty() {
+            0
+        } else {
+            match find_variable(state, current, &*vendor) {
+                Some(i) => i + 1,
+                None => return EFIStatus::INVALID_PARAMETER,
+            }
+        };
+        let Some((next_name, next_vendor, _, _)) = state.variables.get(next) else {
+            return EFIStatus::NOT_FOUND;
+        };
+        let needed = (next_name.len() as u64 + 1) * 2;
+        if *size < needed {
+            *size = needed;
+            return EFIStatus::BUFFER_TOO_SMALL;
+        }
+        ptr::copy_nonoverlapping(next_name.as_ptr(), name, next_name.len());
+        if state.unterminated_names {
+            let units = *size as usize / 2;
+            for i in next_name.len()..units {
+                *name.add(i) = '?' as u16;
+            }
+            *size = units as u64 * 2;
+        } else {
+            *name.add(next_name.len()) = 0;
+            *size = needed;
+        }
+        *vendor = *next_vendor;
+        EFIStatus::SUCCESS
+    })
+}
+
+unsafe extern "efiapi" fn set_variable(
+    name: *mut Wchar,
+    vendor: *mut GUID,
+    attributes: u32,
+    size: u64,
+    data: *mut c_void,
+) -> EFIStatus {
+    let name = unsafe { utf16_slice(name) };
+    let data = unsafe { core::slice::from_raw_parts(data as *const u8, size as usize) };
+    let vendor = unsafe { *vendor };
+    with_state(|state| {
+        let existing = find_variable(state, name, &vendor);
+        if attributes == 0 || (data.is_empty() && attributes & APPEND_WRITE == 0) {
+            return match existing {
+                Some(i) => {
+                    state.variables.remove(i);
+                    EFIStatus::SUCCESS
+                }
+                None => EFIStatus::NOT_FOUND,
+            };
+        }
+        if size > MAX_VARIABLE_SIZE {
+            return EFIStatus::OUT_OF_RESOURCES;
+        }
+        match existing {
+            Some(i) if attributes & APPEND_WRITE != 0 => {
+                state.variables[i].3.extend_from_slice(data)
+            }
+            Some(i) => {
+                state.variables[i].2 = attributes;
+                state.variables[i].3 = data.to_vec();
+            }
+            None => state.variables.push((
+                name.to_vec(),
+                vendor,
+                attributes & !APPEND_WRITE,
+                data.to_vec(),
+            )),
+        }
+        EFIStatus::SUCCESS
+    })
+}
+
+unsafe extern "efiapi" fn get_next_high_mono(count: *mut u64) -> EFIStatus {
+    unsafe { *count = 1 };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn reset_system_type(
+    _: EFIResetType,
+    _: u64,
+    _: u64,
+    _: *mut Wchar,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+unsafe extern "efiapi" fn update_capsule(
+    _: *mut *mut EFICapsuleHeader,
+    _: u64,
+    _: u64,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+unsafe extern "efiapi" fn query_capsule_capabilities(
+    _: *mut *mut EFICapsuleHeader,
+    _: u64,
+    _: u64,
+    _: *mut EFIResetType,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+unsafe extern "efiapi" fn query_variable_info(
+    _: u32,
+    max_storage: *mut u64,
+    remaining_storage: *mut u64,
+    max_variable_size: *mut u64,
+) -> EFIStatus {
+    let used: u64 = with_state(|state| {
+        state
+            .variables
+            .iter()
+            .map(|(name, _, _, data)| (name.len() * 2 + data.len()) as u64)
+            .sum()
+    });
+    unsafe {
+        *max_storage = VARIABLE_STORAGE;
+        *remaining_storage = VARIABLE_STORAGE.saturating_sub(used);
+        *max_variable_size = MAX_VARIABLE_SIZE;
+    }
+    EFIStatus::SUCCESS
+}
+
+// Boot services
+
+fn boot_services() -> EFIBootServices {
+    unsafe extern "C" fn multiple_protocol_interfaces() -> EFIStatus {
+        EFIStatus::UNSUPPORTED
+    }
+
+    EFIBootServices {
+        hdr: header(0x5652_4553_544f_4f42),
+        raise_tpl,
+        restor_tpl,
+        allocate_pages,
+        free_pages,
+        get_memory_map,
+        allocate_pool,
+        free_pool,
+        create_event,
+        set_timer,
+        wait_for_event,
+        signal_event,
+        close_event,
+        check_event,
+        install_protocol_interface,
+        reinstall_protocol_interface,
+        uninstall_protocol_interface,
+        handle_protocol,
+        reserved: ptr::null_mut(),
+        register_protocol_notify,
+        locate_handle,
+        locate_device_path,
+        install_configuration_table,
+        load_image,
+        start_image,
+        exit,
+        unload_image,
+        exit_boot_services,
+        get_next_monotonic_count,
+        stall,
+        set_watchdog_timer,
+        connect_controller,
+        disconnect_controller,
+        open_protocol,
+        close_protocol,
+        open_protocol_information,
+        protocols_per_handle,
+        locate_handle_buffer,
+        locate_protocol,
+        // Variadic functions cannot be defined on stable, these are never called by the crate.
+        install_multiple_protocol_interfaces: unsafe {
+            core::mem::transmute::<
+                unsafe extern "C" fn() -> EFIStatus,
+                unsafe extern "C" fn(*mut Handle, ...) -> EFIStatus,
+            >(multiple_protocol_interfaces)
+        },
+        uninstall_multiple_protocol_interfaces: unsafe {
+            core::mem::transmute::<
+                unsafe extern "C" fn() -> EFIStatus,
+                unsafe extern "C" fn(Handle, ...) -> EFIStatus,
+            >(multiple_protocol_interfaces)
+        },
+        calculate_crc32,
+        copy_mem,
+        set_mem,
+        create_event_ex,
+    }
+}
+
+unsafe extern "efiapi" fn raise_tpl(new_tpl: usize) -> usize {
+    with_state(|state| core::mem::replace(&mut state.tpl, new_tpl))
+}
+
+unsafe extern "efiapi" fn restor_tpl(old_tpl: usize) -> usize {
+    with_state(|state| state.tpl = old_tpl);
+    0
+}
+
+unsafe extern "efiapi" fn allocate_pages(
+    alloc_ty: EFIAllocateType,
+    _: EFIMemoryType,
+    count: usize,
+    addr: *mut u64,
+) -> EFIStatus {
+    if !matches!(alloc_ty, EFIAllocateType::AllocateAnyPages) {
+        return EFIStatus::UNSUPPORTED;
+    }
+    let layout = Layout::from_size_align(count * PAGE_SIZE, PAGE_SIZE).unwrap();
+    let ptr = unsafe { alloc_zeroed(layout) };
+    if ptr.is_null() {
+        return EFIStatus::OUT_OF_RESOURCES;
+    }
+    with_state(|state| {
+        state.pages.insert(ptr as u64, layout);
+        state.map_key += 1;
+    });
+    unsafe { *addr = ptr as u64 };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn free_pages(addr: u64, pages: usize) -> EFIStatus {
+    match with_state(|state| state.pages.remove(&addr)) {
+        Some(layout) if layout.size() == pages * PAGE_SIZE => {
+            unsafe { dealloc(addr as *mut u8, layout) };
+            EFIStatus::SUCCESS
+        }
+        _ => EFIStatus::NOT_FOUND,
+    }
+}
+
+unsafe extern "efiapi" fn get_memory_map(
+    size: *mut usize,
+    map: *mut EFIMemoryDescriptor,
+    key: *mut usize,
+    desc_size: *mut usize,
+    desc_version: *mut u32,
+) -> EFIStatus {
+    with_state(|state| unsafe {
+        let needed = state.memory_map.len() * DESC_SIZE;
+        *desc_size = DESC_SIZE;
+        *desc_version = 1;
+        if *size < needed || map.is_null() {
+            *size = needed;
+            return EFIStatus::BUFFER_TOO_SMALL;
+        }
+        for (i, (ty, start, pages)) in state.memory_map.iter().enumerate() {
+            let desc = (map as *mut u8).add(i * DESC_SIZE) as *mut EFIMemoryDescriptor;
+            desc.write(EFIMemoryDescriptor {
+                ty: *ty as u32,
+                pad: 0,
+                physical_start: *start,
+                virtual_start: 0,
+                number_of_pages: *pages,
+                attribute: 0xf,
+            });
+        }
+        *size = needed;
+        *key = state.map_key;
+        EFIStatus::SUCCESS
+    })
+}
+
+unsafe extern "efiapi" fn allocate_pool(
+    _: u32,
+    size: usize,
+    buffer: *mut *mut c_void,
+) -> EFIStatus {
+    let layout = Layout::from_size_align(size.max(1), 8).unwrap();
+    let ptr = unsafe { alloc_zeroed(layout) };
+    if ptr.is_null() {
+        return EFIStatus::OUT_OF_RESOURCES;
+    }
+    with_state(|state| {
+        state.pool.insert(ptr as usize, layout);
+        state.map_key += 1;
+    });
+    unsafe { *buffer = ptr as *mut c_void };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn free_pool(buffer: *mut c_void) -> EFIStatus {
+    match with_state(|state| state.pool.remove(&(buffer as usize))) {
+        Some(layout) => {
+            unsafe { dealloc(buffer as *mut u8, layout) };
+            EFIStatus::SUCCESS
+        }
+        None => EFIStatus::INVALID_PARAMETER,
+    }
+}
+
+const EVT_TIMER: u32 = 0x8000_0000;
+const EVT_NOTIFY_WAIT: u32 = 0x100;
+const EVT_NOTIFY_SIGNAL: u32 = 0x200;
+const TPL_APPLICATION: usize = 4;
+// Waiting gives up after this many timer expiries without the awaited event firing.
+const MAX_WAIT_STEPS: usize = 10_000;
+
+/// A notify function to run once the state lock is released, it may call back into the mock.
+type Notify = (EFIEventNotify, Event, *mut c_void);
+
+fn run_notifies(notifies: Vec<Notify>) {
+    for (notify, event, ctx) in notifies {
+        unsafe { notify(event, ctx) };
+    }
+}
+
+fn find_event(state: &mut MockState, event: Event) -> Option<&mut MockEvent> {
+    state.events.iter_mut().find(|e| e.event == event)
+}
+
+/// Signal events hand their notify back instead of staying signaled, like the firmware does
+/// once the notify is queued.
+fn signal(state: &mut MockState, event: Event) -> Option<Notify> {
+    let e = find_event(state, event)?;
+    if e.ty & EVT_NOTIFY_SIGNAL != 0 {
+        return e.notify.map(|(notify, ctx)| (notify, event, ctx));
+    }
+    e.signaled = true;
+    None
+}
+
+fn next_deadline(state: &MockState) -> Option<u64> {
+    state
+        .events
+        .iter()
+        .filter_map(|e| e.timer.map(|(deadline, _)| deadline))
+        .min()
+}
+
+/// Moves the clock to `to` and fires every timer due by then.
+fn advance_clock(state: &mut MockState, to: u64) -> Vec<Notify> {
+    state.clock = state.clock.max(to);
+    let mut due = Vec::new();
+    for e in &mut state.events {
+        if let Some((deadline, period)) = e.timer
+            && deadline <= to
+        {
+            e.timer = (period != 0).then_some((deadline + period, period));
+            due.push(e.event);
+        }
+    }
+    due.into_iter().filter_map(|e| signal(state, e)).collect()
+}
+
+/// NOTIFY_WAIT events run their notify while they are checked or waited on and not signaled.
+fn wait_notifies(state: &mut MockState, events: &[Event]) -> Vec<Notify> {
+    events
+        .iter()
+        .filter_map(|event| {
+            let e = find_event(state, *event)?;
+            if e.ty & EVT_NOTIFY_WAIT == 0 || e.signaled {
+                return None;
+            }
+            e.notify.map(|(notify, ctx)| (notify, *event, ctx))
+        })
+        .collect()
+}
+
+/// Reads and clears the signaled state, keys count as long as there are any.
+fn take_signal(state: &mut MockState, event: Event) -> bool {
+    if event == WAIT_FOR_KEY {
+        return !state.keys.is_empty();
+    }
+    find_event(state, event).is_some_and(|e| core::mem::take(&mut e.signaled))
+}
+
+unsafe extern "efiapi" fn create_event(
+    ty: u32,
+    _: u64,
+    notify: Option<EFIEventNotify>,
+    ctx: *mut c_void,
+    out: *mut Event,
+) -> EFIStatus {
+    if ty & (EVT_NOTIFY_WAIT | EVT_NOTIFY_SIGNAL) != 0 && notify.is_none() {
+        return EFIStatus::INVALID_PARAMETER;
+    }
+    let event = with_state(|state| {
+        let event = Event(state.next_event as *mut c_void);
+        state.next_event += 1;
+        state.events.push(MockEvent {
+            event,
+            ty,
+            notify: notify.map(|n| (n, ctx)),
+            signaled: false,
+            timer: None,
+        });
+        event
+    });
+    unsafe { *out = event };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn set_timer(event: Event, ty: u32, trigger: u64) -> EFIStatus {
+    with_state(|state| {
+        let now = state.clock;
+        let Some(e) = find_event(state, event).filter(|e| e.ty & EVT_TIMER != 0) else {
+            return EFIStatus::INVALID_PARAMETER;
+        };
+        e.timer = match ty {
+            0 => None,
+            1 => Some((now + trigger.max(1), trigger.max(1))),
+            2 => Some((now + trigger, 0)),
+            _ => return EFIStatus::INVALID_PARAMETER,
+        };
+        EFIStatus::SUCCESS
+    })
+}
+
+/// Jumps the clock from timer to timer until one of `events` is signaled. Gives up with
+/// `NOT_READY` instead of hanging when nothing is left to fire.
+unsafe extern "efiapi" fn wait_for_event(
+    count: usize,
+    events: *const Event,
+    index: *mut usize,
+) -> EFIStatus {
+    let events = unsafe { core::slice::from_raw_parts(events, count) };
+    let invalid = with_state(|state| {
+        state.tpl != TPL_APPLICATION
+            || events
+                .iter()
+                .any(|e| find_event(state, *e).is_some_and(|e| e.ty & EVT_NOTIFY_SIGNAL != 0))
+    });
+    if invalid {
+        return EFIStatus::UNSUPPORTED;
+    }
+    for _ in 0..MAX_WAIT_STEPS {
+        run_notifies(with_state(|state| wait_notifies(state, events)));
+        let ready = with_state(|state| events.iter().position(|e| take_signal(state, *e)));
+        if let Some(i) = ready {
+            unsafe { *index = i };
+            return EFIStatus::SUCCESS;
+        }
+        let fired = with_state(|state| next_deadline(state).map(|t| advance_clock(state, t)));
+        match fired {
+            Some(notifies) => run_notifies(notifies),
+            None => break,
+        }
+    }
+    EFIStatus::NOT_READY
+}
+
+unsafe extern "efiapi" fn signal_event(event: Event) -> EFIStatus {
+    let known = with_state(|state| find_event(state, event).is_some());
+    if !known {
+        return EFIStatus::INVALID_PARAMETER;
+    }
+    run_notifies(
+        with_state(|state| signal(state, event))
+            .into_iter()
+            .collect(),
+    );
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn close_event(event: Event) -> EFIStatus {
+    with_state(|state| {
+        let before = state.events.len();
+        state.events.retain(|e| e.event != event);
+        if state.events.len() == before {
+            EFIStatus::INVALID_PARAMETER
+        } else {
+            EFIStatus::SUCCESS
+        }
+    })
+}
+
+unsafe extern "efiapi" fn check_event(event: Event) -> EFIStatus {
+    let signal_type =
+        with_state(|state| find_event(state, event).is_some_and(|e| e.ty & EVT_NOTIFY_SIGNAL != 0));
+    if signal_type {
+        return EFIStatus::INVALID_PARAMETER;
+    }
+    run_notifies(with_state(|state| wait_notifies(state, &[event])));
+    if with_state(|state| take_signal(state, event)) {
+        EFIStatus::SUCCESS
+    } else {
+        EFIStatus::NOT_READY
+    }
+}
+
+unsafe extern "efiapi" fn install_protocol_interface(
+    handle: *mut Handle,
+    guid: *const GUID,
+    _: u32,
+    interface: *const c_void,
+) -> EFIStatus {
+    with_state(|state| unsafe {
+        if (*handle).is_null() {
+            *handle = Handle(state.next_handle as *mut c_void);
+            state.next_handle += 1;
+        }
+        state.protocols.push((*handle, *guid, interface.cast_mut()));
+    });
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn reinstall_protocol_interface(
+    handle: Handle,
+    guid: *const GUID,
+    old: *const c_void,
+    new: *const c_void,
+) -> EFIStatus {
+    let guid = unsafe { *guid };
+    with_state(|state| {
+        match state
+            .protocols
+            .iter_mut()
+            .find(|(h, g, i)| *h == handle && *g == guid && *i == old.cast_mut())
+        {
+            Some(entry) => {
+                entry.2 = new.cast_mut();
+                EFIStatus::SUCCESS
+            }
+            None => EFIStatus::NOT_FOUND,
+        }
+    })
+}
+
+unsafe extern "efiapi" fn uninstall_protocol_interface(
+    handle: Handle,
+    guid: *const GUID,
+    interface: *const c_void,
+) -> EFIStatus {
+    let guid = unsafe { *guid };
+    with_state(|state| {
+        let before = state.protocols.len();
+        state
+            .protocols
+            .retain(|(h, g, i)| !(*h == handle && *g == guid && *i == interface.cast_mut()));
+        if state.protocols.len() == before {
+            EFIStatus::NOT_FOUND
+        } else {
+            EFIStatus::SUCCESS
+        }
+    })
+}
+
+fn find_protocol(handle: Handle, guid: &GUID) -> Option<*mut c_void> {
+    with_state(|state| {
+        state
+            .protocols
+            .iter()
+            .find(|(h, g, _)| *h == handle && g == guid)
+            .map(|(_, _, i)| *i)
+    })
+}
+
+unsafe extern "efiapi" fn handle_protocol(
+    handle: Handle,
+    guid: *const GUID,
+    out: *mut *mut c_void,
+) -> EFIStatus {
+    match find_protocol(handle, unsafe { &*guid }) {
+        Some(interface) => {
+            unsafe { *out = interface };
+            EFIStatus::SUCCESS
+        }
+        None => EFIStatus::UNSUPPORTED,
+    }
+}
+
+unsafe extern "efiapi" fn register_protocol_notify(
+    _: *const GUID,
+    _: Event,
+    _: *mut *mut c_void,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+fn handles_for(guid: &GUID) -> Vec<Handle> {
+    with_state(|state| {
+        state
+            .protocols
+            .iter()
+            .filter(|(_, g, _)| g == guid)
+            .map(|(h, _, _)| *h)
+            .collect()
+    })
+}
+
+unsafe extern "efiapi" fn locate_handle(
+    _: i32,
+    guid: *const GUID,
+    _: *const c_void,
+    size: *mut usize,
+    buf: *mut Handle,
+) -> EFIStatus {
+    let handles = handles_for(unsafe { &*guid });
+    if handles.is_empty() {
+        return EFIStatus::NOT_FOUND;
+    }
+    let needed = handles.len() * size_of::<Handle>();
+    unsafe {
+        if *size < needed {
+            *size = needed;
+            return EFIStatus::BUFFER_TOO_SMALL;
+        }
+        ptr::copy_nonoverlapping(handles.as_ptr(), buf, handles.len());
+        *size = needed;
+    }
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn locate_device_path(
+    _: *const GUID,
+    _: *mut *const EFIDevicePath,
+    _: *mut Handle,
+) -> EFIStatus {
+    EFIStatus::NOT_FOUND
+}
+
+unsafe extern "efiapi" fn install_configuration_table(
+    _: *const GUID,
+    _: *const c_void,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+fn child_image(parent: Handle, device: Handle) -> EFILoadedImageProtocol {
+    EFILoadedImageProtocol {
+        revision: 0x1000,
+        parent_handle: parent,
+        system_table: ST.load(Ordering::Acquire) as *mut c_void,
+        device_handle: device,
+        file_path: ptr::null_mut(),
+        reserved: ptr::null_mut(),
+        load_options_size: 0,
+        load_options: ptr::null_mut(),
+        image_base: ptr::null_mut(),
+        image_size: 0,
+        image_code_type: EFIMemoryType::EfiLoaderCode as u32,
+        image_data_type: EFIMemoryType::EfiLoaderData as u32,
+    }
+}
+
+/// Images are `MZ ` followed by a program: `echo` exits with its load options as exit data,
+/// anything else exits with `ABORTED`. Paths are looked up on the mock volume by their file
+/// path nodes.
+unsafe extern "efiapi" fn load_image(
+    _: u8,
+    parent: Handle,
+    path: *const EFIDevicePath,
+    buf: *const u8,
+    size: usize,
+    out: *mut Handle,
+) -> EFIStatus {
+    let (data, device) = if buf.is_null() {
+        let Some(path) = (unsafe { DevicePath::from_ptr(path) }) else {
+            return EFIStatus::INVALID_PARAMETER;
+        };
+        let mut file = String::new();
+        for node in path.nodes().map(|node| node.decode()) {
+            if let DeviceNode::FilePath(_) = node {
+                file.extend(node.file_path_chars());
+            }
+        }
+        match with_state(|state| state.files.get(&file).cloned().flatten()) {
+            Some(data) => (data, VOLUME),
+            None => return EFIStatus::NOT_FOUND,
+        }
+    } else {
+        let data = unsafe { core::slice::from_raw_parts(buf, size) };
+        (data.to_vec(), Handle::null())
+    };
+    if !data.starts_with(b"MZ ") {
+        return EFIStatus::LOAD_ERROR;
+    }
+    let info = Box::into_raw(Box::new(child_image(parent, device)));
+    let handle = with_state(|state| {
+        let handle = Handle(state.next_handle as *mut c_void);
+        state.next_handle += 1;
+        state
+            .protocols
+            .push((handle, known::LOADED_IMAGE_PROTOCOL, info as *mut c_void));
+        state.images.push((handle, data));
+        handle
+    });
+    unsafe { *out = handle };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn start_image(
+    handle: Handle,
+    exit_data_size: *mut usize,
+    exit_data: *mut *mut u16,
+) -> EFIStatus {
+    let program = with_state(|state| {
+        let (_, data) = state.images.iter().find(|(h, _)| *h == handle)?;
+        Some(data[3..].to_vec())
+    });
+    let Some(program) = program else {
+        return EFIStatus::INVALID_PARAMETER;
+    };
+    let info = find_protocol(handle, &known::LOADED_IMAGE_PROTOCOL).unwrap();
+    let info = unsafe { &*(info as *const EFILoadedImageProtocol) };
+    let (status, message): (_, Vec<u16>) = match &program[..] {
+        b"echo" => {
+            let units = info.load_options_size as usize / 2;
+            let options = info.load_options as *const u16;
+            let options = unsafe { core::slice::from_raw_parts(options, units) };
+            (EFIStatus::SUCCESS, options.to_vec())
+        }
+        _ => (
+            EFIStatus::ABORTED,
+            "diagnostics failed\0".encode_utf16().collect(),
+        ),
+    };
+    // Applications are unloaded as soon as they return.
+    unsafe { unload_image(handle) };
+
+    if !message.is_empty() {
+        let size = message.len() * 2;
+        let mut buffer = ptr::null_mut();
+        unsafe {
+            let _ = allocate_pool(EFIMemoryType::EfiBootServicesData as u32, size, &mut buffer);
+            ptr::copy_nonoverlapping(message.as_ptr(), buffer as *mut u16, message.len());
+            *exit_data_size = size;
+            *exit_data = buffer as *mut u16;
+        }
+    }
+    status
+}
+
+unsafe extern "efiapi" fn exit(_: Handle, status: EFIStatus, _: usize, _: *mut u16) -> ! {
+    panic!("image exited with {status}");
+}
+
+unsafe extern "efiapi" fn unload_image(handle: Handle) -> EFIStatus {
+    let info = with_state(|state| {
+        let index = state.images.iter().position(|(h, _)| *h == handle)?;
+        state.images.remove(index);
+        let index = state.protocols.iter().position(|(h, _, _)| *h == handle)?;
+        Some(state.protocols.remove(index).2)
+    });
+    match info {
+        Some(info) => {
+            drop(unsafe { Box::from_raw(info as *mut EFILoadedImageProtocol) });
+            EFIStatus::SUCCESS
+        }
+        None => EFIStatus::INVALID_PARAMETER,
+    }
+}
+
+unsafe extern "efiapi" fn exit_boot_services(image: Handle, map_key: usize) -> EFIStatus {
+    with_state(|state| {
+        state.exit_attempts += 1;
+        if state.stale_exits > 0 {
+            // Something touched the map since the caller read it.
+            state.stale_exits -= 1;
+            state.map_key += 1;
+        }
+        if image != IMAGE || map_key != state.map_key {
+            return EFIStatus::INVALID_PARAMETER;
+        }
+        state.exited = true;
+        EFIStatus::SUCCESS
+    })
+}
+
+unsafe extern "efiapi" fn get_next_monotonic_count(count: *mut u64) -> EFIStatus {
+    unsafe { *count = 1 };
+    EFIStatus::SUCCESS
+}
+
+/// Moves the mock clock, firing the timers that come due on the way.
+unsafe extern "efiapi" fn stall(microseconds: u64) -> EFIStatus {
+    let target = with_state(|state| state.clock + microseconds * 10);
+    loop {
+        let fired = with_state(|state| match next_deadline(state) {
+            Some(t) if t <= target => Some(advance_clock(state, t)),
+            _ => {
+                state.clock = target;
+                None
+            }
+        });
+        match fired {
+            Some(notifies) => run_notifies(notifies),
+            None => return EFIStatus::SUCCESS,
+        }
+    }
+}
+
+unsafe extern "efiapi" fn set_watchdog_timer(_: u64, _: u64, _: u64, _: *const u16) -> EFIStatus {
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn connect_controller(
+    _: Handle,
+    _: *const Handle,
+    _: *const EFIDevicePath,
+    _: u8,
+) -> EFIStatus {
+    EFIStatus::NOT_FOUND
+}
+
+unsafe extern "efiapi" fn disconnect_controller(_: Handle, _: Handle, _: Handle) -> EFIStatus {
+    EFIStatus::NOT_FOUND
+}
+
+unsafe extern "efiapi" fn open_protocol(
+    handle: Handle,
+    guid: *const GUID,
+    interface: *mut *mut c_void,
+    _: Handle,
+    _: Handle,
+    _: u32,
+) -> EFIStatus {
+    match find_protocol(handle, unsafe { &*guid }) {
+        Some(found) => {
+            unsafe { *interface = found };
+            with_state(|state| state.open_protocols += 1);
+            EFIStatus::SUCCESS
+        }
+        None => EFIStatus::UNSUPPORTED,
+    }
+}
+
+unsafe extern "efiapi" fn close_protocol(
+    handle: Handle,
+    guid: *const GUID,
+    _: Handle,
+    _: Handle,
+) -> EFIStatus {
+    if find_protocol(handle, unsafe { &*guid }).is_none() {
+        return EFIStatus::NOT_FOUND;
+    }
+    with_state(|state| state.open_protocols -= 1);
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn open_protocol_information(
+    _: Handle,
+    _: *const GUID,
+    _: *mut *mut EFIOpenProtocolInfoEntry,
+    _: *mut usize,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+unsafe extern "efiapi" fn protocols_per_handle(
+    _: Handle,
+    _: *mut *mut *const GUID,
+    _: *mut usize,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+unsafe extern "efiapi" fn locate_handle_buffer(
+    _: i32,
+    guid: *const GUID,
+    _: *const c_void,
+    count: *mut usize,
+    buf: *mut *mut Handle,
+) -> EFIStatus {
+    let handles = handles_for(unsafe { &*guid });
+    if handles.is_empty() {
+        return EFIStatus::NOT_FOUND;
+    }
+    let mut out = ptr::null_mut();
+    let status = unsafe { allocate_pool(0, handles.len() * size_of::<Handle>(), &mut out) };
+    if status.is_error() {
+        return status;
+    }
+    unsafe {
+        ptr::copy_nonoverlapping(handles.as_ptr(), out as *mut Handle, handles.len());
+        *buf = out as *mut Handle;
+        *count = handles.len();
+    }
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn locate_protocol(
+    guid: *const GUID,
+    _: *mut c_void,
+    out: *mut *mut c_void,
+) -> EFIStatus {
+    let guid = unsafe { *guid };
+    let found = with_state(|state| {
+        state
+            .protocols
+            .iter()
+            .find(|(_, g, _)| *g == guid)
+            .map(|(_, _, i)| *i)
+    });
+    match found {
+        Some(interface) => {
+            unsafe { *out = interface };
+            EFIStatus::SUCCESS
+        }
+        None => EFIStatus::NOT_FOUND,
+    }
+}
+
+unsafe extern "efiapi" fn calculate_crc32(
+    data: *const c_void,
+    size: u64,
+    crc: *mut u32,
+) -> EFIStatus {
+    let data = unsafe { core::slice::from_raw_parts(data as *const u8, size as usize) };
+    let mut value = !0u32;
+    for byte in data {
+        value ^= *byte as u32;
+        for _ in 0..8 {
+            value = if value & 1 != 0 {
+                (value >> 1) ^ 0xedb8_8320
+            } else {
+                value >> 1
+            };
+        }
+    }
+    unsafe { *crc = !value };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn copy_mem(dest: *mut u8, src: *const u8, len: u64) {
+    unsafe { ptr::copy(src, dest, len as usize) };
+}
+
+unsafe extern "efiapi" fn set_mem(buffer: *mut u8, len: u64, value: u8) {
+    unsafe { ptr::write_bytes(buffer, value, len as usize) };
+}
+
+unsafe extern "efiapi" fn create_event_ex(
+    ty: u32,
+    tpl: u64,
+    notify: Option<EFIEventNotify>,
+    ctx: *mut c_void,
+    _: *const GUID,
+    out: *mut Event,
+) -> EFIStatus {
+    unsafe { create_event(ty, tpl, notify, ctx, out) }
+}
+
+// An open handle on the mock volume. The protocol comes first so the interface pointer handed to
+// callers is also the pointer to this struct.
+#[repr(C)]
+struct MockFile {
+    protocol: EFIFileProtocol,
+    /// Absolute path, empty for the root.
+    path: String,
+    writable: bool,
+    position: u64,
+}
+
+fn open_mock_file(path: String, writable: bool) -> *mut EFIFileProtocol {
+    with_state(|state| state.open_files += 1);
+    let file = Box::new(MockFile {
+        protocol: EFIFileProtocol {
+            revision: 0x10000,
+            open: file_open,
+            close: file_close,
+            delete: file_delete,
+            read: file_read,
+            write: file_write,
+            get_position: file_get_position,
+            set_position: file_set_position,
+            get_info: file_get_info,
+            set_info: file_set_info,
+            flush: file_flush,
+            open_ex: ptr::null_mut(),
+            read_ex: ptr::null_mut(),
+            write_ex: ptr::null_mut(),
+            flush_ex: ptr::null_mut(),
+        },
+        path,
+        writable,
+        position: 0,
+    });
+    Box::into_raw(file).cast()
+}
+
+unsafe fn mock_file<'a>(this: *mut EFIFileProtocol) -> &'a mut MockFile {
+    unsafe { &mut *this.cast::<MockFile>() }
+}
+
+/// Resolves `name` against the directory `base`, handling `.` and `..`.
+fn resolve_path(base: &str, name: &str) -> String {
+    let mut parts: Vec<&str> = if name.starts_with('\\') {
+        Vec::new()
+    } else {
+        base.split('\\').filter(|p| !p.is_empty()).collect()
+    };
+    for part in name.split('\\') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.iter().map(|p| format!("\\{p}")).collect()
+}
+
+fn children(files: &BTreeMap<String, Option<Vec<u8>>>, dir: &str) -> Vec<String> {
+    let prefix = format!("{dir}\\");
+    files
+        .keys()
+        .filter(|k| {
+            k.strip_prefix(&prefix)
+                .is_some_and(|rest| !rest.contains('\\'))
+        })
+        .cloned()
+        .collect()
+}
+
+/// `EFI_FILE_INFO` for `path`, `None` if it does not exist.
+fn file_info(state: &MockState, path: &str) -> Option<Vec<u8>> {
+    let data = if path.is_empty() {
+        None
+    } else {
+        state.files.get(path)?.as_ref()
+    };
+    let name = path.rsplit('\\').next().unwrap_or("");
+    let name: Vec<u16> = name.encode_utf16().chain([0]).collect();
+    let size = size_of::<EFIFileInfoHeader>() + name.len() * 2;
+    let file_size = data.map_or(0, |d| d.len() as u64);
+    let header = EFIFileInfoHeader {
+        size: size as u64,
+        file_size,
+        physical_size: file_size.next_multiple_of(512),
+        create_time: state.time,
+        last_access_time: state.time,
+        modification_time: state.time,
+        attribute: if data.is_some() { 0x20 } else { 0x10 },
+    };
+    let mut buf = Vec::with_capacity(size);
+    buf.extend_from_slice(unsafe {
+        core::slice::from_raw_parts(
+            (&header as *const EFIFileInfoHeader).cast::<u8>(),
+            size_of::<EFIFileInfoHeader>(),
+        )
+    });
+    buf.extend(name.iter().flat_map(|c| c.to_le_bytes()));
+    Some(buf)
+}
+
+unsafe fn copy_info(info: &[u8], size: *mut usize, buffer: *mut c_void) -> EFIStatus {
+    let available = unsafe { *size };
+    unsafe { *size = info.len() };
+    if available < info.len() {
+        return EFIStatus::BUFFER_TOO_SMALL;
+    }
+    unsafe { ptr::copy_nonoverlapping(info.as_ptr(), buffer.cast(), info.len()) };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn open_volume(
+    _: *mut EFISimpleFileSystemProtocol,
+    root: *mut *mut EFIFileProtocol,
+) -> EFIStatus {
+    unsafe { *root = open_mock_file(String::new(), true) };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn file_open(
+    this: *mut EFIFileProtocol,
+    new_handle: *mut *mut EFIFileProtocol,
+    name: *const Wchar,
+    mode: u64,
+    attributes: u64,
+) -> EFIStatus {
+    let file = unsafe { mock_file(this) };
+    let path = resolve_path(&file.path, &decode_utf16(name));
+    let create = mode & 0x8000_0000_0000_0000 != 0;
+    let status = with_state(|state| {
+        if path.is_empty() || state.files.contains_key(&path) {
+            return EFIStatus::SUCCESS;
+        }
+        let parent = &path[..path.rfind('\\').unwrap_or(0)];
+        let parent_is_dir = parent.is_empty() || state.files.get(parent) == Some(&None);
+        if !create || !parent_is_dir {
+            return EFIStatus::NOT_FOUND;
+        }
+        let contents = if attributes & 0x10 != 0 {
+            None
+        } else {
+            Some(Vec::new())
+        };
+        state.files.insert(path.clone(), contents);
+        EFIStatus::SUCCESS
+    });
+    if status.is_success() {
+        unsafe { *new_handle = open_mock_file(path, mode & 0x02 != 0) };
+    }
+    status
+}
+
+unsafe extern "efiapi" fn file_close(this: *mut EFIFileProtocol) -> EFIStatus {
+    drop(unsafe { Box::from_raw(this.cast::<MockFile>()) });
+    with_state(|state| state.open_files -= 1);
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn file_delete(this: *mut EFIFileProtocol) -> EFIStatus {
+    let path = unsafe { mock_file(this) }.path.clone();
+    let deleted = with_state(|state| {
+        if path.is_empty() || !children(&state.files, &path).is_empty() {
+            return false;
+        }
+        state.files.remove(&path).is_some()
+    });
+    unsafe { file_close(this) };
+    if deleted {
+        EFIStatus::SUCCESS
+    } else {
+        EFIStatus::WARN_DELETE_FAILURE
+    }
+}
+
+unsafe extern "efiapi" fn file_read(
+    this: *mut EFIFileProtocol,
+    size: *mut usize,
+    buffer: *mut c_void,
+) -> EFIStatus {
+    let file = unsafe { mock_file(this) };
+    with_state(|state| {
+        let data = match file.path.is_empty() {
+            true => None,
+            false => state.files.get(&file.path).cloned().flatten(),
+        };
+        match data {
+            Some(data) => {
+                let start = (file.position as usize).min(data.len());
+                let len = unsafe { *size }.min(data.len() - start);
+                unsafe {
+                    ptr::copy_nonoverlapping(data[start..].as_ptr(), buffer.cast(), len);
+                    *size = len;
+                }
+                file.position += len as u64;
+                EFIStatus::SUCCESS
+            }
+            None => {
+                let entries = children(&state.files, &file.path);
+                let Some(entry) = entries.get(file.position as usize) else {
+                    unsafe { *size = 0 };
+                    return EFIStatus::SUCCESS;
+                };
+                let info = file_info(state, entry).unwrap_or_default();
+                let status = unsafe { copy_info(&info, size, buffer) };
+                if status.is_success() {
+                    file.position += 1;
+                }
+                status
+            }
+        }
+    })
+}
+
+unsafe extern "efiapi" fn file_write(
+    this: *mut EFIFileProtocol,
+    size: *mut usize,
+    buffer: *const c_void,
+) -> EFIStatus {
+    let file = unsafe { mock_file(this) };
+    if !file.writable {
+        return EFIStatus::ACCESS_DENIED;
+    }
+    let src = unsafe { core::slice::from_raw_parts(buffer.cast::<u8>(), *size) };
+    with_state(|state| match state.files.get_mut(&file.path) {
+        Some(Some(data)) => {
+            let start = file.position as usize;
+            let end = start + src.len();
+            if data.len() < end {
+                data.resize(end, 0);
+            }
+            data[start..end].copy_from_slice(src);
+            file.position = end as u64;
+            EFIStatus::SUCCESS
+        }
+        _ => EFIStatus::UNSUPPORTED,
+    })
+}
+
+unsafe extern "efiapi" fn file_get_position(
+    this: *mut EFIFileProtocol,
+    position: *mut u64,
+) -> EFIStatus {
+    unsafe { *position = mock_file(this).position };
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn file_set_position(
+    this: *mut EFIFileProtocol,
+    position: u64,
+) -> EFIStatus {
+    let file = unsafe { mock_file(this) };
+    let len = with_state(|state| match state.files.get(&file.path) {
+        Some(Some(data)) => Some(data.len() as u64),
+        _ => None,
+    });
+    match (len, position) {
+        (Some(len), u64::MAX) => file.position = len,
+        (Some(_), position) | (None, position @ 0) => file.position = position,
+        (None, _) => return EFIStatus::UNSUPPORTED,
+    }
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn file_get_info(
+    this: *mut EFIFileProtocol,
+    info_type: *const GUID,
+    size: *mut usize,
+    buffer: *mut c_void,
+) -> EFIStatus {
+    if unsafe { *info_type } != known::FILE_INFO {
+        return EFIStatus::UNSUPPORTED;
+    }
+    let file = unsafe { mock_file(this) };
+    match with_state(|state| file_info(state, &file.path)) {
+        Some(info) => unsafe { copy_info(&info, size, buffer) },
+        None => EFIStatus::DEVICE_ERROR,
+    }
+}
+
+unsafe extern "efiapi" fn file_set_info(
+    _: *mut EFIFileProtocol,
+    _: *const GUID,
+    _: usize,
+    _: *const c_void,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+unsafe extern "efiapi" fn file_flush(_: *mut EFIFileProtocol) -> EFIStatus {
+    EFIStatus::SUCCESS
+}
+
+fn switch_gop_mode(state: &mut MockState, number: u32) {
+    let (_, height, stride, format) = GOP_MODES[number as usize];
+    state.framebuffer = alloc::vec![BltPixel::default(); (stride * height) as usize];
+    let mode = unsafe { &mut *state.gop_mode };
+    // The infos sit in one array, step from the current entry to the new one.
+    mode.info = unsafe { mode.info.sub(mode.mode as usize).add(number as usize) };
+    mode.mode = number;
+    let blt_only = format == 3;
+    mode.frame_buffer_base = if blt_only {
+        0
+    } else {
+        state.framebuffer.as_ptr() as u64
+    };
+    mode.frame_buffer_size = if blt_only {
+        0
+    } else {
+        size_of_val(&state.framebuffer[..])
+    };
+}
+
+unsafe extern "efiapi" fn gop_query_mode(
+    _: *mut EFIGraphicsOutputProtocol,
+    number: u32,
+    size: *mut usize,
+    info: *mut *mut EFIGraphicsOutputModeInformation,
+) -> EFIStatus {
+    let Some(&(width, height, stride, format)) = GOP_MODES.get(number as usize) else {
+        return EFIStatus::INVALID_PARAMETER;
+    };
+    let len = size_of::<EFIGraphicsOutputModeInformation>();
+    let mut buffer = ptr::null_mut();
+    let status =
+        unsafe { allocate_pool(EFIMemoryType::EfiBootServicesData as u32, len, &mut buffer) };
+    if status.is_error() {
+        return status;
+    }
+    unsafe {
+        buffer
+            .cast::<EFIGraphicsOutputModeInformation>()
+            .write(EFIGraphicsOutputModeInformation {
+                version: 0,
+                horizontal_resolution: width,
+                vertical_resolution: height,
+                pixel_format: format,
+                pixel_information: PixelBitmask::default(),
+                pixels_per_scan_line: stride,
+            });
+        *size = len;
+        *info = buffer.cast();
+    }
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn gop_set_mode(
+    _: *mut EFIGraphicsOutputProtocol,
+    number: u32,
+) -> EFIStatus {
+    if number as usize >= GOP_MODES.len() {
+        return EFIStatus::UNSUPPORTED;
+    }
+    with_state(|state| switch_gop_mode(state, number));
+    EFIStatus::SUCCESS
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe extern "efiapi" fn gop_blt(
+    _: *mut EFIGraphicsOutputProtocol,
+    buffer: *mut BltPixel,
+    operation: u32,
+    src_x: usize,
+    src_y: usize,
+    dest_x: usize,
+    dest_y: usize,
+    width: usize,
+    height: usize,
+    delta: usize,
+) -> EFIStatus {
+    with_state(|state| {
+        let mode = unsafe { &*state.gop_mode };
+        let (screen_w, screen_h, stride, _) = GOP_MODES[mode.mode as usize];
+        let (screen_w, screen_h, stride) = (screen_w as usize, screen_h as usize, stride as usize);
+        let on_screen = |x: usize, y: usize| x + width <= screen_w && y + height <= screen_h;
+        let fb = &mut state.framebuffer;
+        // Buffer rows default to the rectangle width when no delta is given.
+        let row = if delta == 0 {
+            width
+        } else {
+            delta / size_of::<BltPixel>()
+        };
+        match operation {
+            0 if on_screen(dest_x, dest_y) => {
+                let color = unsafe { *buffer };
+                for y in dest_y..dest_y + height {
+                    fb[y * stride + dest_x..][..width].fill(color);
+                }
+            }
+            1 if on_screen(src_x, src_y) => {
+                for y in 0..height {
+                    let src = &fb[(src_y + y) * stride + src_x..][..width];
+                    let dest = unsafe { buffer.add((dest_y + y) * row + dest_x) };
+                    unsafe { ptr::copy_nonoverlapping(src.as_ptr(), dest, width) };
+                }
+            }
+            2 if on_screen(dest_x, dest_y) => {
+                for y in 0..height {
+                    let src = unsafe { buffer.add((src_y + y) * row + src_x) };
+                    let dest = &mut fb[(dest_y + y) * stride + dest_x..][..width];
+                    unsafe { ptr::copy_nonoverlapping(src, dest.as_mut_ptr(), width) };
+                }
+            }
+            3 if on_screen(src_x, src_y) && on_screen(dest_x, dest_y) => {
+                let copy = fb.clone();
+                for y in 0..height {
+                    let src = &copy[(src_y + y) * stride + src_x..][..width];
+                    fb[(dest_y + y) * stride + dest_x..][..width].copy_from_slice(src);
+                }
+            }
+            _ => return EFIStatus::INVALID_PARAMETER,
+        }
+        EFIStatus::SUCCESS
+    })
+}
+
+unsafe extern "efiapi" fn serial_reset(_: *mut EFISerialIoProtocol) -> EFIStatus {
+    EFIStatus::SUCCESS
+}
+
+unsafe extern "efiapi" fn serial_set_attributes(
+    _: *mut EFISerialIoProtocol,
+    _: u64,
+    _: u32,
+    _: u32,
+    _: u32,
+    _: u8,
+    _: u32,
+) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+unsafe extern "efiapi" fn serial_set_control(_: *mut EFISerialIoProtocol, _: u32) -> EFIStatus {
+    EFIStatus::UNSUPPORTED
+}
+
+unsafe extern "efiapi" fn serial_get_control(
+    _: *mut EFISerialIoProtocol,
+    control: *mut u32,
+) -> EFIStatus {
+    unsafe { *control = 0 };
+    EFIStatus::SUCCESS
+}
+
+/// Short writes, like a UART with a small FIFO.
+unsafe extern "efiapi" fn serial_write(
+    _: *mut EFISerialIoProtocol,
+    size: *mut usize,
+    buffer: *const c_void,
+) -> EFIStatus {
+    let len = unsafe { *size }.min(SERIAL_CHUNK);
+    let data = unsafe { core::slice::from_raw_parts(buffer.cast::<u8>(), len) };
+    with_state(|state| state.serial.extend_from_slice(data));
+    unsafe { *size = len };
+    EFIStatus::SUCCESS
+}
+
+/// Nothing ever arrives.
+unsafe extern "efiapi" fn serial_read(
+    _: *mut EFISerialIoProtocol,
+    size: *mut usize,
+    _: *mut c_void,
+) -> EFIStatus {
+    unsafe { *size = 0 };
+    EFIStatus::TIMEOUT
+}
@@ -0,0 +1,402 @@
+use core::ffi::c_void;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+
+use crate::guid::known;
+use crate::{
+    EFIBootServices, EFIDevicePath, EFILoadedImageProtocol, EFIOpenProtocolInfoEntry, EFIStatus,
+    Event, GUID, Handle, SimpleInputInterface, SimpleTextOutputInterface,
+};
+
+/// Ties a `#[repr(C)]` protocol interface struct to its GUID.
+///
+/// # Safety
+/// `Self` must have the exact layout of the interface published under `GUID`.
+pub unsafe trait Protocol {
+    const GUID: GUID;
+}
+
+unsafe impl Protocol for EFILoadedImageProtocol {
+    const GUID: GUID = known::LOADED_IMAGE_PROTOCOL;
+}
+
+unsafe impl Protocol for SimpleTextOutputInterface {
+    const GUID: GUID = known::SIMPLE_TEXT_OUTPUT_PROTOCOL;
+}
+
+unsafe impl Protocol for SimpleInputInterface {
+    const GUID: GUID = known::SIMPLE_TEXT_INPUT_PROTOCOL;
+}
+
+/// `attributes` for `open_protocol`.
+pub mod open_attributes {
+    pub const BY_HANDLE_PROTOCOL: u32 = 0x01;
+    pub const GET_PROTOCOL: u32 = 0x02;
+    pub const TEST_PROTOCOL: u32 = 0x04;
+    pub const BY_CHILD_CONTROLLER: u32 = 0x08;
+    pub const BY_DRIVER: u32 = 0x10;
+    pub const EXCLUSIVE: u32 = 0x20;
+}
+
+// locate_handle / locate_handle_buffer search types.
+const SEARCH_BY_PROTOCOL: i32 = 2;
+
+/// An opened protocol interface, `close_protocol` is called on drop.
+pub struct ScopedProtocol<'a, P: Protocol> {
+    bs: &'a EFIBootServices,
+    interface: *mut P,
+    handle: Handle,
+    agent: Handle,
+    controller: Handle,
+}
+
+impl<'a, P: Protocol> ScopedProtocol<'a, P> {
+    pub fn handle(&self) -> Handle {
+        self.handle
+    }
+
+    pub fn as_ptr(&self) -> *mut P {
+        self.interface
+    }
+
+    /// Keeps the protocol open for good.
+    pub fn leak(self) -> &'a mut P {
+        let interface = self.interface;
+        core::mem::forget(self);
+        unsafe { &mut *interface }
+    }
+}
+
+impl<P: Protocol> Deref for ScopedProtocol<'_, P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        unsafe { &*self.interface }
+    }
+}
+
+impl<P: Protocol> DerefMut for ScopedProtocol<'_, P> {
+    fn deref_mut(&mut self) -> &mut P {
+        unsafe { &mut *self.interface }
+    }
+}
+
+impl<P: Protocol> Drop for ScopedProtocol<'_, P> {
+    fn drop(&mut self) {
+        // Nothing left to close once boot services are gone.
+        if EFIBootServices::fetch_global().is_none() {
+            return;
+        }
+        let _ =
+            unsafe { (self.bs.close_protocol)(self.handle, &P::GUID, self.agent, self.controller) };
+    }
+}
+
+/// Array handed out by the firmware from pool memory, freed on drop.
+pub struct PoolSlice<'a, T> {
+    bs: &'a EFIBootServices,
+    ptr: *mut T,
+    len: usize,
+}
+
+pub type HandleBuffer<'a> = PoolSlice<'a, Handle>;
+
+impl<T> Deref for PoolSlice<'_, T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        if self.ptr.is_null() {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T> Drop for PoolSlice<'_, T> {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() && EFIBootServices::fetch_global().is_some() {
+            let _ = self.bs.free_pool(self.ptr as *mut u8);
+        }
+    }
+}
+
+impl EFIBootServices {
+    /// Opens `P` on `handle` with `GET_PROTOCOL`, using the global image as the agent.
+    pub fn open_protocol<P: Protocol>(
+        &self,
+        handle: Handle,
+    ) -> Result<ScopedProtocol<'_, P>, EFIStatus> {
+        let agent = EFILoadedImageProtocol::global_image_handle().ok_or(EFIStatus::NOT_READY)?;
+        unsafe {
+            self.open_protocol_with::<P>(
+                handle,
+                agent,
+                Handle::null(),
+                open_attributes::GET_PROTOCOL,
+            )
+        }
+    }
+
+    /// # Safety
+    /// `attributes` must be one of the `open_attributes` combinations the spec allows for the
+    /// given agent and controller, `EXCLUSIVE` and `BY_DRIVER` can disconnect other drivers.
+    pub unsafe fn open_protocol_with<P: Protocol>(
+        &self,
+        handle: Handle,
+        agent: Handle,
+        controller: Handle,
+        attributes: u32,
+    ) -> Result<ScopedProtocol<'_, P>, EFIStatus> {
+        let mut interface = ptr::null_mut();
+        unsafe {
+            (self.open_protocol)(
+                handle,
+                &P::GUID,
+                &mut interface,
+                agent,
+                controller,
+                attributes,
+            )
+        }
+        .to_result()?;
+        if interface.is_null() {
+            return Err(EFIStatus::UNSUPPORTED);
+        }
+        Ok(ScopedProtocol {
+            bs: self,
+            interface: interface as *mut P,
+            handle,
+            agent,
+            controller,
+        })
+    }
+
+    /// First instance of `P` in the system, for protocols with a single producer.
+    pub fn locate_protocol<P: Protocol>(&self) -> Result<&'static mut P, EFIStatus> {
+        let mut interface = ptr::null_mut();
+        unsafe { (self.locate_protocol)(&P::GUID, ptr::null_mut(), &mut interface) }.to_result()?;
+        unsafe { (interface as *mut P).as_mut() }.ok_or(EFIStatus::NOT_FOUND)
+    }
+
+    /// Every handle that supports `P`.
+    pub fn locate_handle_buffer<P: Protocol>(&self) -> Result<HandleBuffer<'_>, EFIStatus> {
+        let mut len = 0;
+        let mut buf = ptr::null_mut();
+        unsafe {
+            (self.locate_handle_buffer)(
+                SEARCH_BY_PROTOCOL,
+                &P::GUID,
+                ptr::null(),
+                &mut len,
+                &mut buf,
+            )
+        }
+        .to_result()?;
+        Ok(PoolSlice {
+            bs: self,
+            ptr: buf,
+            len,
+        })
+    }
+
+    /// Raw lookup without an open record, prefer [`EFIBootServices::open_protocol`].
+    pub fn handle_protocol(
+        &self,
+        handle: Handle,
+        protocol: &GUID,
+    ) -> Result<*mut c_void, EFIStatus> {
+        let mut interface = ptr::null_mut();
+        unsafe { (self.handle_protocol)(handle, protocol, &mut interface) }
+            .to_result_with(interface)
+    }
+
+    /// Installs `interface` on `handle`, a null handle creates a new one which is returned.
+    ///
+    /// # Safety
+    /// `interface` must match the layout `protocol` describes and outlive the installation.
+    pub unsafe fn install_protocol_interface(
+        &self,
+        handle: Handle,
+        protocol: &GUID,
+        interface: *const c_void,
+    ) -> Result<Handle, EFIStatus> {
+        let mut handle = handle;
+        // EFI_NATIVE_INTERFACE is the only interface type.
+        unsafe { (self.install_protocol_interface)(&mut handle, protocol, 0, interface) }
+            .to_result_with(handle)
+    }
+
+    /// # Safety
+    /// Same as [`EFIBootServices::install_protocol_interface`], and nobody may still be using
+    /// `old_interface`.
+    pub unsafe fn reinstall_protocol_interface(
+        &self,
+        handle: Handle,
+        protocol: &GUID,
+        old_interface: *const c_void,
+        new_interface: *const c_void,
+    ) -> Result<(), EFIStatus> {
+        unsafe {
+            (self.reinstall_protocol_interface)(handle, protocol, old_interface, new_interface)
+        }
+        .to_result()
+    }
+
+    /// # Safety
+    /// Nobody may still be using `interface`.
+    pub unsafe fn uninstall_protocol_interface(
+        &self,
+        handle: Handle,
+        protocol: &GUID,
+        interface: *const c_void,
+    ) -> Result<(), EFIStatus> {
+        unsafe { (self.uninstall_protocol_interface)(handle, protocol, interface) }.to_result()
+    }
+
+    /// Signals `event` whenever `protocol` is installed, returns the registration key for
+    /// `locate_handle`.
+    pub fn register_protocol_notify(
+        &self,
+        protocol: &GUID,
+        event: Event,
+    ) -> Result<*mut c_void, EFIStatus> {
+        let mut registration = ptr::null_mut();
+        unsafe { (self.register_protocol_notify)(protocol, event, &mut registration) }
+            .to_result_with(registration)
+    }
+
+    /// Fills `buf` with handles supporting `protocol` and returns how many there are. On
+    /// `BUFFER_TOO_SMALL` use [`EFIBootServices::locate_handle_buffer`] instead.
+    pub fn locate_handle(&self, protocol: &GUID, buf: &mut [Handle]) -> Result<usize, EFIStatus> {
+        let mut size = core::mem::size_of_val(buf);
+        unsafe {
+            (self.locate_handle)(
+                SEARCH_BY_PROTOCOL,
+                protocol,
+                ptr::null(),
+                &mut size,
+                buf.as_mut_ptr(),
+            )
+        }
+        .to_result_with(size / core::mem::size_of::<Handle>())
+    }
+
+    /// Finds the handle closest to `device_path` that supports `protocol`, advancing
+    /// `device_path` past the matched part.
+    ///
+    /// # Safety
+    /// `*device_path` must point to a valid device path.
+    pub unsafe fn locate_device_path(
+        &self,
+        protocol: &GUID,
+        device_path: &mut *const EFIDevicePath,
+    ) -> Result<Handle, EFIStatus> {
+        let mut handle = Handle::null();
+        unsafe { (self.locate_device_path)(protocol, device_path, &mut handle) }
+            .to_result_with(handle)
+    }
+
+    pub fn open_protocol_information(
+        &self,
+        handle: Handle,
+        protocol: &GUID,
+    ) -> Result<PoolSlice<'_, EFIOpenProtocolInfoEntry>, EFIStatus> {
+        let mut buf = ptr::null_mut();
+        let mut len = 0;
+        unsafe { (self.open_protocol_information)(handle, protocol, &mut buf, &mut len) }
+            .to_result()?;
+        Ok(PoolSlice {
+            bs: self,
+            ptr: buf,
+            len,
+        })
+    }
+
+    pub fn protocols_per_handle(
+        &self,
+        handle: Handle,
+    ) -> Result<PoolSlice<'_, *const GUID>, EFIStatus> {
+        let mut buf = ptr::null_mut();
+        let mut len = 0;
+        unsafe { (self.protocols_per_handle)(handle, &mut buf, &mut len) }.to_result()?;
+        Ok(PoolSlice {
+            bs: self,
+            ptr: buf,
+            len,
+        })
+    }
+
+    /// Connects every driver that wants `controller`.
+    pub fn connect_controller(&self, controller: Handle, recursive: bool) -> Result<(), EFIStatus> {
+        unsafe { (self.connect_controller)(controller, ptr::null(), ptr::null(), recursive as u8) }
+            .to_result()
+    }
+
+    /// `None` for `driver` or `child` disconnects all of them.
+    pub fn disconnect_controller(
+        &self,
+        controller: Handle,
+        driver: Option<Handle>,
+        child: Option<Handle>,
+    ) -> Result<(), EFIStatus> {
+        unsafe {
+            (self.disconnect_controller)(
+                controller,
+                driver.unwrap_or(Handle::null()),
+                child.unwrap_or(Handle::null()),
+            )
+        }
+        .to_result()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockFirmware, with_state};
+
+    #[test]
+    fn test_scoped_protocol_closes_on_drop() {
+        let _fw = MockFirmware::install();
+        let bs = EFIBootServices::fetch_global().unwrap();
+        {
+            let image = bs
+                .open_protocol::<EFILoadedImageProtocol>(mock::IMAGE)
+                .unwrap();
+            assert_eq!(image.image_data_type, 2);
+            assert_eq!(with_state(|s| s.open_protocols), 1);
+        }
+        assert_eq!(with_state(|s| s.open_protocols), 0);
+        assert_eq!(
+            bs.open_protocol::<SimpleInputInterface>(mock::IMAGE).err(),
+            Some(EFIStatus::UNSUPPORTED)
+        );
+    }
+
+    #[test]
+    fn test_global_image_lookup() {
+        let _fw = MockFirmware::install();
+        assert_eq!(
+            EFILoadedImageProtocol::global_image_handle(),
+            Some(mock::IMAGE)
+        );
+        assert_eq!(EFILoadedImageProtocol::global_image_data_type(), 2);
+        // Leaked on purpose, the image keeps it open for its lifetime.
+        assert_eq!(with_state(|s| s.open_protocols), 1);
+    }
+
+    #[test]
+    fn test_locate_handle_buffer_frees_pool() {
+        let _fw = MockFirmware::install();
+        let bs = EFIBootServices::fetch_global().unwrap();
+        {
+            let handles = bs
+                .locate_handle_buffer::<SimpleTextOutputInterface>()
+                .unwrap();
+            assert_eq!(&handles[..], &[mock::CONSOLE_OUT]);
+            assert_eq!(with_state(|s| s.pool.len()), 1);
+        }
+        assert!(with_state(|s| s.pool.is_empty()));
+        assert!(bs.locate_protocol::<SimpleInputInterface>().is_ok());
+    }
+}
@@ -0,0 +1,246 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::guid::known;
+use crate::variable::VariableAttributes;
+use crate::{EFIBootServices, EFIResetType, EFIRuntimeServices, EFIStatus, GUID, Wchar};
+
+// UTF-16 units kept from the reason string, longer reasons are cut. The buffer lives on the
+// stack since reset is also used from the panic handler, where allocating is not an option.
+const REASON_LEN: usize = 128;
+const GUID_UNITS: usize = size_of::<GUID>() / size_of::<Wchar>();
+
+/// `OsIndications` bit asking the firmware to stop in its setup UI on the next boot.
+const OS_INDICATIONS_BOOT_TO_FW_UI: u64 = 0x1;
+
+/// Builds `ResetData`: the null terminated reason, followed by `platform` for platform specific
+/// resets. Returns the used part of `buf`.
+fn encode_reset_data<'a>(
+    reason: &str,
+    platform: Option<&GUID>,
+    buf: &'a mut [Wchar; REASON_LEN + 1 + GUID_UNITS],
+) -> &'a [Wchar] {
+    let mut len = 0;
+    for c in reason.encode_utf16().take(REASON_LEN) {
+        buf[len] = c;
+        len += 1;
+    }
+    // Don't leave half a surrogate pair behind the cut.
+    if len == REASON_LEN && (0xd800..0xdc00).contains(&buf[len - 1]) {
+        len -= 1;
+    }
+    buf[len] = 0;
+    len += 1;
+    if let Some(guid) = platform {
+        for pair in guid.to_bytes().as_chunks::<2>().0 {
+            buf[len] = u16::from_le_bytes(*pair);
+            len += 1;
+        }
+    }
+    &buf[..len]
+}
+
+impl EFIRuntimeServices {
+    /// Resets or powers off the machine, never returns. `reason` is handed to the firmware, which
+    /// may log it. `platform` is only passed on for `EfiResetPlatformSpecific`.
+    pub fn reset(
+        &self,
+        reset_type: EFIResetType,
+        status: EFIStatus,
+        reason: &str,
+        platform: Option<&GUID>,
+    ) -> ! {
+        let platform = match reset_type {
+            EFIResetType::EfiResetPlatformSpecific => platform,
+            _ => None,
+        };
+        let mut buf = [0; REASON_LEN + 1 + GUID_UNITS];
+        let data = encode_reset_data(reason, platform, &mut buf);
+        unsafe {
+            (self.reset_system_type)(
+                reset_type,
+                status.0,
+                size_of_val(data) as u64,
+                data.as_ptr().cast_mut(),
+            )
+        };
+        // Firmware is not supposed to come back from ResetSystem.
+        halt()
+    }
+
+    /// Has the firmware open its setup UI on the next boot, follow up with a cold reset.
+    /// `UNSUPPORTED` if the firmware doesn't offer it.
+    pub fn request_firmware_setup(&self) -> Result<(), EFIStatus> {
+        let vars = self.variables();
+        let read_u64 = |name| -> Result<u64, EFIStatus> {
+            let (data, _) = vars.get(name, &known::GLOBAL_VARIABLE)?;
+            let mut bytes = [0; 8];
+            let len = data.len().min(8);
+            bytes[..len].copy_from_slice(&data[..len]);
+            Ok(u64::from_le_bytes(bytes))
+        };
+        let supported = match read_u64("OsIndicationsSupported") {
+            Err(EFIStatus::NOT_FOUND) => 0,
+            other => other?,
+        };
+        if supported & OS_INDICATIONS_BOOT_TO_FW_UI == 0 {
+            return Err(EFIStatus::UNSUPPORTED);
+        }
+        let indications = match read_u64("OsIndications") {
+            Err(EFIStatus::NOT_FOUND) => 0,
+            other => other?,
+        };
+        vars.set(
+            "OsIndications",
+            &known::GLOBAL_VARIABLE,
+            VariableAttributes::NV_BS_RT,
+            &(indications | OS_INDICATIONS_BOOT_TO_FW_UI).to_le_bytes(),
+        )
+    }
+}
+
+/// Spins forever.
+pub fn halt() -> ! {
+    loop {
+        core::hint::spin_loop();
+    }
+}
+
+/// What the panic handler does once the message is out.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum PanicPolicy {
+    /// Spin so the message stays on screen.
+    #[default]
+    Halt,
+    /// Cold reset after the delay, counted with `stall` while boot services are up.
+    Reboot { after_secs: u32 },
+    /// Power off, lets headless test runs finish.
+    Shutdown,
+}
+
+// Tag in the low byte, reboot delay in the high half.
+static PANIC_POLICY: AtomicU64 = AtomicU64::new(0);
+
+impl PanicPolicy {
+    const fn to_bits(self) -> u64 {
+        match self {
+            PanicPolicy::Halt => 0,
+            PanicPolicy::Reboot { after_secs } => 1 | (after_secs as u64) << 32,
+            PanicPolicy::Shutdown => 2,
+        }
+    }
+
+    const fn from_bits(bits: u64) -> Self {
+        match bits & 0xff {
+            1 => PanicPolicy::Reboot {
+                after_secs: (bits >> 32) as u32,
+            },
+            2 => PanicPolicy::Shutdown,
+            _ => PanicPolicy::Halt,
+        }
+    }
+}
+
+pub fn set_panic_policy(policy: PanicPolicy) {
+    PANIC_POLICY.store(policy.to_bits(), Ordering::Relaxed);
+}
+
+pub fn panic_policy() -> PanicPolicy {
+    PanicPolicy::from_bits(PANIC_POLICY.load(Ordering::Relaxed))
+}
+
+/// Carries out the panic policy, for use at the end of a `#[panic_handler]`. Falls back to
+/// halting when runtime services are not reachable.
+pub fn apply_panic_policy() -> ! {
+    let policy = panic_policy();
+    let Some(rt) = EFIRuntimeServices::fetch_global() else {
+        halt()
+    };
+    match policy {
+        PanicPolicy::Halt => halt(),
+        PanicPolicy::Reboot { after_secs } => {
+            // Without boot services there is no timer left, reboot right away.
+            if let Some(bs) = EFIBootServices::fetch_global() {
+                for _ in 0..after_secs {
+                    let _ = bs.stall(1_000_000);
+                }
+            }
+            rt.reset(
+                EFIResetType::EfiResetCold,
+                EFIStatus::ABORTED,
+                "panic",
+                None,
+            )
+        }
+        PanicPolicy::Shutdown => rt.reset(
+            EFIResetType::EfiResetShutdown,
+            EFIStatus::ABORTED,
+            "panic",
+            None,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn test_reset_data_layout() {
+        let mut buf = [0; REASON_LEN + 1 + GUID_UNITS];
+        let guid = crate::guid::known::GLOBAL_VARIABLE;
+        let data = encode_reset_data("oops", Some(&guid), &mut buf);
+        assert_eq!(data.len(), 5 + GUID_UNITS);
+        assert_eq!(&data[..5], &[0x6f, 0x6f, 0x70, 0x73, 0]);
+        let bytes: alloc::vec::Vec<u8> = data[5..].iter().flat_map(|u| u.to_le_bytes()).collect();
+        assert_eq!(GUID::from_bytes(bytes.try_into().unwrap()), guid);
+
+        let data = encode_reset_data("", None, &mut buf);
+        assert_eq!(data, &[0]);
+    }
+
+    #[test]
+    fn test_long_reason_is_truncated() {
+        let mut buf = [0; REASON_LEN + 1 + GUID_UNITS];
+        let mut reason: String = core::iter::repeat_n('a', REASON_LEN - 1).collect();
+        reason.push('𐍈');
+        let data = encode_reset_data(&reason, None, &mut buf);
+        assert_eq!(data.len(), REASON_LEN);
+        assert_eq!(data[REASON_LEN - 1], 0);
+    }
+
+    #[test]
+    fn test_request_firmware_setup() {
+        let name = |s: &str| s.encode_utf16().collect::<alloc::vec::Vec<_>>();
+        let _fw = crate::mock::MockFirmware::install();
+        let rt = EFIRuntimeServices::fetch_global().unwrap();
+        assert_eq!(rt.request_firmware_setup(), Err(EFIStatus::UNSUPPORTED));
+
+        crate::mock::with_state(|s| {
+            s.variables.push((
+                name("OsIndicationsSupported"),
+                known::GLOBAL_VARIABLE,
+                VariableAttributes::BOOTSERVICE_ACCESS.bits(),
+                0x41u64.to_le_bytes().to_vec(),
+            ))
+        });
+        rt.request_firmware_setup().unwrap();
+        let (data, attributes) = rt
+            .variables()
+            .get("OsIndications", &known::GLOBAL_VARIABLE)
+            .unwrap();
+        assert_eq!(data, 1u64.to_le_bytes());
+        assert_eq!(attributes, VariableAttributes::NV_BS_RT);
+    }
+
+    #[test]
+    fn test_panic_policy_round_trip() {
+        for policy in [
+            PanicPolicy::Halt,
+            PanicPolicy::Reboot { after_secs: 30 },
+            PanicPolicy::Shutdown,
+        ] {
+            assert_eq!(PanicPolicy::from_bits(policy.to_bits()), policy);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! `EFI_SERIAL_IO_PROTOCOL`, for logging to a serial port while boot services are up.
+//!
+//! ```ignore
+//! let serial = bs.locate_protocol::<EFISerialIoProtocol>()?;
+//! serial.write(b"hello\r\n")?;
+//! ```
+use core::ffi::c_void;
+
+use crate::guid::known;
+use crate::protocol::Protocol;
+use crate::{EFIStatus, GUID};
+
+#[derive(Clone, Copy, Default, Debug)]
+#[repr(C)]
+pub struct EFISerialIoMode {
+    pub control_mask: u32,
+    /// Microseconds a read or write waits per character.
+    pub timeout: u32,
+    pub baud_rate: u64,
+    pub receive_fifo_depth: u32,
+    pub data_bits: u32,
+    pub parity: u32,
+    pub stop_bits: u32,
+}
+
+#[repr(C)]
+pub struct EFISerialIoProtocol {
+    pub revision: u32,
+    pub reset: unsafe extern "efiapi" fn(this: *mut Self) -> EFIStatus,
+    pub set_attributes: unsafe extern "efiapi" fn(
+        this: *mut Self,
+        baud_rate: u64,
+        receive_fifo_depth: u32,
+        timeout: u32,
+        parity: u32,
+        data_bits: u8,
+        stop_bits: u32,
+    ) -> EFIStatus,
+    pub set_control: unsafe extern "efiapi" fn(this: *mut Self, control: u32) -> EFIStatus,
+    pub get_control: unsafe extern "efiapi" fn(this: *mut Self, control: *mut u32) -> EFIStatus,
+    pub write: unsafe extern "efiapi" fn(
+        this: *mut Self,
+        buffer_size: *mut usize,
+        buffer: *const c_void,
+    ) -> EFIStatus,
+    pub read: unsafe extern "efiapi" fn(
+        this: *mut Self,
+        buffer_size: *mut usize,
+        buffer: *mut c_void,
+    ) -> EFIStatus,
+    pub mode: *mut EFISerialIoMode,
+}
+
+unsafe impl Protocol for EFISerialIoProtocol {
+    const GUID: GUID = known::SERIAL_IO_PROTOCOL;
+}
+
+impl EFISerialIoProtocol {
+    pub fn reset(&mut self) -> Result<(), EFIStatus> {
+        unsafe { (self.reset)(self) }.to_result()
+    }
+
+    pub fn mode(&self) -> EFISerialIoMode {
+        unsafe { *self.mode }
+    }
+
+    /// Writes all of `data`. Fails with `TIMEOUT` if the port stops taking bytes, e.g. because
+    /// nothing is listening with flow control on.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), EFIStatus> {
+        let mut done = 0;
+        while done < data.len() {
+            let mut size = data.len() - done;
+            let status = unsafe { (self.write)(self, &mut size, data[done..].as_ptr().cast()) };
+            status.to_result()?;
+            if size == 0 {
+                return Err(EFIStatus::TIMEOUT);
+            }
+            done += size;
+        }
+        Ok(())
+    }
+
+    /// Reads what arrives within the port timeout, which may be nothing.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, EFIStatus> {
+        let mut size = buf.len();
+        match unsafe { (self.read)(self, &mut size, buf.as_mut_ptr().cast()) } {
+            EFIStatus::TIMEOUT => Ok(size),
+            status => status.to_result_with(size),
+        }
+    }
+}
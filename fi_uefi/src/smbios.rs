@@ -0,0 +1,668 @@
+//! SMBIOS entry points and structure table, decoding the records that identify the machine.
+use core::slice;
+
+use crate::acpi::checksum_ok;
+use crate::guid::known;
+use crate::{EFISystemTable, GUID};
+
+const SMBIOS2_ANCHOR: &[u8; 4] = b"_SM_";
+const SMBIOS3_ANCHOR: &[u8; 5] = b"_SM3_";
+const DMI_ANCHOR: &[u8; 5] = b"_DMI_";
+const SMBIOS2_ENTRY_LEN: usize = 0x1f;
+// SMBIOS 2.1 printed the entry point length as 0x1e, firmware written against it still reports
+// that for the same 0x1f byte structure.
+const SMBIOS2_MIN_ENTRY_LEN: usize = 0x1e;
+const SMBIOS3_ENTRY_LEN: usize = 0x18;
+// Offset of the intermediate (_DMI_) part of the 2.x entry point.
+const DMI_OFFSET: usize = 0x10;
+const STRUCTURE_HEADER_LEN: usize = 4;
+
+pub const TYPE_BIOS_INFORMATION: u8 = 0;
+pub const TYPE_SYSTEM_INFORMATION: u8 = 1;
+pub const TYPE_PROCESSOR: u8 = 4;
+pub const TYPE_MEMORY_DEVICE: u8 = 17;
+pub const TYPE_END_OF_TABLE: u8 = 127;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SmbiosError {
+    /// Neither SMBIOS configuration table is published.
+    NotFound,
+    InvalidAnchor,
+    InvalidChecksum,
+    InvalidLength,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// The parts of either entry point needed to find the structure table.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EntryPoint {
+    pub major: u8,
+    pub minor: u8,
+    pub table_address: u64,
+    /// Exact length for 2.x, only an upper bound for 3.x.
+    pub table_len: usize,
+    /// 3.x entry points don't carry a count, the table ends with a type 127 record.
+    pub structure_count: Option<u16>,
+}
+
+impl EntryPoint {
+    /// Parses and validates a 2.x (`_SM_`) or 3.x (`_SM3_`) entry point.
+    ///
+    /// # Safety
+    /// `ptr` must point to a readable entry point, as published in the configuration table.
+    pub unsafe fn from_ptr(ptr: *const u8) -> Result<Self, SmbiosError> {
+        let anchor = unsafe { slice::from_raw_parts(ptr, SMBIOS3_ANCHOR.len()) };
+        let (len_needed, is_v3) = if anchor == SMBIOS3_ANCHOR {
+            (SMBIOS3_ENTRY_LEN, true)
+        } else if anchor[..4] == *SMBIOS2_ANCHOR {
+            (SMBIOS2_MIN_ENTRY_LEN, false)
+        } else {
+            return Err(SmbiosError::InvalidAnchor);
+        };
+        let len_offset = if is_v3 { 0x06 } else { 0x05 };
+        let len = unsafe { *ptr.add(len_offset) } as usize;
+        if len < len_needed {
+            return Err(SmbiosError::InvalidLength);
+        }
+        if is_v3 {
+            Self::parse_v3(unsafe { slice::from_raw_parts(ptr, len) })
+        } else {
+            // The checksum covers the reported length, the structure is read in full.
+            let bytes = unsafe { slice::from_raw_parts(ptr, len.max(SMBIOS2_ENTRY_LEN)) };
+            Self::parse_v2(bytes, len)
+        }
+    }
+
+    fn parse_v2(bytes: &[u8], len: usize) -> Result<Self, SmbiosError> {
+        if !checksum_ok(&bytes[..len]) {
+            return Err(SmbiosError::InvalidChecksum);
+        }
+        let dmi = &bytes[DMI_OFFSET..SMBIOS2_ENTRY_LEN];
+        if dmi[..5] != *DMI_ANCHOR {
+            return Err(SmbiosError::InvalidAnchor);
+        }
+        if !checksum_ok(dmi) {
+            return Err(SmbiosError::InvalidChecksum);
+        }
+        Ok(Self {
+            major: bytes[0x06],
+            minor: bytes[0x07],
+            table_address: read_u32(bytes, 0x18).unwrap() as u64,
+            table_len: read_u16(bytes, 0x16).unwrap() as usize,
+            structure_count: read_u16(bytes, 0x1c),
+        })
+    }
+
+    fn parse_v3(bytes: &[u8]) -> Result<Self, SmbiosError> {
+        if !checksum_ok(bytes) {
+            return Err(SmbiosError::InvalidChecksum);
+        }
+        Ok(Self {
+            major: bytes[0x07],
+            minor: bytes[0x08],
+            table_address: read_u64(bytes, 0x10).unwrap(),
+            table_len: read_u32(bytes, 0x0c).unwrap() as usize,
+            structure_count: None,
+        })
+    }
+}
+
+/// One structure record: the formatted area followed by its string set.
+#[derive(Clone, Copy, Debug)]
+pub struct Structure<'a> {
+    pub ty: u8,
+    pub handle: u16,
+    /// Formatted area, header included so offsets match the spec tables.
+    pub formatted: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Structure<'a> {
+    /// Strings are numbered from 1, 0 means "no string".
+    pub fn string(&self, index: u8) -> Option<&'a str> {
+        let index = (index as usize).checked_sub(1)?;
+        let raw = self.strings().nth(index)?;
+        core::str::from_utf8(raw).ok()
+    }
+
+    /// Raw strings of the string set, in order.
+    pub fn strings(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.strings
+            .split(|b| *b == 0)
+            .take_while(|s| !s.is_empty())
+    }
+
+    pub fn byte(&self, offset: usize) -> Option<u8> {
+        self.formatted.get(offset).copied()
+    }
+
+    pub fn word(&self, offset: usize) -> Option<u16> {
+        read_u16(self.formatted, offset)
+    }
+
+    pub fn dword(&self, offset: usize) -> Option<u32> {
+        read_u32(self.formatted, offset)
+    }
+
+    fn string_at(&self, offset: usize) -> Option<&'a str> {
+        self.string(self.byte(offset)?)
+    }
+}
+
+/// Walks the structure table, stops at the end-of-table record or at the first malformed one.
+pub struct Structures<'a> {
+    bytes: &'a [u8],
+    remaining: Option<u16>,
+}
+
+impl<'a> Structures<'a> {
+    pub fn new(bytes: &'a [u8], structure_count: Option<u16>) -> Self {
+        Self {
+            bytes,
+            remaining: structure_count,
+        }
+    }
+}
+
+impl<'a> Iterator for Structures<'a> {
+    type Item = Structure<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == Some(0) || self.bytes.len() < STRUCTURE_HEADER_LEN {
+            return None;
+        }
+        let ty = self.bytes[0];
+        let len = self.bytes[1] as usize;
+        if len < STRUCTURE_HEADER_LEN || len > self.bytes.len() {
+            self.bytes = &[];
+            return None;
+        }
+        let (formatted, rest) = self.bytes.split_at(len);
+        // The string set ends with a double null, even when it is empty.
+        let Some(end) = rest.windows(2).position(|w| w == [0, 0]) else {
+            self.bytes = &[];
+            return None;
+        };
+        let strings = &rest[..end + 1];
+        self.bytes = &rest[end + 2..];
+        self.remaining = self.remaining.map(|n| n - 1);
+        if ty == TYPE_END_OF_TABLE {
+            self.bytes = &[];
+        }
+        Some(Structure {
+            ty,
+            handle: read_u16(formatted, 2).unwrap(),
+            formatted,
+            strings,
+        })
+    }
+}
+
+/// Type 0.
+#[derive(Clone, Copy, Debug)]
+pub struct BiosInfo<'a> {
+    pub vendor: Option<&'a str>,
+    pub version: Option<&'a str>,
+    pub release_date: Option<&'a str>,
+    /// `(major, minor)` system BIOS release, SMBIOS 2.4 and later.
+    pub release: Option<(u8, u8)>,
+}
+
+impl<'a> BiosInfo<'a> {
+    pub fn parse(s: &Structure<'a>) -> Option<Self> {
+        if s.ty != TYPE_BIOS_INFORMATION {
+            return None;
+        }
+        let release = match (s.byte(0x14), s.byte(0x15)) {
+            (Some(0xff), Some(0xff)) => None,
+            (Some(major), Some(minor)) => Some((major, minor)),
+            _ => None,
+        };
+        Some(Self {
+            vendor: s.string_at(0x04),
+            version: s.string_at(0x05),
+            release_date: s.string_at(0x08),
+            release,
+        })
+    }
+}
+
+/// Type 1.
+#[derive(Clone, Copy, Debug)]
+pub struct SystemInfo<'a> {
+    pub manufacturer: Option<&'a str>,
+    pub product_name: Option<&'a str>,
+    pub version: Option<&'a str>,
+    pub serial_number: Option<&'a str>,
+    /// `None` when the firmware reports it as not present (all zero) or not set (all 0xff).
+    pub uuid: Option<GUID>,
+    pub sku_number: Option<&'a str>,
+    pub family: Option<&'a str>,
+}
+
+impl<'a> SystemInfo<'a> {
+    pub fn parse(s: &Structure<'a>) -> Option<Self> {
+        if s.ty != TYPE_SYSTEM_INFORMATION {
+            return None;
+        }
+        // Since SMBIOS 2.6 the first three fields are little endian, the same layout as GUID.
+        let uuid = s
+            .formatted
+            .get(0x08..0x18)
+            .and_then(|b| <[u8; 16]>::try_from(b).ok())
+            .filter(|b| b.iter().any(|x| *x != 0) && b.iter().any(|x| *x != 0xff))
+            .map(GUID::from_bytes);
+        Some(Self {
+            manufacturer: s.string_at(0x04),
+            product_name: s.string_at(0x05),
+            version: s.string_at(0x06),
+            serial_number: s.string_at(0x07),
+            uuid,
+            sku_number: s.string_at(0x19),
+            family: s.string_at(0x1a),
+        })
+    }
+}
+
+/// Type 4.
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessorInfo<'a> {
+    pub socket: Option<&'a str>,
+    pub manufacturer: Option<&'a str>,
+    pub version: Option<&'a str>,
+    pub max_speed_mhz: Option<u16>,
+    pub current_speed_mhz: Option<u16>,
+    pub core_count: Option<u16>,
+    pub thread_count: Option<u16>,
+}
+
+impl<'a> ProcessorInfo<'a> {
+    pub fn parse(s: &Structure<'a>) -> Option<Self> {
+        if s.ty != TYPE_PROCESSOR {
+            return None;
+        }
+        // 0xff in the byte wide counts points at the 3.0 word wide fields.
+        let count = |byte_offset, word_offset| match s.byte(byte_offset) {
+            Some(0) | None => None,
+            Some(0xff) => s.word(word_offset).filter(|n| *n != 0),
+            Some(n) => Some(n as u16),
+        };
+        Some(Self {
+            socket: s.string_at(0x04),
+            manufacturer: s.string_at(0x07),
+            version: s.string_at(0x10),
+            max_speed_mhz: s.word(0x14).filter(|n| *n != 0),
+            current_speed_mhz: s.word(0x16).filter(|n| *n != 0),
+            core_count: count(0x23, 0x2a),
+            thread_count: count(0x25, 0x2e),
+        })
+    }
+}
+
+/// Type 17.
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryDevice<'a> {
+    /// Size in MiB, `Some(0)` for an empty slot, `None` when unknown.
+    pub size_mib: Option<u64>,
+    pub device_locator: Option<&'a str>,
+    pub bank_locator: Option<&'a str>,
+    pub speed_mts: Option<u16>,
+    pub manufacturer: Option<&'a str>,
+    pub serial_number: Option<&'a str>,
+    pub part_number: Option<&'a str>,
+}
+
+impl<'a> MemoryDevice<'a> {
+    pub fn parse(s: &Structure<'a>) -> Option<Self> {
+        if s.ty != TYPE_MEMORY_DEVICE {
+            return None;
+        }
+        let size_mib = match s.word(0x0c)? {
+            0xffff => None,
+            // Too big for the word, the real size is in the 2.7 extended field.
+            0x7fff => s.dword(0x1c).map(|mib| (mib & 0x7fff_ffff) as u64),
+            // Bit 15 set means the value is in KiB, rounded up so a small device doesn't read
+            // as an empty slot.
+            kib if kib & 0x8000 != 0 => Some(((kib & 0x7fff) as u64).div_ceil(1024)),
+            mib => Some(mib as u64),
+        };
+        Some(Self {
+            size_mib,
+            device_locator: s.string_at(0x10),
+            bank_locator: s.string_at(0x11),
+            speed_mts: s.word(0x15).filter(|n| *n != 0),
+            manufacturer: s.string_at(0x17),
+            serial_number: s.string_at(0x18),
+            part_number: s.string_at(0x1a),
+        })
+    }
+}
+
+/// The structure table an entry point describes.
+#[derive(Clone, Copy)]
+pub struct SmbiosTable {
+    pub entry_point: EntryPoint,
+    bytes: &'static [u8],
+}
+
+impl SmbiosTable {
+    /// # Safety
+    /// The table `entry_point` describes must be readable and identity mapped.
+    pub unsafe fn new(entry_point: EntryPoint) -> Self {
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                entry_point.table_address as *const u8,
+                entry_point.table_len,
+            )
+        };
+        Self { entry_point, bytes }
+    }
+
+    pub fn structures(&self) -> Structures<'static> {
+        Structures::new(self.bytes, self.entry_point.structure_count)
+    }
+
+    pub fn of_type(&self, ty: u8) -> impl Iterator<Item = Structure<'static>> {
+        self.structures().filter(move |s| s.ty == ty)
+    }
+
+    pub fn bios(&self) -> Option<BiosInfo<'static>> {
+        BiosInfo::parse(&self.of_type(TYPE_BIOS_INFORMATION).next()?)
+    }
+
+    pub fn system(&self) -> Option<SystemInfo<'static>> {
+        SystemInfo::parse(&self.of_type(TYPE_SYSTEM_INFORMATION).next()?)
+    }
+
+    pub fn processors(&self) -> impl Iterator<Item = ProcessorInfo<'static>> {
+        self.of_type(TYPE_PROCESSOR)
+            .filter_map(|s| ProcessorInfo::parse(&s))
+    }
+
+    pub fn memory_devices(&self) -> impl Iterator<Item = MemoryDevice<'static>> {
+        self.of_type(TYPE_MEMORY_DEVICE)
+            .filter_map(|s| MemoryDevice::parse(&s))
+    }
+}
+
+/// Entry point from the configuration tables, SMBIOS3 is preferred over 2.x.
+pub fn find_entry_point(st: &EFISystemTable) -> Result<EntryPoint, SmbiosError> {
+    let mut last_err = SmbiosError::NotFound;
+    for guid in [known::SMBIOS3_TABLE, known::SMBIOS_TABLE] {
+        let Some(table) = st.find_config_table(&guid) else {
+            continue;
+        };
+        match unsafe { EntryPoint::from_ptr(table as *const u8) } {
+            Ok(entry) => return Ok(entry),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// The system's SMBIOS table.
+pub fn table() -> Result<SmbiosTable, SmbiosError> {
+    let st = EFISystemTable::fetch_global().ok_or(SmbiosError::NotFound)?;
+    Ok(unsafe { SmbiosTable::new(find_entry_point(st)?) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EFIConfigurationTable;
+    use crate::mock::MockFirmware;
+    use alloc::boxed::Box;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    const UUID: GUID = crate::guid!("b3e4c1a2-1d2e-4f50-9a7b-0c1d2e3f4a5b");
+
+    fn record(ty: u8, handle: u16, body: &[u8], strings: &[&str]) -> Vec<u8> {
+        let mut out = vec![ty, (4 + body.len()) as u8];
+        out.extend_from_slice(&handle.to_le_bytes());
+        out.extend_from_slice(body);
+        for s in strings {
+            out.extend_from_slice(s.as_bytes());
+            out.push(0);
+        }
+        if strings.is_empty() {
+            out.push(0);
+        }
+        out.push(0);
+        out
+    }
+
+    // `fields` use the spec offsets, which count the 4 byte header.
+    fn body(len: usize, fields: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut body = vec![0u8; len - 4];
+        for (offset, bytes) in fields {
+            body[offset - 4..offset - 4 + bytes.len()].copy_from_slice(bytes);
+        }
+        body
+    }
+
+    fn sample_table() -> Vec<u8> {
+        let mut t = Vec::new();
+        t.extend(record(
+            0,
+            0,
+            &body(0x18, &[(0x04, &[1, 2]), (0x08, &[3]), (0x14, &[1, 16])]),
+            &["EFI Development Kit II / OVMF", "0.0.0", "02/06/2015"],
+        ));
+        t.extend(record(
+            1,
+            0x100,
+            &body(
+                0x1b,
+                &[
+                    (0x04, &[1, 2]),
+                    (0x07, &[0]),
+                    (0x08, &UUID.to_bytes()),
+                    (0x1a, &[3]),
+                ],
+            ),
+            &["QEMU", "Standard PC (Q35 + ICH9, 2009)", "fi"],
+        ));
+        t.extend(record(
+            4,
+            0x400,
+            &body(
+                0x30,
+                &[
+                    (0x04, &[1]),
+                    (0x07, &[2]),
+                    (0x14, &2000u16.to_le_bytes()),
+                    (0x16, &2000u16.to_le_bytes()),
+                    (0x23, &[0xff]),
+                    (0x25, &[4]),
+                    (0x2a, &300u16.to_le_bytes()),
+                ],
+            ),
+            &["CPU 0", "QEMU"],
+        ));
+        t.extend(record(
+            17,
+            0x1100,
+            &body(
+                0x22,
+                &[
+                    (0x0c, &0x7fffu16.to_le_bytes()),
+                    (0x10, &[1]),
+                    (0x1c, &(64 * 1024u32).to_le_bytes()),
+                ],
+            ),
+            &["DIMM 0"],
+        ));
+        t.extend(record(
+            17,
+            0x1101,
+            &body(0x22, &[(0x0c, &0x8800u16.to_le_bytes())]),
+            &[],
+        ));
+        t.extend(record(127, 0xfeff, &[], &[]));
+        // Junk after the end of table record must not be parsed.
+        t.extend([1, 4, 0, 0, 0, 0]);
+        t
+    }
+
+    fn leak(bytes: Vec<u8>) -> *mut u8 {
+        Box::leak(bytes.into_boxed_slice()).as_mut_ptr()
+    }
+
+    fn entry_v3(table: &[u8]) -> *mut u8 {
+        let mut e = vec![0u8; SMBIOS3_ENTRY_LEN];
+        e[..5].copy_from_slice(SMBIOS3_ANCHOR);
+        e[6] = SMBIOS3_ENTRY_LEN as u8;
+        e[7] = 3;
+        e[8] = 2;
+        e[0x0c..0x10].copy_from_slice(&(table.len() as u32).to_le_bytes());
+        e[0x10..0x18].copy_from_slice(&(table.as_ptr() as u64).to_le_bytes());
+        let sum = e.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+        e[5] = sum.wrapping_neg();
+        leak(e)
+    }
+
+    #[test]
+    fn test_structures_and_strings() {
+        let table = sample_table();
+        let structures: Vec<_> = Structures::new(&table, None).collect();
+        let types: Vec<_> = structures.iter().map(|s| s.ty).collect();
+        assert_eq!(types, [0, 1, 4, 17, 17, 127]);
+        assert_eq!(structures[1].handle, 0x100);
+        assert_eq!(
+            structures[1].string(2),
+            Some("Standard PC (Q35 + ICH9, 2009)")
+        );
+        assert_eq!(structures[1].string(0), None);
+        assert_eq!(structures[1].string(4), None);
+        assert_eq!(structures[4].strings().count(), 0);
+
+        // The 2.x count stops the walk early.
+        assert_eq!(Structures::new(&table, Some(2)).count(), 2);
+    }
+
+    #[test]
+    fn test_truncated_record_stops_iteration() {
+        let mut table = sample_table();
+        table.truncate(10);
+        assert_eq!(Structures::new(&table, None).count(), 0);
+    }
+
+    #[test]
+    fn test_decode_from_firmware() {
+        let table = leak(sample_table());
+        let len = sample_table().len();
+        let entry = entry_v3(unsafe { slice::from_raw_parts(table, len) });
+        let _fw = MockFirmware::install_with(|s| {
+            s.config_tables = vec![EFIConfigurationTable {
+                vendor_guid: known::SMBIOS3_TABLE,
+                vendor_table: entry.cast(),
+            }]
+        });
+
+        let smbios = super::table().unwrap();
+        assert_eq!((smbios.entry_point.major, smbios.entry_point.minor), (3, 2));
+
+        let bios = smbios.bios().unwrap();
+        assert_eq!(bios.vendor, Some("EFI Development Kit II / OVMF"));
+        assert_eq!(bios.release_date, Some("02/06/2015"));
+        assert_eq!(bios.release, Some((1, 16)));
+
+        let system = smbios.system().unwrap();
+        assert_eq!(system.manufacturer, Some("QEMU"));
+        assert_eq!(system.serial_number, None);
+        assert_eq!(system.uuid, Some(UUID));
+        assert_eq!(system.family, Some("fi"));
+
+        let cpu = smbios.processors().next().unwrap();
+        assert_eq!(cpu.socket, Some("CPU 0"));
+        assert_eq!(cpu.max_speed_mhz, Some(2000));
+        assert_eq!(cpu.core_count, Some(300));
+        assert_eq!(cpu.thread_count, Some(4));
+
+        let dimms: Vec<_> = smbios.memory_devices().collect();
+        assert_eq!(dimms[0].size_mib, Some(64 * 1024));
+        assert_eq!(dimms[0].device_locator, Some("DIMM 0"));
+        assert_eq!(dimms[1].size_mib, Some(2));
+    }
+
+    #[test]
+    fn test_memory_device_sizes() {
+        let size = |word: u16| {
+            let bytes = record(17, 0x1102, &body(0x22, &[(0x0c, &word.to_le_bytes())]), &[]);
+            let s = Structures::new(&bytes, None).next().unwrap();
+            MemoryDevice::parse(&s).unwrap().size_mib
+        };
+        assert_eq!(size(0), Some(0));
+        assert_eq!(size(0x8000 | 512), Some(1));
+        assert_eq!(size(0x8000 | 1024), Some(1));
+        assert_eq!(size(0xffff), None);
+    }
+
+    #[test]
+    fn test_v2_entry_point() {
+        let table = leak(sample_table());
+        let mut e = vec![0u8; SMBIOS2_ENTRY_LEN];
+        e[..4].copy_from_slice(SMBIOS2_ANCHOR);
+        e[5] = SMBIOS2_ENTRY_LEN as u8;
+        e[6] = 2;
+        e[7] = 8;
+        e[0x10..0x15].copy_from_slice(DMI_ANCHOR);
+        e[0x16..0x18].copy_from_slice(&100u16.to_le_bytes());
+        // Only the parse is checked, the address is not dereferenced.
+        e[0x18..0x1c].copy_from_slice(&(table as u64 as u32).to_le_bytes());
+        e[0x1c..0x1e].copy_from_slice(&6u16.to_le_bytes());
+        let dmi_sum = e[0x10..].iter().fold(0u8, |s, b| s.wrapping_add(*b));
+        e[0x15] = dmi_sum.wrapping_neg();
+        let sum = e.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+        e[4] = sum.wrapping_neg();
+
+        let entry = unsafe { EntryPoint::from_ptr(leak(e.clone())) }.unwrap();
+        assert_eq!((entry.major, entry.minor), (2, 8));
+        assert_eq!(entry.table_len, 100);
+        assert_eq!(entry.structure_count, Some(6));
+
+        // The 2.1 length, the outer checksum only covers 0x1e bytes.
+        let mut short = e.clone();
+        short[5] = SMBIOS2_MIN_ENTRY_LEN as u8;
+        short[0x1e] = 0x21;
+        short[0x15] = 0;
+        let dmi_sum = short[0x10..].iter().fold(0u8, |s, b| s.wrapping_add(*b));
+        short[0x15] = dmi_sum.wrapping_neg();
+        short[4] = 0;
+        let sum = short[..SMBIOS2_MIN_ENTRY_LEN]
+            .iter()
+            .fold(0u8, |s, b| s.wrapping_add(*b));
+        short[4] = sum.wrapping_neg();
+        let entry = unsafe { EntryPoint::from_ptr(leak(short.clone())) }.unwrap();
+        assert_eq!(entry.structure_count, Some(6));
+        short[5] = 0x1d;
+        assert_eq!(
+            unsafe { EntryPoint::from_ptr(leak(short)) },
+            Err(SmbiosError::InvalidLength)
+        );
+
+        e[0x1c] = 7;
+        assert_eq!(
+            unsafe { EntryPoint::from_ptr(leak(e)) },
+            Err(SmbiosError::InvalidChecksum)
+        );
+    }
+}
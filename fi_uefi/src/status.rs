@@ -0,0 +1,168 @@
+use core::fmt;
+
+const ERROR_BIT: u64 = 1 << 63;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct EFIStatus(pub u64);
+
+macro_rules! efi_status_codes {
+    (
+        success: [$($s_name:ident = $s_val:literal),* $(,)?],
+        warnings: [$($w_name:ident = $w_val:literal),* $(,)?],
+        errors: [$($e_name:ident = $e_val:literal),* $(,)?] $(,)?
+    ) => {
+        impl EFIStatus {
+            $(pub const $s_name: Self = Self($s_val);)*
+            $(pub const $w_name: Self = Self($w_val);)*
+            $(pub const $e_name: Self = Self(ERROR_BIT | $e_val);)*
+
+            pub fn name(&self) -> Option<&'static str> {
+                match *self {
+                    $(Self::$s_name => Some(stringify!($s_name)),)*
+                    $(Self::$w_name => Some(stringify!($w_name)),)*
+                    $(Self::$e_name => Some(stringify!($e_name)),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+efi_status_codes! {
+    success: [SUCCESS = 0],
+    warnings: [
+        WARN_UNKNOWN_GLYPH = 1,
+        WARN_DELETE_FAILURE = 2,
+        WARN_WRITE_FAILURE = 3,
+        WARN_BUFFER_TOO_SMALL = 4,
+        WARN_STALE_DATA = 5,
+        WARN_FILE_SYSTEM = 6,
+        WARN_RESET_REQUIRED = 7,
+    ],
+    errors: [
+        LOAD_ERROR = 1,
+        INVALID_PARAMETER = 2,
+        UNSUPPORTED = 3,
+        BAD_BUFFER_SIZE = 4,
+        BUFFER_TOO_SMALL = 5,
+        NOT_READY = 6,
+        DEVICE_ERROR = 7,
+        WRITE_PROTECTED = 8,
+        OUT_OF_RESOURCES = 9,
+        VOLUME_CORRUPTED = 10,
+        VOLUME_FULL = 11,
+        NO_MEDIA = 12,
+        MEDIA_CHANGED = 13,
+        NOT_FOUND = 14,
+        ACCESS_DENIED = 15,
+        NO_RESPONSE = 16,
+        NO_MAPPING = 17,
+        TIMEOUT = 18,
+        NOT_STARTED = 19,
+        ALREADY_STARTED = 20,
+        ABORTED = 21,
+        ICMP_ERROR = 22,
+        TFTP_ERROR = 23,
+        PROTOCOL_ERROR = 24,
+        INCOMPATIBLE_VERSION = 25,
+        SECURITY_VIOLATION = 26,
+        CRC_ERROR = 27,
+        END_OF_MEDIA = 28,
+        END_OF_FILE = 31,
+        INVALID_LANGUAGE = 32,
+        COMPROMISED_DATA = 33,
+        IP_ADDRESS_CONFLICT = 34,
+        HTTP_ERROR = 35,
+    ],
+}
+
+impl EFIStatus {
+    pub const fn is_success(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn is_error(&self) -> bool {
+        self.0 & ERROR_BIT != 0
+    }
+
+    pub const fn is_warning(&self) -> bool {
+        !self.is_success() && !self.is_error()
+    }
+
+    /// Warnings are treated as success, only the error bit produces an `Err`.
+    pub fn to_result(self) -> Result<(), EFIStatus> {
+        self.to_result_with(())
+    }
+
+    pub fn to_result_with<T>(self, val: T) -> Result<T, EFIStatus> {
+        if self.is_error() { Err(self) } else { Ok(val) }
+    }
+}
+
+impl From<u64> for EFIStatus {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<EFIStatus> for u64 {
+    fn from(value: EFIStatus) -> Self {
+        value.0
+    }
+}
+
+impl fmt::Debug for EFIStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => write!(f, "EFIStatus::{name}"),
+            None => write!(f, "EFIStatus({:#x})", self.0),
+        }
+    }
+}
+
+impl fmt::Display for EFIStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.name() {
+            Some(name) => f.write_str(name),
+            None if self.is_error() => write!(f, "ERROR({:#x})", self.0 & !ERROR_BIT),
+            None => write!(f, "WARNING({:#x})", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        assert!(EFIStatus::SUCCESS.is_success());
+        assert!(EFIStatus::WARN_STALE_DATA.is_warning());
+        assert!(EFIStatus::NOT_FOUND.is_error());
+        assert!(!EFIStatus::NOT_FOUND.is_warning());
+    }
+
+    #[test]
+    fn test_error_values_match_spec() {
+        assert_eq!(EFIStatus::BUFFER_TOO_SMALL.0, 0x8000_0000_0000_0005);
+        assert_eq!(EFIStatus::HTTP_ERROR.0, 0x8000_0000_0000_0023);
+        assert_eq!(EFIStatus::WARN_RESET_REQUIRED.0, 7);
+    }
+
+    #[test]
+    fn test_to_result() {
+        assert_eq!(EFIStatus::SUCCESS.to_result(), Ok(()));
+        assert_eq!(EFIStatus::WARN_UNKNOWN_GLYPH.to_result_with(3), Ok(3));
+        assert_eq!(
+            EFIStatus::OUT_OF_RESOURCES.to_result(),
+            Err(EFIStatus::OUT_OF_RESOURCES)
+        );
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(EFIStatus::TIMEOUT.name(), Some("TIMEOUT"));
+        assert_eq!(EFIStatus(ERROR_BIT | 0x1000).name(), None);
+    }
+}
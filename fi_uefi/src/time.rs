@@ -0,0 +1,280 @@
+use core::fmt;
+
+use crate::{EFIRuntimeServices, EFIStatus, EFISystemTable, EFITime};
+
+/// `EFITime::timezone` for a local time with no known offset.
+pub const UNSPECIFIED_TIMEZONE: i16 = 0x07ff;
+
+/// `EFITime::daylight` bits.
+pub const ADJUST_DAYLIGHT: u8 = 0x01;
+pub const IN_DAYLIGHT: u8 = 0x02;
+
+const SECS_PER_DAY: i64 = 86_400;
+const MAX_TIMEZONE: i16 = 1440;
+
+const fn is_leap_year(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+const fn days_in_month(year: i64, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date, Howard Hinnant's days_from_civil.
+const fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// Inverse of `days_from_civil`, returns `(year, month, day)`.
+const fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+impl EFITime {
+    /// Checks every field against the ranges the spec allows for `GetTime`/`SetTime`.
+    pub fn is_valid(&self) -> bool {
+        let year = self.year as i64;
+        (1900..=9999).contains(&self.year)
+            && (1..=12).contains(&self.month)
+            && self.day >= 1
+            && self.day <= days_in_month(year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+            && self.nanosecond < 1_000_000_000
+            && (self.timezone == UNSPECIFIED_TIMEZONE
+                || (-MAX_TIMEZONE..=MAX_TIMEZONE).contains(&self.timezone))
+            && self.daylight & !(ADJUST_DAYLIGHT | IN_DAYLIGHT) == 0
+    }
+
+    /// Minutes local time is ahead of UTC, daylight saving included. `None` when the timezone is
+    /// unspecified.
+    ///
+    /// Follows UEFI 2.7 and later, `Localtime = UTC + TimeZone`, older specs had the sign the
+    /// other way around.
+    pub fn utc_offset_minutes(&self) -> Option<i32> {
+        if self.timezone == UNSPECIFIED_TIMEZONE {
+            return None;
+        }
+        let dst = if self.daylight & IN_DAYLIGHT != 0 {
+            60
+        } else {
+            0
+        };
+        Some(self.timezone as i32 + dst)
+    }
+
+    /// Seconds since the Unix epoch, `None` if the time is not valid. An unspecified timezone is
+    /// taken as UTC, which is what most firmware keeps the RTC in.
+    pub fn to_unix(&self) -> Option<i64> {
+        if !self.is_valid() {
+            return None;
+        }
+        let days = days_from_civil(self.year as i64, self.month as i64, self.day as i64);
+        let local = days * SECS_PER_DAY
+            + self.hour as i64 * 3600
+            + self.minute as i64 * 60
+            + self.second as i64;
+        Some(local - self.utc_offset_minutes().unwrap_or(0) as i64 * 60)
+    }
+
+    /// Builds the local time for `secs` since the Unix epoch in the given timezone and daylight
+    /// state. `None` if the result falls outside the years `EFITime` can hold.
+    pub fn from_unix(secs: i64, timezone: i16, daylight: u8) -> Option<Self> {
+        let mut time = Self {
+            timezone,
+            daylight,
+            ..Self::default()
+        };
+        let local = secs.checked_add(time.utc_offset_minutes().unwrap_or(0) as i64 * 60)?;
+        let (year, month, day) = civil_from_days(local.div_euclid(SECS_PER_DAY));
+        let secs_of_day = local.rem_euclid(SECS_PER_DAY);
+
+        time.year = u16::try_from(year).ok()?;
+        time.month = month;
+        time.day = day;
+        time.hour = (secs_of_day / 3600) as u8;
+        time.minute = (secs_of_day / 60 % 60) as u8;
+        time.second = (secs_of_day % 60) as u8;
+        time.is_valid().then_some(time)
+    }
+}
+
+/// ISO-8601, e.g. `2024-02-29T12:30:15Z` or `2024-02-29T13:30:15.5+01:00`. Times with an
+/// unspecified timezone are printed without an offset.
+impl fmt::Display for EFITime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        if self.nanosecond != 0 {
+            let mut digits = 9;
+            let mut fraction = self.nanosecond;
+            while fraction.is_multiple_of(10) {
+                fraction /= 10;
+                digits -= 1;
+            }
+            write!(f, ".{fraction:0digits$}")?;
+        }
+        match self.utc_offset_minutes() {
+            None => Ok(()),
+            Some(0) => f.write_str("Z"),
+            Some(offset) => {
+                let sign = if offset < 0 { '-' } else { '+' };
+                let offset = offset.unsigned_abs();
+                write!(f, "{sign}{:02}:{:02}", offset / 60, offset % 60)
+            }
+        }
+    }
+}
+
+impl EFIRuntimeServices {
+    /// Runtime services stay usable after `exit_boot_services`, until the address map is
+    /// switched to virtual.
+    pub fn fetch_global() -> Option<&'static Self> {
+        let st = EFISystemTable::fetch_global()?;
+        unsafe { st.runtime_services.as_ref() }
+    }
+
+    /// Current time from the RTC, `DEVICE_ERROR` if the firmware hands back garbage.
+    pub fn now(&self) -> Result<EFITime, EFIStatus> {
+        let (time, _) = self.get_time()?;
+        if !time.is_valid() {
+            return Err(EFIStatus::DEVICE_ERROR);
+        }
+        Ok(time)
+    }
+
+    /// Seconds since the Unix epoch.
+    pub fn unix_time(&self) -> Result<i64, EFIStatus> {
+        self.now()?.to_unix().ok_or(EFIStatus::DEVICE_ERROR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockFirmware, with_state};
+    use alloc::format;
+
+    const LEAP_DAY: i64 = 1_709_209_815;
+
+    fn utc(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> EFITime {
+        EFITime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            timezone: 0,
+            ..EFITime::default()
+        }
+    }
+
+    #[test]
+    fn test_unix_round_trip() {
+        let time = utc(2024, 2, 29, 12, 30, 15);
+        assert_eq!(time.to_unix(), Some(LEAP_DAY));
+        assert_eq!(EFITime::from_unix(LEAP_DAY, 0, 0), Some(time));
+        assert_eq!(utc(1970, 1, 1, 0, 0, 0).to_unix(), Some(0));
+        assert_eq!(utc(1969, 12, 31, 23, 59, 59).to_unix(), Some(-1));
+        assert_eq!(EFITime::from_unix(-1, 0, 0).unwrap().year, 1969);
+        assert_eq!(EFITime::from_unix(i64::MAX, 0, 0), None);
+    }
+
+    #[test]
+    fn test_timezone_and_daylight() {
+        // 13:30 in UTC+1 is 12:30 UTC, with daylight saving on top it is 11:30 UTC.
+        let mut time = utc(2024, 2, 29, 13, 30, 15);
+        time.timezone = 60;
+        assert_eq!(time.to_unix(), Some(LEAP_DAY));
+        time.daylight = ADJUST_DAYLIGHT | IN_DAYLIGHT;
+        assert_eq!(time.to_unix(), Some(LEAP_DAY - 3600));
+
+        let local = EFITime::from_unix(LEAP_DAY, -300, 0).unwrap();
+        assert_eq!((local.hour, local.minute), (7, 30));
+        assert_eq!(local.to_unix(), Some(LEAP_DAY));
+
+        time.timezone = UNSPECIFIED_TIMEZONE;
+        time.daylight = 0;
+        assert_eq!(time.to_unix(), Some(LEAP_DAY + 3600));
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(utc(2000, 2, 29, 23, 59, 59).is_valid());
+        assert!(!utc(1900, 2, 29, 0, 0, 0).is_valid());
+        assert!(!utc(2023, 2, 29, 0, 0, 0).is_valid());
+        assert!(!utc(2024, 4, 31, 0, 0, 0).is_valid());
+        assert!(!utc(2024, 13, 1, 0, 0, 0).is_valid());
+        assert!(!utc(2024, 1, 1, 24, 0, 0).is_valid());
+        let mut time = utc(2024, 1, 1, 0, 0, 0);
+        time.timezone = 1441;
+        assert!(!time.is_valid());
+        assert_eq!(time.to_unix(), None);
+    }
+
+    #[test]
+    fn test_iso_8601() {
+        let mut time = utc(2024, 2, 29, 12, 30, 15);
+        assert_eq!(format!("{time}"), "2024-02-29T12:30:15Z");
+        time.nanosecond = 500_000_000;
+        time.timezone = -330;
+        assert_eq!(format!("{time}"), "2024-02-29T12:30:15.5-05:30");
+        time.nanosecond = 0;
+        time.timezone = UNSPECIFIED_TIMEZONE;
+        assert_eq!(format!("{time}"), "2024-02-29T12:30:15");
+    }
+
+    #[test]
+    fn test_now_from_firmware() {
+        let _fw = MockFirmware::install();
+        let rt = EFIRuntimeServices::fetch_global().unwrap();
+        assert_eq!(rt.unix_time(), Ok(LEAP_DAY));
+
+        let later = EFITime::from_unix(LEAP_DAY + 60, 0, 0).unwrap();
+        rt.set_time(&later).unwrap();
+        assert_eq!(rt.now(), Ok(later));
+        assert_eq!(
+            rt.set_time(&utc(2023, 2, 29, 0, 0, 0)),
+            Err(EFIStatus::INVALID_PARAMETER)
+        );
+
+        with_state(|s| s.time.month = 0);
+        assert_eq!(rt.now(), Err(EFIStatus::DEVICE_ERROR));
+    }
+
+    #[test]
+    fn test_wakeup_timer() {
+        let _fw = MockFirmware::install();
+        let rt = EFIRuntimeServices::fetch_global().unwrap();
+        let at = utc(2024, 3, 1, 6, 0, 0);
+        rt.set_wakeup_time(Some(&at)).unwrap();
+        assert_eq!(rt.get_wakeup_time(), Ok((true, false, at)));
+        rt.set_wakeup_time(None).unwrap();
+        assert!(!rt.get_wakeup_time().unwrap().0);
+    }
+}
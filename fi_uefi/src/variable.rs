@@ -0,0 +1,297 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::flags::flags;
+use crate::{EFIRuntimeServices, EFIStatus, GUID};
+
+// Most variables are a handful of bytes, the first read tries with this much.
+const INITIAL_DATA_LEN: usize = 64;
+const INITIAL_NAME_LEN: usize = 64;
+
+flags! {
+    pub struct VariableAttributes(u32) {
+        const NON_VOLATILE = 0x01;
+        const BOOTSERVICE_ACCESS = 0x02;
+        const RUNTIME_ACCESS = 0x04;
+        const HARDWARE_ERROR_RECORD = 0x08;
+        /// Deprecated by the spec, firmware may refuse it.
+        const AUTHENTICATED_WRITE_ACCESS = 0x10;
+        const TIME_BASED_AUTHENTICATED_WRITE_ACCESS = 0x20;
+        const APPEND_WRITE = 0x40;
+        const ENHANCED_AUTHENTICATED_ACCESS = 0x80;
+        /// Persistent and visible both before and after `exit_boot_services`.
+        const NV_BS_RT =
+            Self::NON_VOLATILE.0 | Self::BOOTSERVICE_ACCESS.0 | Self::RUNTIME_ACCESS.0;
+    }
+}
+
+/// Result of `QueryVariableInfo` for one attribute combination.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct VariableStorageInfo {
+    pub maximum_storage: u64,
+    pub remaining_storage: u64,
+    pub maximum_variable_size: u64,
+}
+
+/// Name and vendor of a variable, as returned by [`Variables::keys`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct VariableKey {
+    /// UTF-16 name without the terminator.
+    pub name: Vec<u16>,
+    pub vendor: GUID,
+}
+
+impl VariableKey {
+    pub fn name(&self) -> String {
+        char::decode_utf16(self.name.iter().copied())
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect()
+    }
+}
+
+fn encode_name(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain([0]).collect()
+}
+
+/// Typed access to the firmware variable store.
+///
+/// ```ignore
+/// let vars = EFIRuntimeServices::fetch_global().unwrap().variables();
+/// let (boot_order, _) = vars.get("BootOrder", &known::GLOBAL_VARIABLE)?;
+/// ```
+#[derive(Clone, Copy)]
+pub struct Variables<'a> {
+    rt: &'a EFIRuntimeServices,
+}
+
+impl<'a> Variables<'a> {
+    pub fn new(rt: &'a EFIRuntimeServices) -> Self {
+        Self { rt }
+    }
+
+    /// Reads a whole variable, growing the buffer until it fits.
+    pub fn get(
+        &self,
+        name: &str,
+        vendor: &GUID,
+    ) -> Result<(Vec<u8>, VariableAttributes), EFIStatus> {
+        let name = encode_name(name);
+        let mut data = vec![0u8; INITIAL_DATA_LEN];
+        loop {
+            let mut attributes = 0;
+            let mut size = data.len() as u64;
+            let status = unsafe {
+                (self.rt.get_variable)(
+                    name.as_ptr(),
+                    vendor,
+                    &mut attributes,
+                    &mut size,
+                    data.as_mut_ptr().cast(),
+                )
+            };
+            match status {
+                EFIStatus::BUFFER_TOO_SMALL => data.resize(size as usize, 0),
+                status => {
+                    status.to_result()?;
+                    data.truncate(size as usize);
+                    return Ok((data, VariableAttributes(attributes)));
+                }
+            }
+        }
+    }
+
+    /// Creates or replaces a variable. With `APPEND_WRITE` the data is added to the existing
+    /// value instead.
+    pub fn set(
+        &self,
+        name: &str,
+        vendor: &GUID,
+        attributes: VariableAttributes,
+        data: &[u8],
+    ) -> Result<(), EFIStatus> {
+        let mut name = encode_name(name);
+        let mut vendor = *vendor;
+        unsafe {
+            (self.rt.set_variable)(
+                name.as_mut_ptr(),
+                &mut vendor,
+                attributes.bits(),
+                data.len() as u64,
+                data.as_ptr().cast_mut().cast(),
+            )
+        }
+        .to_result()
+    }
+
+    /// `NOT_FOUND` if there was nothing to delete.
+    pub fn delete(&self, name: &str, vendor: &GUID) -> Result<(), EFIStatus> {
+        self.set(name, vendor, VariableAttributes::empty(), &[])
+    }
+
+    /// Every variable visible right now. Modifying the store while iterating makes the firmware
+    /// return `INVALID_PARAMETER`.
+    pub fn keys(&self) -> VariableKeys<'a> {
+        VariableKeys {
+            rt: self.rt,
+            name: vec![0u16; INITIAL_NAME_LEN],
+            vendor: GUID::zero(),
+            done: false,
+        }
+    }
+
+    pub fn query(&self, attributes: VariableAttributes) -> Result<VariableStorageInfo, EFIStatus> {
+        let (maximum_storage, remaining_storage, maximum_variable_size) =
+            self.rt.query_variable_info(attributes.bits())?;
+        Ok(VariableStorageInfo {
+            maximum_storage,
+            remaining_storage,
+            maximum_variable_size,
+        })
+    }
+}
+
+/// Iterator over `GetNextVariableName`, see [`Variables::keys`].
+pub struct VariableKeys<'a> {
+    rt: &'a EFIRuntimeServices,
+    // Holds the previous name for the next call, starts out as the empty string.
+    name: Vec<u16>,
+    vendor: GUID,
+    done: bool,
+}
+
+impl Iterator for VariableKeys<'_> {
+    type Item = Result<VariableKey, EFIStatus>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let mut size = (self.name.len() * 2) as u64;
+            let status = unsafe {
+                (self.rt.get_next_variable)(&mut size, self.name.as_mut_ptr(), &mut self.vendor)
+            };
+            match status {
+                EFIStatus::BUFFER_TOO_SMALL => self.name.resize((size as usize).div_ceil(2), 0),
+                EFIStatus::NOT_FOUND => {
+                    self.done = true;
+                    return None;
+                }
+                status => {
+                    if let Err(err) = status.to_result() {
+                        self.done = true;
+                        return Some(Err(err));
+                    }
+                    // Ending here would look like the end of the store.
+                    let Some(len) = self.name.iter().position(|c| *c == 0) else {
+                        self.done = true;
+                        return Some(Err(EFIStatus::VOLUME_CORRUPTED));
+                    };
+                    return Some(Ok(VariableKey {
+                        name: self.name[..len].to_vec(),
+                        vendor: self.vendor,
+                    }));
+                }
+            }
+        }
+    }
+}
+
+impl EFIRuntimeServices {
+    pub fn variables(&self) -> Variables<'_> {
+        Variables::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::guid::known;
+    use crate::mock::MockFirmware;
+
+    const VENDOR: GUID = crate::guid!("3b6f0a9c-2b8e-4d1a-9f44-6f1e0c2d7a10");
+
+    fn vars() -> Variables<'static> {
+        EFIRuntimeServices::fetch_global().unwrap().variables()
+    }
+
+    #[test]
+    fn test_set_get_delete() {
+        let _fw = MockFirmware::install();
+        let vars = vars();
+        assert_eq!(vars.get("Missing", &VENDOR), Err(EFIStatus::NOT_FOUND));
+
+        // Bigger than the first read attempt, has to go through the resize path.
+        let big: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        vars.set("Config", &VENDOR, VariableAttributes::NV_BS_RT, &big)
+            .unwrap();
+        let (data, attributes) = vars.get("Config", &VENDOR).unwrap();
+        assert_eq!(data, big);
+        assert_eq!(attributes, VariableAttributes::NV_BS_RT);
+
+        vars.set(
+            "Config",
+            &VENDOR,
+            VariableAttributes::NV_BS_RT | VariableAttributes::APPEND_WRITE,
+            &[0xff],
+        )
+        .unwrap();
+        assert_eq!(vars.get("Config", &VENDOR).unwrap().0.len(), 201);
+
+        vars.delete("Config", &VENDOR).unwrap();
+        assert_eq!(vars.get("Config", &VENDOR), Err(EFIStatus::NOT_FOUND));
+        assert_eq!(vars.delete("Config", &VENDOR), Err(EFIStatus::NOT_FOUND));
+    }
+
+    #[test]
+    fn test_keys_enumerates_everything() {
+        let _fw = MockFirmware::install();
+        let vars = vars();
+        let long_name: String = core::iter::repeat_n('x', 100).collect();
+        vars.set(
+            "BootOrder",
+            &known::GLOBAL_VARIABLE,
+            VariableAttributes::NV_BS_RT,
+            &[0, 0],
+        )
+        .unwrap();
+        vars.set(
+            &long_name,
+            &VENDOR,
+            VariableAttributes::BOOTSERVICE_ACCESS,
+            &[1],
+        )
+        .unwrap();
+
+        let keys: Vec<_> = vars.keys().collect::<Result<_, _>>().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].name(), "BootOrder");
+        assert_eq!(keys[0].vendor, known::GLOBAL_VARIABLE);
+        assert_eq!(keys[1].name(), long_name);
+        assert_eq!(keys[1].vendor, VENDOR);
+    }
+
+    #[test]
+    fn test_query_storage() {
+        let _fw = MockFirmware::install();
+        let vars = vars();
+        let before = vars.query(VariableAttributes::NV_BS_RT).unwrap();
+        vars.set("LastBoot", &VENDOR, VariableAttributes::NV_BS_RT, &[0; 16])
+            .unwrap();
+        let after = vars.query(VariableAttributes::NV_BS_RT).unwrap();
+        assert_eq!(after.maximum_storage, before.maximum_storage);
+        assert!(after.remaining_storage < before.remaining_storage);
+    }
+
+    #[test]
+    fn test_keys_unterminated_name_is_an_error() {
+        let _fw = MockFirmware::install_with(|s| s.unterminated_names = true);
+        let vars = vars();
+        vars.set("Boot0000", &VENDOR, VariableAttributes::NV_BS_RT, &[1])
+            .unwrap();
+        let mut keys = vars.keys();
+        assert_eq!(keys.next(), Some(Err(EFIStatus::VOLUME_CORRUPTED)));
+        assert_eq!(keys.next(), None);
+    }
+}
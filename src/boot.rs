@@ -0,0 +1,114 @@
+//! Carrying out a menu entry.
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+
+use fi_uefi::acpi::find_rsdp;
+use fi_uefi::elf::ElfImage;
+use fi_uefi::fs::read_file_to_vec;
+use fi_uefi::graphics::EFIGraphicsOutputProtocol;
+use fi_uefi::handoff::{BootInfo, exit_boot_services};
+use fi_uefi::image::boot_volume_file_path;
+use fi_uefi::{
+    EFIBootServices, EFIResetType, EFIRuntimeServices, EFIStatus, EFISystemTable, info, log,
+};
+
+use fi_os::config::Action;
+
+/// The kernel is entered with the System V calling convention, `BootInfo` in `rdi`.
+type KernelEntry = extern "sysv64" fn(info: *const BootInfo) -> !;
+
+/// Only comes back for chainloaded images, with a line about how they exited, and on errors.
+pub fn run(action: &Action) -> Result<String, (&'static str, EFIStatus)> {
+    let bs = EFIBootServices::fetch_global().ok_or(("boot services", EFIStatus::NOT_READY))?;
+    let rt =
+        EFIRuntimeServices::fetch_global().ok_or(("runtime services", EFIStatus::NOT_READY))?;
+    match action {
+        Action::Kernel { path, options } => boot_kernel(bs, path, options),
+        Action::Chainload { path, options } => chainload(bs, path, options),
+        Action::FirmwareSetup => {
+            rt.request_firmware_setup()
+                .map_err(|err| ("firmware setup", err))?;
+            rt.reset(
+                EFIResetType::EfiResetCold,
+                EFIStatus::SUCCESS,
+                "firmware setup",
+                None,
+            )
+        }
+        Action::Reboot => rt.reset(EFIResetType::EfiResetCold, EFIStatus::SUCCESS, "", None),
+        Action::Shutdown => rt.reset(EFIResetType::EfiResetShutdown, EFIStatus::SUCCESS, "", None),
+    }
+}
+
+fn chainload(
+    bs: &EFIBootServices,
+    path: &str,
+    options: &str,
+) -> Result<String, (&'static str, EFIStatus)> {
+    info!("chainloading {path} {options}");
+    let device_path = boot_volume_file_path(path).map_err(|err| ("device path", err))?;
+    let mut image = bs
+        .load_image_from_path(&device_path.as_path())
+        .map_err(|err| ("load image", err))?;
+    // The shell convention, the image name comes first.
+    let name = path.rsplit(['\\', '/']).next().unwrap_or(path);
+    image
+        .set_load_options(&format!("{name} {options}"))
+        .map_err(|err| ("load options", err))?;
+    let exit = image.start();
+    info!("{name} exited with {}", exit.status);
+    Ok(match exit.exit_data {
+        Some(data) => format!("{name} exited with {}: {data}", exit.status),
+        None => format!("{name} exited with {}", exit.status),
+    })
+}
+
+fn boot_kernel(
+    bs: &EFIBootServices,
+    path: &str,
+    options: &str,
+) -> Result<String, (&'static str, EFIStatus)> {
+    info!("loading kernel {path}");
+    let data = read_file_to_vec(path).map_err(|err| ("read kernel", err))?;
+    let kernel = ElfImage::parse(&data)
+        .and_then(|elf| elf.load(bs))
+        .map_err(|err| ("load kernel", err))?;
+    info!(
+        "kernel at {:#x}, {} pages, entry {:#x}",
+        kernel.start, kernel.pages, kernel.entry
+    );
+
+    let st = EFISystemTable::fetch_global().ok_or(("system table", EFIStatus::NOT_READY))?;
+    let framebuffer = bs
+        .locate_protocol::<EFIGraphicsOutputProtocol>()
+        .ok()
+        .and_then(|gop| gop.framebuffer())
+        .unwrap_or_default();
+    let rsdp = find_rsdp(st).map_or(0, |rsdp| rsdp.address);
+    let cmdline: &'static str = Box::leak(options.into());
+    // Loader data, it outlives boot services.
+    let info = Box::leak(Box::new(BootInfo {
+        memory_map: core::ptr::null(),
+        memory_map_size: 0,
+        desc_size: 0,
+        framebuffer,
+        rsdp,
+        system_table: core::ptr::null_mut(),
+        cmdline: cmdline.as_ptr(),
+        cmdline_len: cmdline.len(),
+        log_buffer: core::ptr::null(),
+        log_buffer_len: 0,
+        log_written: 0,
+    }));
+
+    let ctx = exit_boot_services().map_err(|err| ("exit boot services", err))?;
+    info.set_runtime_context(&ctx);
+    info!("exited boot services, {} map entries", ctx.memory_map.len());
+    // The map has to stay where the kernel is told it is.
+    core::mem::forget(ctx);
+    info.set_log_buffer(log::buffer());
+    // Still on the firmware's identity map, `ElfImage::parse` only takes kernels linked for it.
+    let entry: KernelEntry = unsafe { core::mem::transmute(kernel.entry as usize) };
+    entry(info)
+}
@@ -0,0 +1,392 @@
+//! The boot menu configuration, `\fi\boot.conf` on the boot volume.
+//!
+//! ```text
+//! # Seconds before the default entry boots, `timeout menu` waits for a key.
+//! timeout 5
+//! default "fi kernel"
+//!
+//! entry "fi kernel"
+//!     kernel \fi\kernel.elf
+//!     options loglevel=debug
+//!
+//! entry "UEFI shell"
+//!     efi \EFI\tools\shell.efi
+//!     options -nostartup
+//!
+//! entry "Firmware setup"
+//!     firmware-setup
+//!
+//! entry "Shut down"
+//!     shutdown
+//! ```
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use fi_uefi::args::split_args;
+
+pub const CONFIG_PATH: &str = "\\fi\\boot.conf";
+pub const KERNEL_PATH: &str = "\\fi\\kernel.elf";
+const DEFAULT_TIMEOUT: u32 = 5;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Load the fi_kernel ELF and hand over the machine, `options` becomes its command line.
+    Kernel {
+        path: String,
+        options: String,
+    },
+    /// Run another UEFI image from the boot volume and come back to the menu when it exits.
+    Chainload {
+        path: String,
+        options: String,
+    },
+    FirmwareSetup,
+    Reboot,
+    Shutdown,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Entry {
+    pub title: String,
+    pub action: Action,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Config {
+    /// Seconds before `default` boots, `None` waits for a key.
+    pub timeout: Option<u32>,
+    pub default: usize,
+    pub entries: Vec<Entry>,
+}
+
+/// Line numbers count from 1.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ConfigError {
+    pub line: usize,
+    pub message: &'static str,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Config {
+    /// Used when the boot volume has no configuration.
+    pub fn builtin() -> Self {
+        let entry = |title: &str, action| Entry {
+            title: title.to_string(),
+            action,
+        };
+        Self {
+            timeout: Some(DEFAULT_TIMEOUT),
+            default: 0,
+            entries: vec![
+                entry(
+                    "fi kernel",
+                    Action::Kernel {
+                        path: KERNEL_PATH.to_string(),
+                        options: String::new(),
+                    },
+                ),
+                entry("Firmware setup", Action::FirmwareSetup),
+                entry("Reboot", Action::Reboot),
+                entry("Shut down", Action::Shutdown),
+            ],
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut timeout = Some(DEFAULT_TIMEOUT);
+        let mut default = None;
+        // Title, action and the line the entry started on.
+        let mut entries: Vec<(String, Option<Action>, usize)> = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message| ConfigError {
+                line: line_number,
+                message,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, rest) = line
+                .split_once(char::is_whitespace)
+                .map_or((line, ""), |(key, rest)| (key, rest.trim()));
+            // Most values are a single, possibly quoted, word.
+            let word = || -> Result<String, ConfigError> {
+                let mut words = split_args(rest).map_err(|_| error("unbalanced quotes"))?;
+                if words.len() != 1 {
+                    return Err(error("expected exactly one value"));
+                }
+                Ok(words.remove(0))
+            };
+            let no_value = || rest.is_empty().then_some(()).ok_or(error("takes no value"));
+            let current = entries.last_mut();
+
+            match key {
+                "timeout" => {
+                    timeout = match word()?.as_str() {
+                        "menu" => None,
+                        secs => Some(secs.parse().map_err(|_| error("invalid timeout"))?),
+                    }
+                }
+                "default" => default = Some((word()?, line_number)),
+                "entry" => entries.push((word()?, None, line_number)),
+                "options" => match current {
+                    Some((
+                        _,
+                        Some(Action::Kernel { options, .. } | Action::Chainload { options, .. }),
+                        _,
+                    )) => *options = rest.to_string(),
+                    _ => return Err(error("options must follow kernel or efi")),
+                },
+                "kernel" | "efi" | "firmware-setup" | "reboot" | "shutdown" => {
+                    let Some((_, action, _)) = current else {
+                        return Err(error("action outside of an entry"));
+                    };
+                    if action.is_some() {
+                        return Err(error("entry already has an action"));
+                    }
+                    *action = Some(match key {
+                        "kernel" => Action::Kernel {
+                            path: word()?,
+                            options: String::new(),
+                        },
+                        "efi" => Action::Chainload {
+                            path: word()?,
+                            options: String::new(),
+                        },
+                        "firmware-setup" => no_value().map(|_| Action::FirmwareSetup)?,
+                        "reboot" => no_value().map(|_| Action::Reboot)?,
+                        _ => no_value().map(|_| Action::Shutdown)?,
+                    });
+                }
+                _ => return Err(error("unknown keyword")),
+            }
+        }
+
+        let entries = entries
+            .into_iter()
+            .map(|(title, action, line)| match action {
+                Some(action) => Ok(Entry { title, action }),
+                None => Err(ConfigError {
+                    line,
+                    message: "entry has no action",
+                }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if entries.is_empty() {
+            return Err(ConfigError {
+                line: text.lines().count(),
+                message: "no entries",
+            });
+        }
+        let default = match default {
+            None => 0,
+            // An entry title, or a number counting from 1.
+            Some((name, line)) => entries
+                .iter()
+                .position(|entry| entry.title == name)
+                .or_else(|| {
+                    let n = name.parse::<usize>().ok()?;
+                    (1..=entries.len()).contains(&n).then(|| n - 1)
+                })
+                .ok_or(ConfigError {
+                    line,
+                    message: "default names no entry",
+                })?,
+        };
+        Ok(Self {
+            timeout,
+            default,
+            entries,
+        })
+    }
+
+    /// Points every kernel entry at `path`, for the `kernel=` load option.
+    pub fn override_kernel(&mut self, path: &str) {
+        for entry in &mut self.entries {
+            if let Action::Kernel { path: kernel, .. } = &mut entry.action {
+                *kernel = path.to_string();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+
+    const EXAMPLE: &str = r#"
+# Seconds before the default entry boots.
+timeout 3
+default "UEFI shell"
+
+entry "fi kernel"
+    kernel \fi\kernel.elf
+    options loglevel=debug  console=serial
+
+entry "UEFI shell"
+    efi "\EFI\tools\shell.efi"
+    options -nostartup
+
+entry Setup
+    firmware-setup
+entry Reboot
+    reboot
+entry "Shut down"
+    shutdown
+"#;
+
+    fn entry(title: &str, action: Action) -> Entry {
+        Entry {
+            title: title.to_string(),
+            action,
+        }
+    }
+
+    #[test]
+    fn test_parse_example() {
+        let config = Config::parse(EXAMPLE).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                timeout: Some(3),
+                default: 1,
+                entries: vec![
+                    entry(
+                        "fi kernel",
+                        Action::Kernel {
+                            path: "\\fi\\kernel.elf".to_string(),
+                            // Kept as written, it is handed on as a whole.
+                            options: "loglevel=debug  console=serial".to_string(),
+                        },
+                    ),
+                    entry(
+                        "UEFI shell",
+                        Action::Chainload {
+                            path: "\\EFI\\tools\\shell.efi".to_string(),
+                            options: "-nostartup".to_string(),
+                        },
+                    ),
+                    entry("Setup", Action::FirmwareSetup),
+                    entry("Reboot", Action::Reboot),
+                    entry("Shut down", Action::Shutdown),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_timeout_and_default() {
+        let entries = "entry one\n reboot\nentry two\n shutdown\n";
+        let cases = [
+            ("", Some(DEFAULT_TIMEOUT), 0),
+            ("timeout 0\n", Some(0), 0),
+            ("timeout menu\n", None, 0),
+            ("default two\n", Some(DEFAULT_TIMEOUT), 1),
+            ("default 2\n", Some(DEFAULT_TIMEOUT), 1),
+            ("default 1\ntimeout 10\n", Some(10), 0),
+        ];
+        for (head, timeout, default) in cases {
+            let config = Config::parse(&format!("{head}{entries}")).unwrap();
+            assert_eq!(
+                (config.timeout, config.default),
+                (timeout, default),
+                "{head}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let cases = [
+            ("timeout soon", 1, "invalid timeout"),
+            ("timeout", 1, "expected exactly one value"),
+            ("default \"one", 1, "unbalanced quotes"),
+            ("entry one two\n reboot", 1, "expected exactly one value"),
+            ("entry one\n reboot now", 2, "takes no value"),
+            (
+                "entry one\n reboot\n options -v",
+                3,
+                "options must follow kernel or efi",
+            ),
+            (
+                "options -v\nentry one\n reboot",
+                1,
+                "options must follow kernel or efi",
+            ),
+            (
+                "reboot\nentry one\n reboot",
+                1,
+                "action outside of an entry",
+            ),
+            (
+                "entry one\n reboot\n shutdown",
+                3,
+                "entry already has an action",
+            ),
+            ("entry one\n reboot\n color blue", 3, "unknown keyword"),
+            ("entry one\n\nentry two\n reboot", 1, "entry has no action"),
+            ("# nothing here\ntimeout 5", 2, "no entries"),
+            (
+                "default 3\nentry one\n reboot\nentry two\n reboot",
+                1,
+                "default names no entry",
+            ),
+            ("default 0\nentry one\n reboot", 1, "default names no entry"),
+            (
+                "default three\nentry one\n reboot",
+                1,
+                "default names no entry",
+            ),
+        ];
+        for (text, line, message) in cases {
+            assert_eq!(
+                Config::parse(text),
+                Err(ConfigError { line, message }),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_override_kernel() {
+        let mut config = Config::parse(EXAMPLE).unwrap();
+        config.entries.push(entry(
+            "fallback",
+            Action::Kernel {
+                path: KERNEL_PATH.to_string(),
+                options: "safe".to_string(),
+            },
+        ));
+        config.override_kernel("\\test\\kernel.elf");
+        let kernels: Vec<_> = config
+            .entries
+            .iter()
+            .filter_map(|entry| match &entry.action {
+                Action::Kernel { path, options } => Some((path.as_str(), options.as_str())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            kernels,
+            [
+                ("\\test\\kernel.elf", "loglevel=debug  console=serial"),
+                ("\\test\\kernel.elf", "safe"),
+            ]
+        );
+        // Everything else stays as it was.
+        assert_eq!(
+            config.entries[1].action,
+            Config::parse(EXAMPLE).unwrap().entries[1].action
+        );
+    }
+}
@@ -1,29 +1,152 @@
 #![no_std]
 #![no_main]
 
-macro_rules! cstr_16 {
-    [$($n:literal),*] => {
-        [$($n as u16),*]
-    };
-}
+extern crate alloc;
+
+mod boot;
+mod menu;
+
+use alloc::format;
+use alloc::string::String;
 use core::ffi::c_void;
 use core::panic::PanicInfo;
-use fi_uefi::{EFILoadedImageProtocol, EFISystemTable};
+use fi_uefi::allocator::UefiAllocator;
+use fi_uefi::args::{Flags, args};
+use fi_uefi::fs::read_file_to_vec;
+use fi_uefi::log::{self, Filter, Level, Sinks};
+use fi_uefi::reset::apply_panic_policy;
+use fi_uefi::{
+    EFIBootServices, EFILoadedImageProtocol, EFIStatus, EFISystemTable, Handle, eprintln, error,
+    info, warn,
+};
+
+use fi_os::config::{CONFIG_PATH, Config};
+
+#[global_allocator]
+static ALLOCATOR: UefiAllocator = UefiAllocator::new();
 
 #[panic_handler]
 fn panic_handler(panic_info: &PanicInfo) -> ! {
-    let st = EFISystemTable::fetch_global().unwrap();
-    st.test_print(&cstr_16!('o', 'o', 'f'));
-    loop {}
+    // Through the log, so the kernel sees why the loader died if it got that far.
+    error!("{}", panic_info);
+    if !(log::sinks().contains(Sinks::CONSOLE) && log::enabled(Level::Error, module_path!())) {
+        eprintln!("{}", panic_info);
+    }
+    apply_panic_policy()
+}
+
+/// The configuration from the boot volume, or the built-in one if there is none. A broken
+/// file is reported and replaced by the built-in menu, so the machine stays bootable.
+fn load_config(path: &str) -> Config {
+    let text = match read_file_to_vec(path) {
+        Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+        Err(EFIStatus::NOT_FOUND) => {
+            info!("no {path}, using the built-in menu");
+            return Config::builtin();
+        }
+        Err(err) => {
+            warn!("can't read {path}: {err}");
+            let _ = menu::pause(&format!("Can't read {path}: {err}"));
+            return Config::builtin();
+        }
+    };
+    Config::parse(&text).unwrap_or_else(|err| {
+        warn!("{path}: {err}");
+        let _ = menu::pause(&format!("{path}: {err}"));
+        Config::builtin()
+    })
+}
+
+/// What the load options can change.
+struct Options {
+    config_path: String,
+    kernel: String,
+    show_menu: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            config_path: String::from(CONFIG_PATH),
+            kernel: String::new(),
+            show_menu: false,
+        }
+    }
+}
+
+/// Our load options, all or nothing: a bad one leaves every option at its default.
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options::default();
+    // Already applied by `log::init`, declared so it isn't an unknown option here.
+    let mut log_filter = Filter::default();
+    let args = args().map_err(|err| format!("{err:?}"))?;
+    let rest = Flags::new()
+        .value(
+            "config",
+            "menu configuration on the boot volume",
+            &mut options.config_path,
+        )
+        .value(
+            "kernel",
+            "kernel image for every kernel entry",
+            &mut options.kernel,
+        )
+        .switch(
+            "menu",
+            "wait for a key instead of counting down",
+            &mut options.show_menu,
+        )
+        .value(
+            log::LOG_OPTION,
+            "log filter, e.g. debug or info,fi_uefi::fs=trace",
+            &mut log_filter,
+        )
+        .parse(&args)
+        .map_err(|err| format!("{err}"))?;
+    for word in rest {
+        warn!("ignoring load option word '{word}'");
+    }
+    Ok(options)
 }
 
 #[unsafe(no_mangle)]
-pub extern "C" fn efi_main(image: *mut c_void, system_table: *mut c_void) -> usize {
+pub extern "C" fn efi_main(image: Handle, system_table: *mut c_void) -> usize {
     unsafe {
         EFILoadedImageProtocol::from_image_handle(image);
         EFISystemTable::set_system_table(system_table as *const EFISystemTable);
     }
+    log::init(Sinks::ALL);
+    // The firmware reboots after five minutes in an application unless told otherwise.
+    if let Some(bs) = EFIBootServices::fetch_global() {
+        let _ = bs.set_watchdog_timer(0, 0);
+    }
+
+    let options = parse_options().unwrap_or_else(|err| {
+        warn!("ignoring load options: {err}");
+        let _ = menu::pause(&format!("Ignoring load options: {err}"));
+        Options::default()
+    });
+
+    let mut config = load_config(&options.config_path);
+    if !options.kernel.is_empty() {
+        config.override_kernel(&options.kernel);
+    }
+    if options.show_menu {
+        config.timeout = None;
+    }
 
-    loop {}
-    0
+    loop {
+        let chosen = match menu::choose(&config) {
+            Ok(chosen) => chosen,
+            Err(err) => return err.0 as usize,
+        };
+        let message = match boot::run(&config.entries[chosen].action) {
+            Ok(message) => message,
+            Err((what, err)) => format!("{what}: {err}"),
+        };
+        let _ = menu::pause(&message);
+        // Coming back means the countdown already had its chance.
+        config.timeout = None;
+        config.default = chosen;
+    }
 }
@@ -0,0 +1,166 @@
+//! Drawing the entry list on the text console and picking one with the keyboard.
+use alloc::format;
+use core::time::Duration;
+
+use fi_uefi::console::{Attribute, Color, Window};
+use fi_uefi::executor::{Either, block_on, next_key, select, sleep};
+use fi_uefi::input::{Key, ScanCode};
+use fi_uefi::{EFIStatus, EFISystemTable, SimpleTextOutputInterface};
+
+use fi_os::config::Config;
+
+const HEADER: Attribute = Attribute::new(Color::White, Color::Black);
+const SELECTED: Attribute = Attribute::DEFAULT.inverted();
+
+const HEADER_ROW: usize = 0;
+const FIRST_ENTRY_ROW: usize = 2;
+// Left margin, and the same again on the right.
+const MARGIN: usize = 2;
+
+fn stdout() -> Result<&'static mut SimpleTextOutputInterface, EFIStatus> {
+    let st = EFISystemTable::fetch_global().ok_or(EFIStatus::NOT_READY)?;
+    unsafe { st.stdout.as_mut() }.ok_or(EFIStatus::NOT_READY)
+}
+
+/// Clears the screen once and hands back a window inside the margins with the header drawn.
+fn screen(out: &mut SimpleTextOutputInterface) -> Result<Window<'_>, EFIStatus> {
+    let mode = out.current_mode()?;
+    out.set_attribute(Attribute::DEFAULT.0 as u64)?;
+    out.clear_screen()?;
+    let mut window = Window::new(
+        out,
+        MARGIN,
+        1,
+        mode.columns.saturating_sub(2 * MARGIN),
+        mode.rows.saturating_sub(2),
+    );
+    window.write_line_with(HEADER_ROW, "fi boot menu", HEADER)?;
+    Ok(window)
+}
+
+struct Menu<'a> {
+    window: Window<'a>,
+    config: &'a Config,
+    /// First entry on screen, the list scrolls when it is taller than the window.
+    top: usize,
+}
+
+impl Menu<'_> {
+    /// How many entries fit above the blank row and the footer.
+    fn visible(&self) -> usize {
+        self.window
+            .height()
+            .saturating_sub(FIRST_ENTRY_ROW + 2)
+            .max(1)
+    }
+
+    fn footer_row(&self) -> usize {
+        FIRST_ENTRY_ROW + self.config.entries.len().min(self.visible()) + 1
+    }
+
+    fn draw_entries(&mut self, selected: usize) -> Result<(), EFIStatus> {
+        let visible = self.visible();
+        // Scroll just far enough to bring the selection on screen.
+        if selected < self.top {
+            self.top = selected;
+        } else if selected >= self.top + visible {
+            self.top = selected + 1 - visible;
+        }
+        let entries = self.config.entries.iter().enumerate();
+        for (row, (i, entry)) in entries.skip(self.top).take(visible).enumerate() {
+            let attribute = if i == selected {
+                SELECTED
+            } else {
+                Attribute::DEFAULT
+            };
+            let text = format!(" {} ", entry.title);
+            self.window
+                .write_line_with(FIRST_ENTRY_ROW + row, &text, attribute)?;
+        }
+        Ok(())
+    }
+
+    fn footer(&mut self, remaining: Option<u32>, selected: usize) -> Result<(), EFIStatus> {
+        let row = self.footer_row();
+        match remaining {
+            Some(secs) => self.window.write_line(
+                row,
+                &format!(
+                    "Booting '{}' in {secs}s, press any key to stop",
+                    self.config.entries[selected].title
+                ),
+            ),
+            None => self
+                .window
+                .write_line(row, "Up/Down to select, Enter to boot"),
+        }
+    }
+}
+
+/// Shows the menu until an entry is chosen or the countdown runs out, returns its index.
+pub fn choose(config: &Config) -> Result<usize, EFIStatus> {
+    let count = config.entries.len();
+    let mut selected = config.default.min(count - 1);
+    let mut remaining = config.timeout;
+    if remaining == Some(0) {
+        return Ok(selected);
+    }
+
+    let out = stdout()?;
+    let _ = out.enable_cursor(false);
+    let mut menu = Menu {
+        window: screen(out)?,
+        config,
+        top: 0,
+    };
+    menu.draw_entries(selected)?;
+    menu.footer(remaining, selected)?;
+    let chosen = loop {
+        let key = match remaining {
+            Some(0) => break selected,
+            Some(secs) => match block_on(select(next_key(), sleep(Duration::from_secs(1))))? {
+                Either::Left(key) => {
+                    // Any key stops the countdown.
+                    remaining = None;
+                    menu.footer(remaining, selected)?;
+                    key?
+                }
+                Either::Right(slept) => {
+                    slept?;
+                    remaining = Some(secs - 1);
+                    menu.footer(remaining, selected)?;
+                    continue;
+                }
+            },
+            None => block_on(next_key())??,
+        };
+        match key {
+            Key::Char('\r' | '\n') => break selected,
+            Key::Special(ScanCode::Up) => selected = (selected + count - 1) % count,
+            Key::Special(ScanCode::Down) => selected = (selected + 1) % count,
+            Key::Special(ScanCode::Home | ScanCode::PageUp) => selected = 0,
+            Key::Special(ScanCode::End | ScanCode::PageDown) => selected = count - 1,
+            // Digits pick an entry directly.
+            Key::Char(c @ '1'..='9') if (c as usize - '1' as usize) < count => {
+                break c as usize - '1' as usize;
+            }
+            _ => continue,
+        }
+        menu.draw_entries(selected)?;
+    };
+    let out = stdout()?;
+    out.clear_screen()?;
+    let _ = out.enable_cursor(true);
+    Ok(chosen)
+}
+
+/// Shows `message` on a clean screen and waits for a key, for errors and for the exit status
+/// of chainloaded images.
+pub fn pause(message: &str) -> Result<(), EFIStatus> {
+    let out = stdout()?;
+    let mut window = screen(out)?;
+    window.write_line(FIRST_ENTRY_ROW, message)?;
+    window.write_line(FIRST_ENTRY_ROW + 2, "Press any key to return to the menu")?;
+    block_on(next_key())??;
+    Ok(())
+}